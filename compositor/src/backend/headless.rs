@@ -0,0 +1,585 @@
+//! Headless backend
+//!
+//! The headless backend runs without a display server or a GPU. Each output is a virtual output which is
+//! rendered into memory using the Pixman software renderer. This makes the backend useful for automated
+//! testing, such as in CI or when testing a window manager.
+
+use std::{
+    error, fmt, mem,
+    num::{ParseFloatError, ParseIntError},
+    str::FromStr,
+    time::Duration,
+};
+
+use calloop::{
+    timer::{TimeoutAction, Timer},
+    LoopHandle, RegistrationToken,
+};
+use smithay::{
+    backend::{
+        allocator::{dmabuf::Dmabuf, Fourcc},
+        renderer::{
+            damage::{OutputDamageTracker, OutputDamageTrackerError},
+            element::AsRenderElements,
+            pixman::{PixmanError, PixmanRenderer},
+            Bind, ImportMemWl, Offscreen,
+        },
+    },
+    output::{Mode, Output},
+    reexports::{pixman::Image, wayland_protocols::wp::presentation_time::server::wp_presentation_feedback},
+    utils::{Physical, Rectangle, Scale, Size, Transform},
+    wayland::{
+        dmabuf::{DmabufGlobal, DmabufState, ImportError},
+        shm::ShmState,
+    },
+};
+use wayland_server::{protocol::wl_buffer::WlBuffer, DisplayHandle};
+
+use crate::{
+    backend::OutputInfo,
+    capture::{self, CaptureError},
+    scene::{Hierarchy, SceneGraphElement},
+    Aerugo, Loop, StartupError,
+};
+
+/// Description of a virtual output created by the headless backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualOutput {
+    /// The size of the output in physical pixels.
+    pub size: Size<i32, Physical>,
+
+    /// The refresh rate of the output in millihertz.
+    pub refresh: i32,
+
+    /// The transform of the output, such as the rotation of a tablet.
+    pub transform: Transform,
+}
+
+/// Sizes of additional modes virtual outputs advertise, allowing the mode to be changed.
+const ADDITIONAL_MODES: [(i32, i32); 4] = [(1280, 720), (1920, 1080), (2560, 1440), (3840, 2160)];
+
+impl Default for VirtualOutput {
+    fn default() -> Self {
+        Self {
+            size: (1920, 1080).into(),
+            refresh: 60_000,
+            transform: Transform::Normal,
+        }
+    }
+}
+
+/// An error which occurs while parsing a [`VirtualOutput`].
+#[derive(Debug, thiserror::Error)]
+pub enum ParseVirtualOutputError {
+    #[error("expected an output formatted as `<width>x<height>[@<refresh>]`")]
+    Format,
+
+    #[error("invalid size: {0}")]
+    Size(#[source] ParseIntError),
+
+    #[error("the size must be positive")]
+    EmptySize,
+
+    #[error("invalid refresh rate: {0}")]
+    Refresh(#[source] ParseFloatError),
+
+    #[error("the refresh rate must be positive")]
+    NonPositiveRefresh,
+}
+
+impl FromStr for VirtualOutput {
+    type Err = ParseVirtualOutputError;
+
+    /// Parse a virtual output formatted as `<width>x<height>[@<refresh>]`.
+    ///
+    /// The refresh rate is in hertz and defaults to 60 Hz.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (size, refresh) = match s.split_once('@') {
+            Some((size, refresh)) => {
+                let refresh = refresh.parse::<f64>().map_err(ParseVirtualOutputError::Refresh)?;
+
+                if !(refresh > 0.0 && refresh.is_finite()) {
+                    return Err(ParseVirtualOutputError::NonPositiveRefresh);
+                }
+
+                (size, (refresh * 1_000.0).round() as i32)
+            }
+            None => (s, Self::default().refresh),
+        };
+
+        let (w, h) = size.split_once('x').ok_or(ParseVirtualOutputError::Format)?;
+        let w = w.parse::<i32>().map_err(ParseVirtualOutputError::Size)?;
+        let h = h.parse::<i32>().map_err(ParseVirtualOutputError::Size)?;
+
+        if w <= 0 || h <= 0 {
+            return Err(ParseVirtualOutputError::EmptySize);
+        }
+
+        Ok(Self {
+            size: (w, h).into(),
+            refresh: refresh.max(1),
+            ..Self::default()
+        })
+    }
+}
+
+pub struct Backend {
+    renderer: PixmanRenderer,
+    outputs: Vec<HeadlessOutput>,
+    r#loop: LoopHandle<'static, Loop>,
+    display: DisplayHandle,
+    shm_state: ShmState,
+    dmabuf_state: DmabufState,
+}
+
+impl fmt::Debug for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Backend")
+            .field("outputs", &self.outputs)
+            .field("r#loop", &self.r#loop)
+            .field("display", &self.display)
+            .field("shm_state", &self.shm_state)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A virtual output and the memory the output is rendered into.
+struct HeadlessOutput {
+    output: Output,
+    size: Size<i32, Physical>,
+    buffer: Image<'static, 'static>,
+    damage_tracker: OutputDamageTracker,
+    /// The age of the buffer, zero if the buffer has not been rendered to yet.
+    age: usize,
+    /// Whether the output needs to be rendered.
+    render_scheduled: bool,
+    /// The refresh rate the output timer fires at, in millihertz.
+    refresh: i32,
+    /// The timer simulating the output presenting.
+    timer: RegistrationToken,
+}
+
+impl fmt::Debug for HeadlessOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeadlessOutput")
+            .field("output", &self.output)
+            .field("size", &self.size)
+            .field("damage_tracker", &self.damage_tracker)
+            .field("age", &self.age)
+            .field("render_scheduled", &self.render_scheduled)
+            .field("refresh", &self.refresh)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An error which occurs while initializing the headless backend.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to initialize the renderer")]
+    Renderer(#[from] PixmanError),
+
+    #[error("failed to register the output timer")]
+    Timer(#[source] calloop::Error),
+}
+
+impl From<Error> for StartupError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Renderer(_) => StartupError::Renderer(err.into()),
+            err => StartupError::Backend(err.into()),
+        }
+    }
+}
+
+impl dyn super::Backend {
+    fn headless_mut(&mut self) -> &mut Backend {
+        self.downcast_mut().expect("Not headless")
+    }
+}
+
+impl Backend {
+    /// Create a headless backend with the specified virtual outputs.
+    ///
+    /// Outputs are named `HEADLESS-<n>`, starting from 1 in the order the outputs were specified.
+    pub fn new(
+        r#loop: LoopHandle<'static, Loop>,
+        display: DisplayHandle,
+        outputs: impl IntoIterator<Item = VirtualOutput>,
+    ) -> Result<Self, Error> {
+        let mut renderer = PixmanRenderer::new()?;
+        let shm_state = ShmState::new::<Aerugo>(&display, renderer.shm_formats().collect());
+
+        let outputs = outputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, virtual_output)| {
+                let buffer = Offscreen::<Image<'static, 'static>>::create_buffer(
+                    &mut renderer,
+                    Fourcc::Argb8888,
+                    (virtual_output.size.w, virtual_output.size.h).into(),
+                )?;
+
+                let mode = Mode {
+                    size: virtual_output.size,
+                    refresh: virtual_output.refresh,
+                };
+                let modes = ADDITIONAL_MODES
+                    .into_iter()
+                    .map(|size| Mode {
+                        size: size.into(),
+                        refresh: virtual_output.refresh,
+                    })
+                    .filter(|additional| additional != &mode)
+                    .collect();
+                let output = OutputInfo {
+                    make: "Aerugo".into(),
+                    model: "Virtual output".into(),
+                    modes,
+                    transform: virtual_output.transform,
+                    ..OutputInfo::new(format!("HEADLESS-{}", index + 1), mode)
+                }
+                .create_output();
+                let timer = insert_timer(&r#loop, &output, virtual_output.refresh)?;
+
+                Ok(HeadlessOutput {
+                    damage_tracker: OutputDamageTracker::from_output(&output),
+                    output,
+                    size: virtual_output.size,
+                    buffer,
+                    age: 0,
+                    render_scheduled: false,
+                    refresh: virtual_output.refresh,
+                    timer,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // The compositor is not available until the backend has been created, so defer announcing the outputs.
+        let connected = outputs.iter().map(|output| output.output.clone()).collect::<Vec<_>>();
+        r#loop.insert_idle(move |state| {
+            for output in connected {
+                state.comp.add_output(output);
+            }
+        });
+
+        Ok(Self {
+            renderer,
+            outputs,
+            r#loop,
+            display,
+            shm_state,
+            dmabuf_state: DmabufState::new(),
+        })
+    }
+
+    /// The virtual outputs managed by this backend.
+    pub fn outputs(&self) -> impl Iterator<Item = &Output> {
+        self.outputs.iter().map(|output| &output.output)
+    }
+
+    /// The memory the output was last rendered into.
+    ///
+    /// The image uses the `Argb8888` format.
+    pub fn output_image(&self, output: &Output) -> Option<&Image<'static, 'static>> {
+        self.outputs
+            .iter()
+            .find(|headless| &headless.output == output)
+            .map(|headless| &headless.buffer)
+    }
+}
+
+/// Simulate the output presenting at the refresh rate of the output.
+///
+/// The output is only rendered if a render was scheduled.
+fn insert_timer(r#loop: &LoopHandle<'static, Loop>, output: &Output, refresh: i32) -> Result<RegistrationToken, Error> {
+    let interval = Duration::from_secs_f64(1_000.0 / refresh.max(1) as f64);
+    let output = output.clone();
+
+    r#loop
+        .insert_source(Timer::from_duration(interval), move |_, _, state| {
+            if let Err(err) = draw(state, &output) {
+                tracing::error!(%err, output = %output.name(), "Failed to render output");
+            }
+
+            TimeoutAction::ToDuration(interval)
+        })
+        .map_err(|err| Error::Timer(err.error))
+}
+
+fn draw(aerugo: &mut Loop, output: &Output) -> Result<(), OutputDamageTrackerError<PixmanRenderer>> {
+    let backend = aerugo.comp.backend.headless_mut();
+
+    let Some(headless) = backend.outputs.iter_mut().find(|headless| &headless.output == output) else {
+        return Ok(());
+    };
+
+    if !headless.render_scheduled {
+        return Ok(());
+    }
+
+    headless.render_scheduled = false;
+    backend
+        .renderer
+        .bind(headless.buffer.clone())
+        .map_err(OutputDamageTrackerError::Rendering)?;
+
+    let scale = Scale::from(output.current_scale().fractional_scale());
+    let elems: Vec<SceneGraphElement> = if let Some(hir) = aerugo.comp.scene.get_graph(output) {
+        hir.render_elements(&mut backend.renderer, (0, 0).into(), scale, 1.0)
+    } else {
+        Vec::new()
+    };
+
+    let (damage, states) =
+        headless
+            .damage_tracker
+            .render_output(&mut backend.renderer, headless.age, &elems, super::CLEAR_COLOR)?;
+
+    // The output is always rendered into the same buffer.
+    headless.age = 1;
+
+    if damage.is_none() {
+        aerugo.comp.frame_skipped(output, &elems, &states);
+        return Ok(());
+    }
+
+    // Rendering into memory presents the frame immediately.
+    aerugo.comp.frame_submitted(output, &elems, &states);
+    let time = aerugo.comp.clock.now();
+    aerugo
+        .comp
+        .frame_presented(output, time.into(), wp_presentation_feedback::Kind::empty());
+
+    Ok(())
+}
+
+impl crate::backend::Backend for Backend {
+    fn shm_state(&self) -> &ShmState {
+        &self.shm_state
+    }
+
+    fn dmabuf_state(&mut self) -> &mut DmabufState {
+        &mut self.dmabuf_state
+    }
+
+    fn dmabuf_imported(&mut self, _global: &DmabufGlobal, _dmabuf: Dmabuf) -> Result<(), ImportError> {
+        // No dmabuf global is advertised, since outputs are rendered into memory by Pixman.
+        Err(ImportError::Failed)
+    }
+
+    fn schedule_render(&mut self, output: &Output) {
+        if let Some(headless) = self.outputs.iter_mut().find(|headless| &headless.output == output) {
+            headless.render_scheduled = true;
+        }
+    }
+
+    fn set_mode(&mut self, output: &Output, mode: Mode) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let Some(headless) = self.outputs.iter_mut().find(|headless| &headless.output == output) else {
+            return Err(format!("{} is not a headless output", output.name()).into());
+        };
+
+        headless.buffer = Offscreen::<Image<'static, 'static>>::create_buffer(
+            &mut self.renderer,
+            Fourcc::Argb8888,
+            (mode.size.w, mode.size.h).into(),
+        )?;
+        headless.size = mode.size;
+        // The new buffer has never been rendered to.
+        headless.age = 0;
+        headless.render_scheduled = true;
+
+        if headless.refresh != mode.refresh {
+            // Re-arm the timer so the new refresh rate applies from the next frame.
+            let timer = insert_timer(&self.r#loop, output, mode.refresh)?;
+            self.r#loop.remove(mem::replace(&mut headless.timer, timer));
+            headless.refresh = mode.refresh;
+        }
+
+        Ok(())
+    }
+
+    fn capture(
+        &mut self,
+        hierarchy: Option<Hierarchy<'_>>,
+        region: Rectangle<i32, Physical>,
+        scale: Scale<f64>,
+        clear_color: [f32; 4],
+        buffer: &WlBuffer,
+    ) -> Result<(), CaptureError> {
+        capture::render_to_shm::<_, Image<'static, 'static>>(
+            &mut self.renderer,
+            hierarchy,
+            region,
+            scale,
+            clear_color,
+            buffer,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use calloop::EventLoop;
+    use smithay::output::{Mode, Output};
+    use wayland_server::Display;
+
+    use crate::{
+        backend::{
+            headless::{Backend, VirtualOutput},
+            Backend as _,
+        },
+        Aerugo, Loop,
+    };
+
+    /// Create a server using the headless backend without binding a socket.
+    fn server(outputs: impl IntoIterator<Item = VirtualOutput>) -> (EventLoop<'static, Loop>, Display<Aerugo>, Loop) {
+        let event_loop = EventLoop::try_new().unwrap();
+        let display = Display::<Aerugo>::new().unwrap();
+        let handle = event_loop.handle();
+        let backend = Backend::new(handle.clone(), display.handle(), outputs).unwrap();
+        let comp = Aerugo::new(&handle, display.handle(), Box::new(backend)).unwrap();
+        let state = Loop {
+            r#loop: handle,
+            signal: event_loop.get_signal(),
+            comp,
+            display: display.handle(),
+        };
+
+        (event_loop, display, state)
+    }
+
+    /// Dispatch the event loop until the condition is true, returning false if the timeout elapsed first.
+    fn dispatch_until(
+        event_loop: &mut EventLoop<'static, Loop>,
+        state: &mut Loop,
+        timeout: Duration,
+        mut condition: impl FnMut(&mut Loop) -> bool,
+    ) -> bool {
+        let deadline = Instant::now() + timeout;
+
+        while !condition(state) {
+            let now = Instant::now();
+
+            if now >= deadline {
+                return false;
+            }
+
+            event_loop.dispatch(deadline - now, state).unwrap();
+        }
+
+        true
+    }
+
+    fn render_scheduled(state: &mut Loop, output: &Output) -> bool {
+        let backend = state.comp.backend.headless_mut();
+        let headless = backend.outputs.iter().find(|headless| &headless.output == output);
+        headless.unwrap().render_scheduled
+    }
+
+    #[test]
+    fn parse_virtual_output() {
+        let output = "1280x720".parse::<VirtualOutput>().unwrap();
+        assert_eq!(output.size, (1280, 720).into());
+        assert_eq!(output.refresh, 60_000);
+
+        let output = "800x600@59.94".parse::<VirtualOutput>().unwrap();
+        assert_eq!(output.size, (800, 600).into());
+        assert_eq!(output.refresh, 59_940);
+
+        for invalid in [
+            "1280",
+            "1280x",
+            "ax720",
+            "0x720",
+            "1280x-1",
+            "1280x720@",
+            "1280x720@0",
+            "1280x720@inf",
+        ] {
+            assert!(invalid.parse::<VirtualOutput>().is_err(), "{invalid} should not parse");
+        }
+    }
+
+    #[test]
+    fn creates_outputs() {
+        let (mut event_loop, _display, mut state) = server([
+            VirtualOutput::default(),
+            VirtualOutput {
+                size: (1280, 720).into(),
+                refresh: 30_000,
+                ..Default::default()
+            },
+        ]);
+
+        // Outputs are announced to the compositor once the event loop runs.
+        event_loop.dispatch(Duration::ZERO, &mut state).unwrap();
+
+        let outputs = state.comp.backend.headless_mut().outputs().cloned().collect::<Vec<_>>();
+        let names = outputs.iter().map(Output::name).collect::<Vec<_>>();
+        assert_eq!(names, ["HEADLESS-1", "HEADLESS-2"]);
+
+        let modes = outputs.iter().map(Output::current_mode).collect::<Vec<_>>();
+        assert_eq!(
+            modes,
+            [
+                Some(Mode {
+                    size: (1920, 1080).into(),
+                    refresh: 60_000
+                }),
+                Some(Mode {
+                    size: (1280, 720).into(),
+                    refresh: 30_000
+                }),
+            ]
+        );
+
+        for output in &outputs {
+            assert!(state.comp.scene.get_graph(output).is_some());
+        }
+    }
+
+    #[test]
+    fn renders_scheduled_frame() {
+        let (mut event_loop, _display, mut state) = server([VirtualOutput::default()]);
+        event_loop.dispatch(Duration::ZERO, &mut state).unwrap();
+
+        let output = state.comp.backend.headless_mut().outputs().next().unwrap().clone();
+        state.comp.backend.schedule_render(&output);
+        assert!(render_scheduled(&mut state, &output));
+
+        let rendered = dispatch_until(&mut event_loop, &mut state, Duration::from_secs(1), |state| {
+            !render_scheduled(state, &output)
+        });
+        assert!(rendered, "the output timer did not render the output");
+
+        let backend = state.comp.backend.headless_mut();
+        assert_eq!(backend.outputs[0].age, 1);
+        assert!(backend.output_image(&output).is_some());
+    }
+
+    /// Changing the refresh rate applies to the next frame instead of waiting for the old interval.
+    #[test]
+    fn refresh_change_rearms_timer() {
+        let (mut event_loop, _display, mut state) = server([VirtualOutput {
+            refresh: 1_000,
+            ..Default::default()
+        }]);
+        event_loop.dispatch(Duration::ZERO, &mut state).unwrap();
+
+        let output = state.comp.backend.headless_mut().outputs().next().unwrap().clone();
+        let mode = Mode {
+            size: (1920, 1080).into(),
+            refresh: 60_000,
+        };
+        state.comp.backend.set_mode(&output, mode).unwrap();
+        assert_eq!(state.comp.backend.headless_mut().outputs[0].refresh, 60_000);
+
+        // The old timer would only fire after a second.
+        let rendered = dispatch_until(&mut event_loop, &mut state, Duration::from_millis(500), |state| {
+            !render_scheduled(state, &output)
+        });
+        assert!(rendered, "the output timer still uses the old refresh rate");
+    }
+}
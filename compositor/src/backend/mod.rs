@@ -1,19 +1,23 @@
+pub mod headless;
+mod wayland;
 mod x11;
 
-use std::{error::Error, fmt};
+use std::{env, error::Error, ffi::OsString, fmt};
 
 use calloop::LoopHandle;
 use downcast_rs::{impl_downcast, Downcast};
 use smithay::{
     backend::allocator::dmabuf::Dmabuf,
+    output::{self, Mode, Output, PhysicalProperties, Subpixel},
+    utils::{Physical, Raw, Rectangle, Scale, Size, Transform},
     wayland::{
         dmabuf::{DmabufGlobal, DmabufState, ImportError},
         shm::ShmState,
     },
 };
-use wayland_server::DisplayHandle;
+use wayland_server::{protocol::wl_buffer::WlBuffer, DisplayHandle};
 
-use crate::Loop;
+use crate::{capture::CaptureError, scene::Hierarchy, Loop, StartupError};
 
 pub trait Backend: fmt::Debug + Downcast {
     fn shm_state(&self) -> &ShmState;
@@ -33,22 +37,316 @@ pub trait Backend: fmt::Debug + Downcast {
         false
     }
 
-    // TODO: Outputs?
+    /// Request the output is rendered.
+    ///
+    /// The backend renders the output the next time the output is able to present. An output is only repainted
+    /// if something on the output was damaged.
+    fn schedule_render(&mut self, output: &Output);
+
+    /// Change the mode of an output.
+    ///
+    /// The mode is always one of the modes the output advertises. The compositor updates the state of the
+    /// [`Output`] after the mode was changed.
+    fn set_mode(&mut self, output: &Output, _mode: Mode) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err(format!("the backend cannot change the mode of {}", output.name()).into())
+    }
+
+    /// Render an area of a hierarchy into a shared memory buffer for screen capture.
+    ///
+    /// The region is in the coordinate space of the hierarchy rendered at the scale and is the same size as the
+    /// buffer. If there is no hierarchy, the buffer is filled with the clear color.
+    fn capture(
+        &mut self,
+        hierarchy: Option<Hierarchy<'_>>,
+        region: Rectangle<i32, Physical>,
+        scale: Scale<f64>,
+        clear_color: [f32; 4],
+        buffer: &WlBuffer,
+    ) -> Result<(), CaptureError>;
+
+    // Outputs are announced to the compositor using `Aerugo::add_output` and `Aerugo::remove_output`.
+
     // TODO: Seat?
 }
 impl_downcast!(Backend);
 
-pub fn default_backend(
+/// The color outputs are cleared with before rendering the scene.
+pub(crate) const CLEAR_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+/// Description of an output connected to a backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputInfo {
+    /// The name of the output, such as `DP-1`.
+    pub name: String,
+
+    /// The manufacturer of the output.
+    pub make: String,
+
+    /// The model of the output.
+    pub model: String,
+
+    /// The physical size of the output in millimeters.
+    ///
+    /// This is zero if the size is unknown or the output has no physical size.
+    pub physical_size: Size<i32, Raw>,
+
+    /// The subpixel layout of the output.
+    pub subpixel: Subpixel,
+
+    /// The modes the output supports.
+    ///
+    /// The current and preferred modes do not need to be included.
+    pub modes: Vec<Mode>,
+
+    /// The current mode of the output.
+    pub current_mode: Mode,
+
+    /// The preferred mode of the output.
+    pub preferred_mode: Option<Mode>,
+
+    /// The scale of the output.
+    pub scale: output::Scale,
+
+    /// The transform of the output.
+    pub transform: Transform,
+}
+
+impl OutputInfo {
+    /// Create the description of an output with the specified name and current mode.
+    ///
+    /// The current mode is also the preferred mode.
+    pub fn new(name: impl Into<String>, current_mode: Mode) -> Self {
+        Self {
+            name: name.into(),
+            make: String::new(),
+            model: String::new(),
+            physical_size: (0, 0).into(),
+            subpixel: Subpixel::Unknown,
+            modes: Vec::new(),
+            current_mode,
+            preferred_mode: Some(current_mode),
+            scale: output::Scale::Integer(1),
+            transform: Transform::Normal,
+        }
+    }
+
+    /// Create an output using this description.
+    ///
+    /// The output is not advertised to clients until it is added to the compositor.
+    pub fn create_output(self) -> Output {
+        let output = Output::new(
+            self.name,
+            PhysicalProperties {
+                size: self.physical_size,
+                subpixel: self.subpixel,
+                make: self.make,
+                model: self.model,
+            },
+        );
+
+        for mode in self.modes {
+            output.add_mode(mode);
+        }
+
+        output.change_current_state(Some(self.current_mode), Some(self.transform), Some(self.scale), None);
+
+        if let Some(mode) = self.preferred_mode {
+            output.set_preferred(mode);
+        }
+
+        output
+    }
+}
+
+/// The backend selected to create.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BackendSelection {
+    /// Automatically choose the backend depending on the environment.
+    ///
+    /// The Wayland backend is chosen if `WAYLAND_DISPLAY` is set, otherwise the X11 backend is chosen if
+    /// `DISPLAY` is set. If neither are set and the compositor is run from a TTY, then the KMS backend is
+    /// chosen.
+    #[default]
+    Auto,
+
+    /// Use kernel mode setting.
+    Kms,
+
+    /// Run inside a window, selecting Wayland or X11 as appropriate.
+    Windowed,
+
+    /// Run inside a window as a Wayland client.
+    Wayland,
+
+    /// Run inside a window as an X11 client.
+    X11,
+
+    /// Run without any display, rendering virtual outputs to memory.
+    Headless,
+}
+
+impl fmt::Display for BackendSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BackendSelection::Auto => "auto",
+            BackendSelection::Kms => "kms",
+            BackendSelection::Windowed => "windowed",
+            BackendSelection::Wayland => "wayland",
+            BackendSelection::X11 => "x11",
+            BackendSelection::Headless => "headless",
+        })
+    }
+}
+
+/// The renderer selected to use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RendererSelection {
+    /// Select the most optimal renderer the backend supports.
+    #[default]
+    Default,
+
+    /// Use the OpenGL ES renderer.
+    Gles,
+
+    /// Use the Pixman software renderer.
+    Pixman,
+}
+
+impl fmt::Display for RendererSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RendererSelection::Default => "default",
+            RendererSelection::Gles => "gles",
+            RendererSelection::Pixman => "pixman",
+        })
+    }
+}
+
+/// An error describing why a backend or renderer could not be selected.
+#[derive(Debug, thiserror::Error)]
+pub enum SelectionError {
+    #[error(
+        "could not select a backend automatically: neither WAYLAND_DISPLAY or DISPLAY are set and the compositor \
+        was not started from a TTY"
+    )]
+    NoEnvironment,
+
+    #[error("could not select a windowed backend: neither WAYLAND_DISPLAY or DISPLAY are set")]
+    NoWindowedDisplay,
+
+    #[error("the {0} backend is not implemented yet")]
+    Unimplemented(BackendSelection),
+
+    #[error("the {renderer} renderer is not supported by the {backend} backend")]
+    UnsupportedRenderer {
+        backend: BackendSelection,
+        renderer: RendererSelection,
+    },
+}
+
+/// The parts of the environment used to automatically select a backend.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Environment {
+    /// The value of `WAYLAND_DISPLAY`.
+    pub wayland_display: Option<OsString>,
+
+    /// The value of `DISPLAY`.
+    pub x11_display: Option<OsString>,
+
+    /// The value of `XDG_SESSION_TYPE`.
+    pub session_type: Option<OsString>,
+}
+
+impl Environment {
+    /// Read the environment of the current process.
+    pub fn from_env() -> Self {
+        // An empty variable is treated the same as an unset variable.
+        let var = |key| env::var_os(key).filter(|value| !value.is_empty());
+
+        Self {
+            wayland_display: var("WAYLAND_DISPLAY"),
+            x11_display: var("DISPLAY"),
+            session_type: var("XDG_SESSION_TYPE"),
+        }
+    }
+
+    /// Select the concrete backend and renderer to use.
+    ///
+    /// The returned backend will never be [`BackendSelection::Auto`] or [`BackendSelection::Windowed`] and the
+    /// returned renderer will never be [`RendererSelection::Default`].
+    pub fn select(
+        &self,
+        backend: BackendSelection,
+        renderer: RendererSelection,
+    ) -> Result<(BackendSelection, RendererSelection), SelectionError> {
+        let backend = match backend {
+            BackendSelection::Auto => match self.select_windowed() {
+                Ok(backend) => backend,
+                Err(_) if self.session_type.as_deref() == Some("tty".as_ref()) => BackendSelection::Kms,
+                Err(_) => return Err(SelectionError::NoEnvironment),
+            },
+            BackendSelection::Windowed => self.select_windowed()?,
+            backend => backend,
+        };
+
+        let renderer = match (backend, renderer) {
+            (BackendSelection::Kms, _) => return Err(SelectionError::Unimplemented(backend)),
+
+            // The X11 backend presents buffers allocated on the GPU.
+            (BackendSelection::X11, RendererSelection::Default | RendererSelection::Gles) => RendererSelection::Gles,
+
+            // The Wayland and headless backends present from memory.
+            (
+                BackendSelection::Wayland | BackendSelection::Headless,
+                RendererSelection::Default | RendererSelection::Pixman,
+            ) => RendererSelection::Pixman,
+
+            (backend, renderer) => return Err(SelectionError::UnsupportedRenderer { backend, renderer }),
+        };
+
+        Ok((backend, renderer))
+    }
+
+    fn select_windowed(&self) -> Result<BackendSelection, SelectionError> {
+        if self.wayland_display.is_some() {
+            return Ok(BackendSelection::Wayland);
+        }
+
+        if self.x11_display.is_some() {
+            return Ok(BackendSelection::X11);
+        }
+
+        Err(SelectionError::NoWindowedDisplay)
+    }
+}
+
+/// Create a backend using the specified selection.
+///
+/// If the backend or renderer needs to be selected automatically, the environment of the current process is
+/// used. The virtual outputs are only used by the headless backend.
+pub fn create_backend(
+    backend: BackendSelection,
+    renderer: RendererSelection,
+    headless_outputs: Vec<headless::VirtualOutput>,
     r#loop: LoopHandle<'static, Loop>,
     display: DisplayHandle,
-) -> Result<Box<dyn Backend>, Box<dyn Error>> {
-    // TODO: X11 backend only exists right now, so the backend selection is ignored.
-    Ok(Box::new(x11::Backend::new(r#loop, display).expect("TODO: Error type")))
+) -> Result<Box<dyn Backend>, StartupError> {
+    let (backend, renderer) = Environment::from_env().select(backend, renderer)?;
+    tracing::info!(%backend, %renderer, "Selected backend");
+
+    match backend {
+        BackendSelection::X11 => Ok(Box::new(x11::Backend::new(r#loop, display)?)),
+        BackendSelection::Wayland => Ok(Box::new(wayland::Backend::new(r#loop, display)?)),
+        BackendSelection::Headless => Ok(Box::new(headless::Backend::new(r#loop, display, headless_outputs)?)),
+        BackendSelection::Auto | BackendSelection::Windowed | BackendSelection::Kms => {
+            unreachable!("backend selection returned {backend}")
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::backend::Backend;
+    use crate::backend::{Backend, BackendSelection, Environment, RendererSelection, SelectionError};
 
     /// Test that [`Backend`] is object safe.
     #[test]
@@ -56,4 +354,76 @@ mod tests {
     fn dynamic_dispatch() {
         let _: Box<dyn Backend> = panic!("Should panic if Backend is object safe, or compilation will fail");
     }
+
+    fn environment(wayland_display: bool, x11_display: bool, session_type: Option<&str>) -> Environment {
+        Environment {
+            wayland_display: wayland_display.then(|| "wayland-1".into()),
+            x11_display: x11_display.then(|| ":0".into()),
+            session_type: session_type.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn auto_prefers_wayland() {
+        let env = environment(true, true, Some("wayland"));
+        let selected = env.select(BackendSelection::Auto, RendererSelection::Default).unwrap();
+        assert_eq!(selected, (BackendSelection::Wayland, RendererSelection::Pixman));
+    }
+
+    #[test]
+    fn auto_x11() {
+        let env = environment(false, true, Some("x11"));
+        let selected = env.select(BackendSelection::Auto, RendererSelection::Default).unwrap();
+        assert_eq!(selected, (BackendSelection::X11, RendererSelection::Gles));
+    }
+
+    /// Running from a TTY selects KMS, which is not implemented yet.
+    #[test]
+    fn auto_tty() {
+        let env = environment(false, false, Some("tty"));
+        assert!(matches!(
+            env.select(BackendSelection::Auto, RendererSelection::Default),
+            Err(SelectionError::Unimplemented(BackendSelection::Kms))
+        ));
+    }
+
+    #[test]
+    fn auto_no_environment() {
+        let env = environment(false, false, None);
+        assert!(matches!(
+            env.select(BackendSelection::Auto, RendererSelection::Default),
+            Err(SelectionError::NoEnvironment)
+        ));
+    }
+
+    #[test]
+    fn windowed_no_display() {
+        let env = environment(false, false, Some("tty"));
+        assert!(matches!(
+            env.select(BackendSelection::Windowed, RendererSelection::Default),
+            Err(SelectionError::NoWindowedDisplay)
+        ));
+    }
+
+    /// An explicitly selected backend is used regardless of the environment.
+    #[test]
+    fn explicit_backend() {
+        let env = environment(true, false, None);
+        let selected = env
+            .select(BackendSelection::Headless, RendererSelection::Pixman)
+            .unwrap();
+        assert_eq!(selected, (BackendSelection::Headless, RendererSelection::Pixman));
+    }
+
+    #[test]
+    fn unsupported_renderer() {
+        let env = environment(false, true, None);
+        assert!(matches!(
+            env.select(BackendSelection::Auto, RendererSelection::Pixman),
+            Err(SelectionError::UnsupportedRenderer {
+                backend: BackendSelection::X11,
+                renderer: RendererSelection::Pixman,
+            })
+        ));
+    }
 }
@@ -0,0 +1,1129 @@
+//! Nested Wayland backend
+//!
+//! This backend runs the compositor as a client of a parent Wayland compositor. Each output is presented in
+//! its own `xdg_toplevel` window. Outputs are rendered using the Pixman software renderer and presented to the
+//! parent compositor using `wl_shm` buffers.
+//!
+//! If the parent compositor supports `wp_presentation`, frames are reported as presented at the time the parent
+//! compositor presented the frame. Otherwise the frame callback of the parent compositor is used as an
+//! approximation.
+//!
+//! Input from the parent compositor's seat is translated into [`InputEvent`]s using the [`WaylandInput`]
+//! input backend.
+
+use std::{fmt, time::Duration};
+
+use calloop::LoopHandle;
+use smithay::{
+    backend::{
+        allocator::{dmabuf::Dmabuf, Fourcc},
+        input::{
+            AbsolutePositionEvent, Axis, AxisSource, ButtonState, Device, DeviceCapability, Event, InputBackend,
+            InputEvent, KeyState, KeyboardKeyEvent, PointerAxisEvent, PointerButtonEvent, PointerMotionAbsoluteEvent,
+            UnusedEvent,
+        },
+        renderer::{
+            damage::{OutputDamageTracker, OutputDamageTrackerError},
+            element::AsRenderElements,
+            pixman::{PixmanError, PixmanRenderer},
+            Bind, ExportMem, ImportMemWl, Offscreen,
+        },
+    },
+    output::{Mode, Output},
+    reexports::{pixman::Image, wayland_protocols::wp::presentation_time::server::wp_presentation_feedback},
+    utils::{Physical, Rectangle, Scale, Size},
+    wayland::{
+        dmabuf::{DmabufGlobal, DmabufState, ImportError},
+        shm::ShmState,
+    },
+};
+use smithay_client_toolkit::{
+    compositor::{CompositorHandler, CompositorState},
+    delegate_compositor, delegate_output, delegate_pointer, delegate_registry, delegate_seat, delegate_shm,
+    delegate_xdg_shell, delegate_xdg_window,
+    output::{OutputHandler, OutputState},
+    reexports::{
+        calloop_wayland_source::WaylandSource,
+        protocols::wp::presentation_time::client::{
+            wp_presentation, wp_presentation_feedback as parent_presentation_feedback,
+        },
+    },
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+    seat::{
+        pointer::{AxisScroll, PointerEvent, PointerEventKind, PointerHandler},
+        Capability, SeatHandler, SeatState,
+    },
+    shell::{
+        xdg::{
+            window::{Window, WindowConfigure, WindowDecorations, WindowHandler},
+            XdgShell,
+        },
+        WaylandSurface,
+    },
+    shm::{
+        slot::{ActivateSlotError, Buffer, CreateBufferError, SlotPool},
+        CreatePoolError, Shm, ShmHandler,
+    },
+};
+use wayland_client::{
+    globals::{registry_queue_init, BindError, GlobalError},
+    protocol::{wl_keyboard, wl_output, wl_pointer, wl_seat, wl_shm, wl_surface},
+    ConnectError, Connection, Dispatch, QueueHandle, WEnum,
+};
+use wayland_server::{protocol::wl_buffer::WlBuffer, DisplayHandle};
+
+use crate::{
+    backend::OutputInfo,
+    capture::{self, CaptureError},
+    scene::{Hierarchy, SceneGraphElement},
+    Aerugo, Loop, StartupError,
+};
+
+/// The size of a window before the parent compositor has configured the window.
+const DEFAULT_WINDOW_SIZE: (i32, i32) = (1280, 800);
+
+/// The refresh rate advertised for outputs.
+///
+/// The parent compositor does not tell clients the refresh rate of the outputs a window is presented on.
+const DEFAULT_REFRESH: i32 = 60_000;
+
+/// The id of `CLOCK_MONOTONIC`, which is the clock presentation times are reported in.
+const CLOCK_MONOTONIC: u32 = 1;
+
+/// An error that may occur when initializing the Wayland backend.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to connect to the parent compositor")]
+    Connect(#[from] ConnectError),
+
+    #[error("failed to enumerate the globals of the parent compositor")]
+    Globals(#[from] GlobalError),
+
+    #[error("the parent compositor does not support {interface}")]
+    MissingGlobal {
+        interface: &'static str,
+        #[source]
+        source: BindError,
+    },
+
+    #[error("failed to create shm pool")]
+    Pool(#[from] CreatePoolError),
+
+    #[error("failed to initialize the renderer")]
+    Renderer(#[from] PixmanError),
+
+    #[error("failed to register the Wayland event source")]
+    EventSource(#[source] calloop::Error),
+}
+
+impl From<Error> for StartupError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Renderer(_) => StartupError::Renderer(err.into()),
+            err => StartupError::Backend(err.into()),
+        }
+    }
+}
+
+/// An error that may occur when presenting an output.
+#[derive(Debug, thiserror::Error)]
+enum DrawError {
+    #[error(transparent)]
+    Render(#[from] OutputDamageTrackerError<PixmanRenderer>),
+
+    #[error("failed to copy framebuffer")]
+    Copy(#[from] PixmanError),
+
+    #[error("failed to allocate buffer")]
+    CreateBuffer(#[from] CreateBufferError),
+
+    #[error("failed to attach buffer")]
+    Attach(#[from] ActivateSlotError),
+}
+
+pub struct Backend {
+    registry_state: RegistryState,
+    seat_state: SeatState,
+    output_state: OutputState,
+    compositor_state: CompositorState,
+    xdg_shell: XdgShell,
+    shm: Shm,
+    /// The presentation time global of the parent compositor, if supported.
+    presentation: Option<wp_presentation::WpPresentation>,
+    /// The clock the parent compositor reports presentation times in.
+    presentation_clock: Option<u32>,
+    pool: SlotPool,
+    queue_handle: QueueHandle<Loop>,
+    keyboard: Option<wl_keyboard::WlKeyboard>,
+    pointer: Option<wl_pointer::WlPointer>,
+    /// The window which has keyboard focus in the parent compositor.
+    keyboard_focus: Option<wl_surface::WlSurface>,
+    renderer: PixmanRenderer,
+    windows: Vec<WaylandOutput>,
+    next_output: u32,
+    r#loop: LoopHandle<'static, Loop>,
+    display: DisplayHandle,
+    shm_state: ShmState,
+    dmabuf_state: DmabufState,
+    shutdown: bool,
+}
+
+impl fmt::Debug for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Backend")
+            .field("windows", &self.windows)
+            .field("r#loop", &self.r#loop)
+            .field("display", &self.display)
+            .field("shm_state", &self.shm_state)
+            .field("shutdown", &self.shutdown)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An output presented in a window of the parent compositor.
+struct WaylandOutput {
+    window: Window,
+    output: Output,
+    size: Size<i32, Physical>,
+    /// The image the output is rendered into.
+    ///
+    /// This is recreated when the window is resized.
+    image: Option<Image<'static, 'static>>,
+    buffer: Option<Buffer>,
+    configured: bool,
+    damage_tracker: OutputDamageTracker,
+    /// The age of the image, zero if the image has not been rendered to yet.
+    age: usize,
+    /// Whether the output needs to be rendered.
+    render_scheduled: bool,
+    /// Whether a frame callback from the parent compositor is pending.
+    frame_pending: bool,
+}
+
+impl fmt::Debug for WaylandOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WaylandOutput")
+            .field("window", &self.window)
+            .field("output", &self.output)
+            .field("size", &self.size)
+            .field("configured", &self.configured)
+            .field("damage_tracker", &self.damage_tracker)
+            .field("age", &self.age)
+            .field("render_scheduled", &self.render_scheduled)
+            .field("frame_pending", &self.frame_pending)
+            .finish_non_exhaustive()
+    }
+}
+
+impl dyn super::Backend {
+    fn wayland(&self) -> &Backend {
+        self.downcast_ref().expect("Not Wayland")
+    }
+
+    fn wayland_mut(&mut self) -> &mut Backend {
+        self.downcast_mut().expect("Not Wayland")
+    }
+}
+
+impl Backend {
+    pub fn new(r#loop: LoopHandle<'static, Loop>, display: DisplayHandle) -> Result<Self, Error> {
+        let connection = Connection::connect_to_env()?;
+        let (globals, queue) = registry_queue_init::<Loop>(&connection)?;
+        let queue_handle = queue.handle();
+
+        let compositor_state =
+            CompositorState::bind(&globals, &queue_handle).map_err(|source| Error::MissingGlobal {
+                interface: "wl_compositor",
+                source,
+            })?;
+        let xdg_shell = XdgShell::bind(&globals, &queue_handle).map_err(|source| Error::MissingGlobal {
+            interface: "xdg_wm_base",
+            source,
+        })?;
+        let shm = Shm::bind(&globals, &queue_handle).map_err(|source| Error::MissingGlobal {
+            interface: "wl_shm",
+            source,
+        })?;
+
+        // Frames are reported as presented using the frame callback if the parent does not support presentation time.
+        let presentation = globals.bind(&queue_handle, 1..=1, ()).ok();
+
+        let (width, height) = DEFAULT_WINDOW_SIZE;
+        let pool = SlotPool::new((width * height * 4) as usize, &shm)?;
+        let renderer = PixmanRenderer::new()?;
+
+        WaylandSource::new(connection, queue)
+            .insert(r#loop.clone())
+            .map_err(|err| Error::EventSource(err.error))?;
+
+        let mut backend = Self {
+            registry_state: RegistryState::new(&globals),
+            seat_state: SeatState::new(&globals, &queue_handle),
+            output_state: OutputState::new(&globals, &queue_handle),
+            compositor_state,
+            xdg_shell,
+            shm,
+            presentation,
+            presentation_clock: None,
+            pool,
+            queue_handle,
+            keyboard: None,
+            pointer: None,
+            keyboard_focus: None,
+            shm_state: ShmState::new::<Aerugo>(&display, renderer.shm_formats().collect()),
+            dmabuf_state: DmabufState::new(),
+            renderer,
+            windows: Vec::new(),
+            next_output: 1,
+            r#loop,
+            display,
+            shutdown: false,
+        };
+
+        backend.create_window();
+
+        Ok(backend)
+    }
+
+    /// Create a new window in the parent compositor and the output the window presents.
+    pub fn create_window(&mut self) -> Output {
+        let surface = self.compositor_state.create_surface(&self.queue_handle);
+        let window = self
+            .xdg_shell
+            .create_window(surface, WindowDecorations::RequestServer, &self.queue_handle);
+
+        let name = format!("WL-{}", self.next_output);
+        self.next_output += 1;
+
+        window.set_title(format!("Aerugo ({name})"));
+        window.set_app_id("aerugo");
+        // Perform the initial commit so the parent compositor sends a configure.
+        window.commit();
+
+        let mode = Mode {
+            size: DEFAULT_WINDOW_SIZE.into(),
+            refresh: DEFAULT_REFRESH,
+        };
+        let output = OutputInfo {
+            make: "Aerugo".into(),
+            model: "Wayland window".into(),
+            ..OutputInfo::new(name, mode)
+        }
+        .create_output();
+
+        // The backend may be borrowed by the caller, so defer announcing the output.
+        let connected = output.clone();
+        self.r#loop.insert_idle(move |state| {
+            state.comp.add_output(connected);
+        });
+
+        self.windows.push(WaylandOutput {
+            window,
+            output: output.clone(),
+            size: DEFAULT_WINDOW_SIZE.into(),
+            image: None,
+            buffer: None,
+            configured: false,
+            damage_tracker: OutputDamageTracker::from_output(&output),
+            age: 0,
+            render_scheduled: false,
+            frame_pending: false,
+        });
+
+        output
+    }
+
+    fn output_for_surface(&self, surface: &wl_surface::WlSurface) -> Option<&Output> {
+        self.windows
+            .iter()
+            .find(|window| window.window.wl_surface() == surface)
+            .map(|window| &window.output)
+    }
+}
+
+fn draw(aerugo: &mut Loop, surface: &wl_surface::WlSurface) -> Result<(), DrawError> {
+    let backend = aerugo.comp.backend.wayland_mut();
+
+    let Some(window) = backend
+        .windows
+        .iter_mut()
+        .find(|window| window.window.wl_surface() == surface)
+    else {
+        return Ok(());
+    };
+
+    // Do not draw until the parent compositor has configured the window. The next frame is drawn once the
+    // parent compositor is ready for another frame.
+    if !window.configured || !window.render_scheduled || window.frame_pending {
+        return Ok(());
+    }
+
+    window.render_scheduled = false;
+    let size = window.size;
+
+    if window.image.is_none() {
+        window.image = Some(
+            Offscreen::<Image<'static, 'static>>::create_buffer(
+                &mut backend.renderer,
+                Fourcc::Argb8888,
+                (size.w, size.h).into(),
+            )
+            .map_err(OutputDamageTrackerError::Rendering)?,
+        );
+        window.age = 0;
+    }
+
+    backend
+        .renderer
+        .bind(window.image.clone().unwrap())
+        .map_err(OutputDamageTrackerError::Rendering)?;
+
+    let scale = Scale::from(window.output.current_scale().fractional_scale());
+    let elems: Vec<SceneGraphElement> = if let Some(hir) = aerugo.comp.scene.get_graph(&window.output) {
+        hir.render_elements(&mut backend.renderer, (0, 0).into(), scale, 1.0)
+    } else {
+        Vec::new()
+    };
+
+    let (damage, states) =
+        window
+            .damage_tracker
+            .render_output(&mut backend.renderer, window.age, &elems, super::CLEAR_COLOR)?;
+
+    // The output is always rendered into the same image.
+    window.age = 1;
+
+    // Nothing changed, so there is no need to present a new buffer.
+    let Some(damage) = damage else {
+        let output = window.output.clone();
+        aerugo.comp.frame_skipped(&output, &elems, &states);
+        return Ok(());
+    };
+
+    // Copy the rendered output into a shm buffer the parent compositor can read.
+    let mapping = backend
+        .renderer
+        .copy_framebuffer(Rectangle::from_loc_and_size((0, 0), (size.w, size.h)), Fourcc::Argb8888)?;
+    let pixels = backend.renderer.map_texture(&mapping)?;
+
+    let stride = size.w * 4;
+    if window.buffer.is_none() {
+        let (buffer, _) = backend
+            .pool
+            .create_buffer(size.w, size.h, stride, wl_shm::Format::Argb8888)?;
+        window.buffer = Some(buffer);
+    }
+
+    let buffer = window.buffer.as_mut().unwrap();
+
+    let canvas = match backend.pool.canvas(buffer) {
+        Some(canvas) => canvas,
+        None => {
+            // The parent compositor has not released the previous buffer yet, so allocate another buffer.
+            let (second_buffer, canvas) =
+                backend
+                    .pool
+                    .create_buffer(size.w, size.h, stride, wl_shm::Format::Argb8888)?;
+            *buffer = second_buffer;
+            canvas
+        }
+    };
+
+    canvas.copy_from_slice(pixels);
+
+    // The whole image is copied into the buffer, so only the damaged area changes from the previous buffer.
+    let surface = window.window.wl_surface();
+    for rect in damage {
+        surface.damage_buffer(rect.loc.x, rect.loc.y, rect.size.w, rect.size.h);
+    }
+
+    // Request a frame callback to know when to present the next frame.
+    surface.frame(&backend.queue_handle, surface.clone());
+
+    if let Some(presentation) = backend.presentation.as_ref() {
+        presentation.feedback(surface, &backend.queue_handle, surface.clone());
+    }
+    buffer.attach_to(surface)?;
+    window.window.commit();
+    window.frame_pending = true;
+
+    let output = window.output.clone();
+    aerugo.comp.frame_submitted(&output, &elems, &states);
+
+    Ok(())
+}
+
+impl crate::backend::Backend for Backend {
+    fn shm_state(&self) -> &ShmState {
+        &self.shm_state
+    }
+
+    fn dmabuf_state(&mut self) -> &mut DmabufState {
+        &mut self.dmabuf_state
+    }
+
+    fn dmabuf_imported(&mut self, _global: &DmabufGlobal, _dmabuf: Dmabuf) -> Result<(), ImportError> {
+        // No dmabuf global is advertised, since outputs are rendered into memory by Pixman.
+        Err(ImportError::Failed)
+    }
+
+    fn should_shutdown(&self) -> bool {
+        self.shutdown
+    }
+
+    fn schedule_render(&mut self, output: &Output) {
+        let Some(window) = self.windows.iter_mut().find(|window| &window.output == output) else {
+            return;
+        };
+
+        if window.render_scheduled {
+            return;
+        }
+
+        window.render_scheduled = true;
+
+        // The output is drawn once configured or when the parent compositor is ready for another frame.
+        if window.configured && !window.frame_pending {
+            let surface = window.window.wl_surface().clone();
+
+            self.r#loop.insert_idle(move |state| {
+                if let Err(err) = draw(state, &surface) {
+                    tracing::error!(%err, "Failed to draw output");
+                }
+            });
+        }
+    }
+
+    fn capture(
+        &mut self,
+        hierarchy: Option<Hierarchy<'_>>,
+        region: Rectangle<i32, Physical>,
+        scale: Scale<f64>,
+        clear_color: [f32; 4],
+        buffer: &WlBuffer,
+    ) -> Result<(), CaptureError> {
+        capture::render_to_shm::<_, Image<'static, 'static>>(
+            &mut self.renderer,
+            hierarchy,
+            region,
+            scale,
+            clear_color,
+            buffer,
+        )
+    }
+}
+
+impl CompositorHandler for Loop {
+    fn scale_factor_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _new_factor: i32,
+    ) {
+    }
+
+    fn transform_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _new_transform: wl_output::Transform,
+    ) {
+    }
+
+    fn frame(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, surface: &wl_surface::WlSurface, _time: u32) {
+        let backend = self.comp.backend.wayland_mut();
+
+        let presentation = backend.presentation.is_some();
+
+        if let Some(window) = backend.windows.iter_mut().find(|w| w.window.wl_surface() == surface) {
+            window.frame_pending = false;
+
+            // Without presentation time, the frame callback is the closest approximation of when the parent
+            // compositor presented the frame.
+            if !presentation {
+                let output = window.output.clone();
+                let time = self.comp.clock.now();
+                self.comp
+                    .frame_presented(&output, time.into(), wp_presentation_feedback::Kind::empty());
+            }
+        }
+
+        if let Err(err) = draw(self, surface) {
+            tracing::error!(%err, "Failed to draw output");
+        }
+    }
+}
+
+impl OutputHandler for Loop {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.comp.backend.wayland_mut().output_state
+    }
+
+    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: wl_output::WlOutput) {}
+
+    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: wl_output::WlOutput) {}
+
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: wl_output::WlOutput) {}
+}
+
+impl WindowHandler for Loop {
+    fn request_close(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, window: &Window) {
+        let backend = self.comp.backend.wayland_mut();
+
+        let Some(index) = backend.windows.iter().position(|w| &w.window == window) else {
+            return;
+        };
+
+        let closed = backend.windows.remove(index);
+
+        // Once the last window is closed, there is nothing left to present.
+        if backend.windows.is_empty() {
+            backend.shutdown = true;
+        }
+
+        self.comp.remove_output(&closed.output);
+    }
+
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        window: &Window,
+        configure: WindowConfigure,
+        _serial: u32,
+    ) {
+        let backend = self.comp.backend.wayland_mut();
+
+        let Some(wayland_output) = backend.windows.iter_mut().find(|w| &w.window == window) else {
+            return;
+        };
+
+        let (default_width, default_height) = DEFAULT_WINDOW_SIZE;
+        let size = Size::from((
+            configure.new_size.0.map(|w| w.get() as i32).unwrap_or(default_width),
+            configure.new_size.1.map(|h| h.get() as i32).unwrap_or(default_height),
+        ));
+
+        // Forward the window size to the output mode.
+        let resized = size != wayland_output.size || !wayland_output.configured;
+
+        if resized {
+            wayland_output.size = size;
+            wayland_output.image = None;
+            wayland_output.buffer = None;
+            wayland_output.render_scheduled = true;
+        }
+
+        wayland_output.configured = true;
+
+        let output = wayland_output.output.clone();
+        let surface = wayland_output.window.wl_surface().clone();
+        let render_scheduled = wayland_output.render_scheduled;
+
+        if resized {
+            let mode = Mode {
+                size,
+                refresh: DEFAULT_REFRESH,
+            };
+
+            // The previous size of the window is no longer a valid mode.
+            self.comp.output_mode_changed(&output, mode);
+        }
+
+        // The first frame must be drawn to receive frame callbacks.
+        if render_scheduled {
+            if let Err(err) = draw(self, &surface) {
+                tracing::error!(%err, "Failed to draw output");
+            }
+        }
+    }
+}
+
+impl SeatHandler for Loop {
+    fn seat_state(&mut self) -> &mut SeatState {
+        &mut self.comp.backend.wayland_mut().seat_state
+    }
+
+    fn new_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: wl_seat::WlSeat) {}
+
+    fn new_capability(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        seat: wl_seat::WlSeat,
+        capability: Capability,
+    ) {
+        let backend = self.comp.backend.wayland_mut();
+
+        // Only a single keyboard and pointer is used. Input from all seats of the parent compositor is merged
+        // into the same device.
+        if capability == Capability::Keyboard && backend.keyboard.is_none() {
+            backend.keyboard = Some(seat.get_keyboard(qh, ()));
+        }
+
+        if capability == Capability::Pointer && backend.pointer.is_none() {
+            match backend.seat_state.get_pointer(qh, &seat) {
+                Ok(pointer) => backend.pointer = Some(pointer),
+                Err(err) => tracing::warn!(%err, "Failed to create pointer"),
+            }
+        }
+    }
+
+    fn remove_capability(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _seat: wl_seat::WlSeat,
+        capability: Capability,
+    ) {
+        let backend = self.comp.backend.wayland_mut();
+
+        if capability == Capability::Keyboard {
+            if let Some(keyboard) = backend.keyboard.take() {
+                keyboard.release();
+            }
+        }
+
+        if capability == Capability::Pointer {
+            if let Some(pointer) = backend.pointer.take() {
+                pointer.release();
+            }
+        }
+    }
+
+    fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: wl_seat::WlSeat) {}
+}
+
+impl Dispatch<wl_keyboard::WlKeyboard, ()> for Loop {
+    fn event(
+        state: &mut Self,
+        _proxy: &wl_keyboard::WlKeyboard,
+        event: wl_keyboard::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_keyboard::Event::Enter { surface, .. } => {
+                state.comp.backend.wayland_mut().keyboard_focus = Some(surface);
+            }
+
+            wl_keyboard::Event::Leave { .. } => {
+                state.comp.backend.wayland_mut().keyboard_focus = None;
+            }
+
+            wl_keyboard::Event::Key {
+                time,
+                key,
+                state: WEnum::Value(key_state),
+                ..
+            } => {
+                let backend = state.comp.backend.wayland();
+
+                let Some(output) = backend
+                    .keyboard_focus
+                    .as_ref()
+                    .and_then(|surface| backend.output_for_surface(surface))
+                    .cloned()
+                else {
+                    return;
+                };
+
+                let event = WaylandKeyboardKeyEvent {
+                    time,
+                    key,
+                    state: match key_state {
+                        wl_keyboard::KeyState::Pressed => KeyState::Pressed,
+                        _ => KeyState::Released,
+                    },
+                };
+
+                state
+                    .comp
+                    .process_input_event(&output, InputEvent::<WaylandInput>::Keyboard { event });
+            }
+
+            // The compositor uses its own keymap, and modifiers are derived from the key events.
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wp_presentation::WpPresentation, ()> for Loop {
+    fn event(
+        state: &mut Self,
+        _proxy: &wp_presentation::WpPresentation,
+        event: wp_presentation::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wp_presentation::Event::ClockId { clk_id } = event {
+            state.comp.backend.wayland_mut().presentation_clock = Some(clk_id);
+        }
+    }
+}
+
+impl Dispatch<parent_presentation_feedback::WpPresentationFeedback, wl_surface::WlSurface> for Loop {
+    fn event(
+        state: &mut Self,
+        _proxy: &parent_presentation_feedback::WpPresentationFeedback,
+        event: parent_presentation_feedback::Event,
+        surface: &wl_surface::WlSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let backend = state.comp.backend.wayland();
+
+        let Some(output) = backend.output_for_surface(surface).cloned() else {
+            return;
+        };
+
+        match event {
+            parent_presentation_feedback::Event::Presented {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+                flags,
+                ..
+            } => {
+                let flags = match flags {
+                    WEnum::Value(flags) => flags.bits(),
+                    WEnum::Unknown(flags) => flags,
+                };
+                let flags = wp_presentation_feedback::Kind::from_bits_truncate(flags);
+
+                // The time can only be used if the parent uses the same clock. Otherwise the flags describing the
+                // time do not apply to the time the frame is reported as presented at.
+                let (time, flags) = if backend.presentation_clock == Some(CLOCK_MONOTONIC) {
+                    let secs = (u64::from(tv_sec_hi) << 32) | u64::from(tv_sec_lo);
+                    let flags = flags
+                        & (wp_presentation_feedback::Kind::Vsync
+                            | wp_presentation_feedback::Kind::HwClock
+                            | wp_presentation_feedback::Kind::HwCompletion);
+                    (Duration::new(secs, tv_nsec), flags)
+                } else {
+                    let flags = flags & wp_presentation_feedback::Kind::Vsync;
+                    (state.comp.clock.now().into(), flags)
+                };
+
+                // Buffers are copied into the shm buffers presented by the parent, so the frame is never
+                // presented with zero copies.
+                state.comp.frame_presented(&output, time, flags);
+            }
+
+            parent_presentation_feedback::Event::Discarded => {
+                state.comp.frame_discarded(&output);
+            }
+
+            _ => {}
+        }
+    }
+}
+
+impl PointerHandler for Loop {
+    fn pointer_frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _pointer: &wl_pointer::WlPointer,
+        events: &[PointerEvent],
+    ) {
+        for event in events {
+            let backend = self.comp.backend.wayland();
+
+            let Some(window) = backend
+                .windows
+                .iter()
+                .find(|window| window.window.wl_surface() == &event.surface)
+            else {
+                continue;
+            };
+
+            let output = window.output.clone();
+            let (x, y) = event.position;
+
+            let input_event = match event.kind {
+                PointerEventKind::Enter { .. } | PointerEventKind::Motion { .. } => InputEvent::PointerMotionAbsolute {
+                    event: WaylandPointerMotionAbsoluteEvent {
+                        time: match event.kind {
+                            PointerEventKind::Motion { time } => time,
+                            _ => 0,
+                        },
+                        x,
+                        y,
+                        size: window.size,
+                    },
+                },
+
+                PointerEventKind::Press { time, button, .. } => InputEvent::PointerButton {
+                    event: WaylandPointerButtonEvent {
+                        time,
+                        button,
+                        state: ButtonState::Pressed,
+                    },
+                },
+
+                PointerEventKind::Release { time, button, .. } => InputEvent::PointerButton {
+                    event: WaylandPointerButtonEvent {
+                        time,
+                        button,
+                        state: ButtonState::Released,
+                    },
+                },
+
+                PointerEventKind::Axis {
+                    time,
+                    horizontal,
+                    vertical,
+                    source,
+                } => InputEvent::PointerAxis {
+                    event: WaylandPointerAxisEvent {
+                        time,
+                        horizontal,
+                        vertical,
+                        source: match source {
+                            Some(wl_pointer::AxisSource::Finger) => AxisSource::Finger,
+                            Some(wl_pointer::AxisSource::Continuous) => AxisSource::Continuous,
+                            Some(wl_pointer::AxisSource::WheelTilt) => AxisSource::WheelTilt,
+                            _ => AxisSource::Wheel,
+                        },
+                    },
+                },
+
+                PointerEventKind::Leave { .. } => continue,
+            };
+
+            self.comp.process_input_event::<WaylandInput>(&output, input_event);
+        }
+    }
+}
+
+impl ShmHandler for Loop {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.comp.backend.wayland_mut().shm
+    }
+}
+
+impl ProvidesRegistryState for Loop {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.comp.backend.wayland_mut().registry_state
+    }
+
+    registry_handlers![OutputState, SeatState];
+}
+
+delegate_compositor!(Loop);
+delegate_output!(Loop);
+delegate_shm!(Loop);
+delegate_seat!(Loop);
+delegate_pointer!(Loop);
+delegate_xdg_shell!(Loop);
+delegate_xdg_window!(Loop);
+delegate_registry!(Loop);
+
+/// Marker type for the input backend of the nested Wayland backend.
+#[derive(Debug)]
+pub struct WaylandInput;
+
+impl InputBackend for WaylandInput {
+    type Device = WaylandVirtualDevice;
+    type KeyboardKeyEvent = WaylandKeyboardKeyEvent;
+    type PointerAxisEvent = WaylandPointerAxisEvent;
+    type PointerButtonEvent = WaylandPointerButtonEvent;
+    type PointerMotionEvent = UnusedEvent;
+    type PointerMotionAbsoluteEvent = WaylandPointerMotionAbsoluteEvent;
+    type GestureSwipeBeginEvent = UnusedEvent;
+    type GestureSwipeUpdateEvent = UnusedEvent;
+    type GestureSwipeEndEvent = UnusedEvent;
+    type GesturePinchBeginEvent = UnusedEvent;
+    type GesturePinchUpdateEvent = UnusedEvent;
+    type GesturePinchEndEvent = UnusedEvent;
+    type GestureHoldBeginEvent = UnusedEvent;
+    type GestureHoldEndEvent = UnusedEvent;
+    type TouchDownEvent = UnusedEvent;
+    type TouchUpEvent = UnusedEvent;
+    type TouchMotionEvent = UnusedEvent;
+    type TouchCancelEvent = UnusedEvent;
+    type TouchFrameEvent = UnusedEvent;
+    type TabletToolAxisEvent = UnusedEvent;
+    type TabletToolProximityEvent = UnusedEvent;
+    type TabletToolTipEvent = UnusedEvent;
+    type TabletToolButtonEvent = UnusedEvent;
+    type SpecialEvent = UnusedEvent;
+}
+
+/// The virtual input device of the parent compositor's seat.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WaylandVirtualDevice;
+
+impl Device for WaylandVirtualDevice {
+    fn id(&self) -> String {
+        "wayland".into()
+    }
+
+    fn name(&self) -> String {
+        "Wayland virtual input".into()
+    }
+
+    fn has_capability(&self, capability: DeviceCapability) -> bool {
+        matches!(capability, DeviceCapability::Keyboard | DeviceCapability::Pointer)
+    }
+
+    fn usb_id(&self) -> Option<(u32, u32)> {
+        None
+    }
+
+    fn syspath(&self) -> Option<std::path::PathBuf> {
+        None
+    }
+}
+
+/// A key was pressed or released in a window of the parent compositor.
+#[derive(Debug, Clone)]
+pub struct WaylandKeyboardKeyEvent {
+    time: u32,
+    key: u32,
+    state: KeyState,
+}
+
+impl Event<WaylandInput> for WaylandKeyboardKeyEvent {
+    fn time(&self) -> u64 {
+        self.time as u64 * 1000
+    }
+
+    fn device(&self) -> WaylandVirtualDevice {
+        WaylandVirtualDevice
+    }
+}
+
+impl KeyboardKeyEvent<WaylandInput> for WaylandKeyboardKeyEvent {
+    fn key_code(&self) -> u32 {
+        // Like the key codes from the parent compositor, this is an evdev key code.
+        self.key
+    }
+
+    fn state(&self) -> KeyState {
+        self.state
+    }
+
+    fn count(&self) -> u32 {
+        // The parent compositor only sends key events for the window's seat.
+        1
+    }
+}
+
+/// The pointer moved within a window of the parent compositor.
+#[derive(Debug, Clone)]
+pub struct WaylandPointerMotionAbsoluteEvent {
+    time: u32,
+    x: f64,
+    y: f64,
+    /// The size of the window the pointer is in.
+    size: Size<i32, Physical>,
+}
+
+impl Event<WaylandInput> for WaylandPointerMotionAbsoluteEvent {
+    fn time(&self) -> u64 {
+        self.time as u64 * 1000
+    }
+
+    fn device(&self) -> WaylandVirtualDevice {
+        WaylandVirtualDevice
+    }
+}
+
+impl AbsolutePositionEvent<WaylandInput> for WaylandPointerMotionAbsoluteEvent {
+    fn x(&self) -> f64 {
+        self.x
+    }
+
+    fn y(&self) -> f64 {
+        self.y
+    }
+
+    fn x_transformed(&self, width: i32) -> f64 {
+        self.x * width as f64 / self.size.w.max(1) as f64
+    }
+
+    fn y_transformed(&self, height: i32) -> f64 {
+        self.y * height as f64 / self.size.h.max(1) as f64
+    }
+}
+
+impl PointerMotionAbsoluteEvent<WaylandInput> for WaylandPointerMotionAbsoluteEvent {}
+
+/// A pointer button was pressed or released in a window of the parent compositor.
+#[derive(Debug, Clone)]
+pub struct WaylandPointerButtonEvent {
+    time: u32,
+    button: u32,
+    state: ButtonState,
+}
+
+impl Event<WaylandInput> for WaylandPointerButtonEvent {
+    fn time(&self) -> u64 {
+        self.time as u64 * 1000
+    }
+
+    fn device(&self) -> WaylandVirtualDevice {
+        WaylandVirtualDevice
+    }
+}
+
+impl PointerButtonEvent<WaylandInput> for WaylandPointerButtonEvent {
+    fn button_code(&self) -> u32 {
+        self.button
+    }
+
+    fn state(&self) -> ButtonState {
+        self.state
+    }
+}
+
+/// A scroll occurred in a window of the parent compositor.
+#[derive(Debug, Clone)]
+pub struct WaylandPointerAxisEvent {
+    time: u32,
+    horizontal: AxisScroll,
+    vertical: AxisScroll,
+    source: AxisSource,
+}
+
+impl WaylandPointerAxisEvent {
+    fn axis(&self, axis: Axis) -> &AxisScroll {
+        match axis {
+            Axis::Horizontal => &self.horizontal,
+            Axis::Vertical => &self.vertical,
+        }
+    }
+}
+
+impl Event<WaylandInput> for WaylandPointerAxisEvent {
+    fn time(&self) -> u64 {
+        self.time as u64 * 1000
+    }
+
+    fn device(&self) -> WaylandVirtualDevice {
+        WaylandVirtualDevice
+    }
+}
+
+impl PointerAxisEvent<WaylandInput> for WaylandPointerAxisEvent {
+    fn amount(&self, axis: Axis) -> Option<f64> {
+        let scroll = self.axis(axis);
+        (!scroll.is_none()).then_some(scroll.absolute)
+    }
+
+    fn amount_discrete(&self, axis: Axis) -> Option<f64> {
+        let scroll = self.axis(axis);
+        (scroll.discrete != 0).then_some(scroll.discrete as f64)
+    }
+
+    fn source(&self) -> AxisSource {
+        self.source
+    }
+}
@@ -1,5 +1,7 @@
 //! X11 input and output backend
 
+use std::io;
+
 use calloop::LoopHandle;
 use smithay::{
     backend::{
@@ -7,27 +9,106 @@ use smithay::{
             dmabuf::{Dmabuf, DmabufAllocator},
             gbm::GbmAllocator,
         },
-        egl::{EGLContext, EGLDisplay},
-        renderer::{element::AsRenderElements, gles::GlesRenderer, utils::draw_render_elements, Bind, Frame, Renderer},
-        x11::{Window, WindowBuilder, X11Backend, X11Event, X11Handle, X11Surface},
+        egl::{self, EGLContext, EGLDisplay},
+        renderer::{
+            damage::{OutputDamageTracker, OutputDamageTrackerError},
+            element::AsRenderElements,
+            gles::{GlesError, GlesRenderbuffer, GlesRenderer},
+            Bind,
+        },
+        x11::{
+            AllocateBuffersError, PresentError, Window, WindowBuilder, X11Backend, X11Error, X11Event, X11Handle,
+            X11Surface,
+        },
+    },
+    output::{Mode, Output},
+    reexports::{
+        gbm::{self, BufferObjectFlags},
+        wayland_protocols::wp::presentation_time::server::wp_presentation_feedback,
     },
-    reexports::gbm::{self, BufferObjectFlags},
-    utils::{DeviceFd, Rectangle, Transform},
+    utils::{DeviceFd, Physical, Rectangle, Scale},
     wayland::{
         dmabuf::{DmabufGlobal, DmabufState, ImportError},
         shm::ShmState,
     },
 };
-use wayland_server::DisplayHandle;
+use wayland_server::{protocol::wl_buffer::WlBuffer, DisplayHandle};
+
+use crate::{
+    backend::OutputInfo,
+    capture::{self, CaptureError},
+    scene::{Hierarchy, SceneGraphElement},
+    Aerugo, Loop, StartupError,
+};
+
+/// An error which occurs while initializing the X11 backend.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to connect to the X server")]
+    Connect(#[source] X11Error),
+
+    #[error("failed to create the window")]
+    Window(#[source] X11Error),
+
+    #[error("failed to get the DRM node used by the X server")]
+    DrmNode(#[source] X11Error),
+
+    #[error("failed to create the GBM device")]
+    Gbm(#[source] io::Error),
+
+    #[error("failed to initialize EGL")]
+    Egl(#[from] egl::Error),
+
+    #[error("failed to create the window surface")]
+    Surface(#[source] X11Error),
+
+    #[error("failed to create the OpenGL ES renderer")]
+    Renderer(#[from] GlesError),
+
+    #[error("failed to register the X11 event source")]
+    EventSource(#[source] calloop::Error),
+}
 
-use crate::{scene::SceneGraphElement, Aerugo, Loop};
+/// An error that may occur when presenting the output.
+#[derive(Debug, thiserror::Error)]
+enum DrawError {
+    #[error("failed to allocate buffer")]
+    Buffer(#[from] AllocateBuffersError),
+
+    #[error("failed to bind buffer")]
+    Bind(#[from] GlesError),
+
+    #[error(transparent)]
+    Render(#[from] OutputDamageTrackerError<GlesRenderer>),
+
+    #[error("failed to present buffer")]
+    Present(#[from] PresentError),
+}
+
+impl From<Error> for StartupError {
+    fn from(err: Error) -> Self {
+        match err {
+            // Without a DRM node or working EGL implementation, the X server has no usable GPU.
+            Error::DrmNode(_) | Error::Gbm(_) | Error::Egl(_) | Error::Renderer(_) => {
+                StartupError::Renderer(err.into())
+            }
+            err => StartupError::Backend(err.into()),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Backend {
     x11: X11Handle,
     window: Window,
+    output: Output,
     renderer: GlesRenderer,
     surface: X11Surface,
+    damage_tracker: OutputDamageTracker,
+    /// Whether the output needs to be rendered.
+    render_scheduled: bool,
+    /// Whether a buffer was submitted and has not been presented yet.
+    present_pending: bool,
     r#loop: LoopHandle<'static, Loop>,
     display: DisplayHandle,
     shm_state: ShmState,
@@ -41,30 +122,37 @@ impl dyn super::Backend {
 }
 
 impl Backend {
-    // TODO: Error type
-    pub fn new(r#loop: LoopHandle<'static, Loop>, display: DisplayHandle) -> Result<Self, ()> {
-        let backend = X11Backend::new().unwrap();
+    pub fn new(r#loop: LoopHandle<'static, Loop>, display: DisplayHandle) -> Result<Self, Error> {
+        let backend = X11Backend::new().map_err(Error::Connect)?;
         let x11 = backend.handle();
 
-        // TODO: Initialize output with window.
-        //
         // TODO for Smithay:
         // - Allow specifying the format of the buffers presented to the window. For now we rely on the X11
         //   backend to select Argb8888 or Xrgb8888. It may be desireable however to use Argb2101010 if
         //   available. This will however require a way to enumerate what formats the window could be created
         //   with.
-        let window = WindowBuilder::new().title("Aerugo").build(&x11).unwrap();
+        let window = WindowBuilder::new()
+            .title("Aerugo")
+            .build(&x11)
+            .map_err(Error::Window)?;
         window.map();
 
+        let output = OutputInfo {
+            make: "Aerugo".into(),
+            model: "X11 window".into(),
+            ..OutputInfo::new("X11-1", window_mode(&window))
+        }
+        .create_output();
+
         // Get the drm node for buffer allocation and initializing EGL.
         //
         // TODO for Smithay:
         // - This should return just the path to the drm device. For the legacy DRI3 fallback, there should be
         //   a separate function to get the DRM file descriptor in that case.
-        let (_, fd) = x11.drm_node().expect("Failed to get DRM node used by X server");
-        let device = gbm::Device::new(DeviceFd::from(fd)).unwrap();
-        let egl = EGLDisplay::new(device.clone()).unwrap();
-        let context = EGLContext::new(&egl).unwrap();
+        let (_, fd) = x11.drm_node().map_err(Error::DrmNode)?;
+        let device = gbm::Device::new(DeviceFd::from(fd)).map_err(Error::Gbm)?;
+        let egl = EGLDisplay::new(device.clone())?;
+        let context = EGLContext::new(&egl)?;
 
         let surface = x11
             .create_surface(
@@ -72,15 +160,24 @@ impl Backend {
                 DmabufAllocator(GbmAllocator::new(device.clone(), BufferObjectFlags::RENDERING)),
                 context.dmabuf_render_formats().iter().map(|format| format.modifier),
             )
-            .unwrap();
+            .map_err(Error::Surface)?;
 
-        let renderer = unsafe { GlesRenderer::new(context) }.unwrap();
+        let renderer = unsafe { GlesRenderer::new(context) }?;
 
-        r#loop.insert_source(backend, dispatch_x11_event).unwrap();
+        r#loop
+            .insert_source(backend, dispatch_x11_event)
+            .map_err(|err| Error::EventSource(err.error))?;
+
+        // The compositor is not available until the backend has been created, so defer announcing the output.
+        let connected = output.clone();
+        r#loop.insert_idle(move |state| {
+            state.comp.add_output(connected);
+        });
 
         Ok(Self {
             x11,
             window,
+            output,
             r#loop,
             display: display.clone(),
             // TODO: Additional renderer shm formats
@@ -88,19 +185,47 @@ impl Backend {
             shutdown: false,
             renderer,
             surface,
+            damage_tracker: OutputDamageTracker::from_output(&output),
+            render_scheduled: false,
+            present_pending: false,
         })
     }
 }
 
 fn dispatch_x11_event(event: X11Event, _: &mut (), aerugo: &mut Loop) {
     match event {
-        X11Event::Refresh { window_id: _ } => draw(aerugo),
-        X11Event::Input(_) => {}
+        X11Event::Refresh { window_id: _ } => {
+            let output = aerugo.comp.backend.x11_mut().output.clone();
+            aerugo.comp.backend.schedule_render(&output);
+        }
+        X11Event::Input(event) => {
+            let output = aerugo.comp.backend.x11_mut().output.clone();
+            aerugo.comp.process_input_event(&output, event);
+        }
         X11Event::Resized {
             new_size: _,
             window_id: _,
-        } => draw(aerugo),
-        X11Event::PresentCompleted { window_id: _ } => draw(aerugo),
+        } => {
+            let backend = aerugo.comp.backend.x11_mut();
+            let mode = window_mode(&backend.window);
+            let output = backend.output.clone();
+
+            // The previous size of the window is no longer a valid mode.
+            aerugo.comp.output_mode_changed(&output, mode);
+        }
+        X11Event::PresentCompleted { window_id: _ } => {
+            let backend = aerugo.comp.backend.x11_mut();
+            backend.present_pending = false;
+
+            // The X server does not provide the time the frame was presented.
+            let output = backend.output.clone();
+            let time = aerugo.comp.clock.now();
+            aerugo
+                .comp
+                .frame_presented(&output, time.into(), wp_presentation_feedback::Kind::Vsync);
+
+            render(aerugo);
+        }
         X11Event::CloseRequested { window_id: _ } => {
             // TODO: shutdown based on output counts
             let backend: &mut Backend = &mut aerugo.comp.backend.downcast_mut().unwrap();
@@ -110,54 +235,60 @@ fn dispatch_x11_event(event: X11Event, _: &mut (), aerugo: &mut Loop) {
     }
 }
 
-fn draw(aerugo: &mut Loop) {
+/// The mode of the output which presents to the window.
+fn window_mode(window: &Window) -> Mode {
+    let size = window.size();
+
+    Mode {
+        size: (size.w as i32, size.h as i32).into(),
+        // The X server does not tell clients the refresh rate of the monitor a window is presented on.
+        refresh: 60_000,
+    }
+}
+
+fn render(aerugo: &mut Loop) {
+    if let Err(err) = draw(aerugo) {
+        tracing::error!(%err, "Failed to draw output");
+    }
+}
+
+fn draw(aerugo: &mut Loop) -> Result<(), DrawError> {
     let backend = aerugo.comp.backend.x11_mut();
-    let (buffer, _age) = backend.surface.buffer().unwrap();
-    backend.renderer.bind(buffer).unwrap();
-
-    let elems: Vec<SceneGraphElement> = if let Some(hir) = aerugo.comp.scene.get_graph(&aerugo.comp.output) {
-        hir.render_elements(
-            &mut backend.renderer,
-            (0, 0).into(),
-            smithay::utils::Scale { x: 1., y: 1. },
-            1.0,
-        )
-        .into()
+
+    // The next frame is rendered once the previous frame has been presented.
+    if !backend.render_scheduled || backend.present_pending {
+        return Ok(());
+    }
+
+    backend.render_scheduled = false;
+
+    let (buffer, age) = backend.surface.buffer()?;
+    backend.renderer.bind(buffer)?;
+
+    let scale = Scale::from(backend.output.current_scale().fractional_scale());
+    let elems: Vec<SceneGraphElement> = if let Some(hir) = aerugo.comp.scene.get_graph(&backend.output) {
+        hir.render_elements(&mut backend.renderer, (0, 0).into(), scale, 1.0)
     } else {
         Vec::new()
     };
 
-    {
-        let mut frame = backend
-            .renderer
-            .render(
-                (backend.window.size().w as i32, backend.window.size().h as i32).into(),
-                Transform::Normal,
-            )
-            .unwrap();
-
-        frame
-            .clear(
-                [0.8, 0.8, 0.8, 1.0],
-                &[Rectangle::from_loc_and_size(
-                    (0, 0),
-                    (backend.window.size().w as i32, backend.window.size().h as i32),
-                )],
-            )
-            .unwrap();
+    let (damage, states) =
+        backend
+            .damage_tracker
+            .render_output(&mut backend.renderer, age as usize, &elems, super::CLEAR_COLOR)?;
+    let output = backend.output.clone();
 
-        draw_render_elements::<GlesRenderer, _, _>(
-            &mut frame,
-            1.0,
-            &elems,
-            &[Rectangle::from_loc_and_size((0, 0), (i32::MAX, i32::MAX))],
-        )
-        .unwrap();
-
-        frame.finish().unwrap();
+    // If nothing was damaged, the buffer was not rendered to. The same buffer is reused for the next frame.
+    if damage.is_none() {
+        aerugo.comp.frame_skipped(&output, &elems, &states);
+        return Ok(());
     }
 
-    backend.surface.submit().unwrap();
+    backend.surface.submit()?;
+    backend.present_pending = true;
+    aerugo.comp.frame_submitted(&output, &elems, &states);
+
+    Ok(())
 }
 
 impl crate::backend::Backend for Backend {
@@ -176,4 +307,28 @@ impl crate::backend::Backend for Backend {
     fn should_shutdown(&self) -> bool {
         self.shutdown
     }
+
+    fn schedule_render(&mut self, _output: &Output) {
+        if self.render_scheduled {
+            return;
+        }
+
+        self.render_scheduled = true;
+
+        // If a frame is waiting to be presented, the output is rendered once the frame is presented.
+        if !self.present_pending {
+            self.r#loop.insert_idle(render);
+        }
+    }
+
+    fn capture(
+        &mut self,
+        hierarchy: Option<Hierarchy<'_>>,
+        region: Rectangle<i32, Physical>,
+        scale: Scale<f64>,
+        clear_color: [f32; 4],
+        buffer: &WlBuffer,
+    ) -> Result<(), CaptureError> {
+        capture::render_to_shm::<_, GlesRenderbuffer>(&mut self.renderer, hierarchy, region, scale, clear_color, buffer)
+    }
 }
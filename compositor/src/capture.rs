@@ -0,0 +1,428 @@
+//! Screen capture
+//!
+//! Clients with the [`PrivilegedGlobals::SCREEN_CAPTURE`](crate::PrivilegedGlobals::SCREEN_CAPTURE) bit may
+//! capture the contents of an output or a toplevel into a shared memory buffer. The protocol implementations in
+//! [`crate::wayland`] queue captures here and are notified once the capture has completed or failed.
+//!
+//! The first capture of a session is completed immediately. Subsequent captures wait until the source is
+//! damaged, which paces clients to the rate the source is updated at.
+
+use std::{error::Error, time::Duration};
+
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            damage::OutputDamageTracker, element::AsRenderElements, Bind, ExportMem, ImportAll, ImportMem, Offscreen,
+            Renderer,
+        },
+    },
+    output::Output,
+    utils::{Physical, Rectangle, Scale, Size, Transform},
+    wayland::shm,
+};
+use wayland_server::{
+    protocol::{wl_buffer::WlBuffer, wl_shm},
+    Resource,
+};
+
+use crate::{
+    backend,
+    output::output_size,
+    scene::{Hierarchy, SceneGraphElement},
+    shell::ToplevelId,
+    wayland::{
+        ext::image_copy_capture::{
+            ext_image_copy_capture_frame_v1::ExtImageCopyCaptureFrameV1,
+            ext_image_copy_capture_session_v1::ExtImageCopyCaptureSessionV1,
+        },
+        wlr,
+    },
+    Aerugo,
+};
+
+/// Formats of shared memory buffers which captures may be copied into.
+pub const SHM_FORMATS: [wl_shm::Format; 2] = [wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888];
+
+/// Something which can be captured.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureSource {
+    /// The contents of an output.
+    Output(Output),
+
+    /// The contents of a toplevel, including any subsurfaces.
+    Toplevel(ToplevelId),
+}
+
+/// An error which occurs while capturing a source.
+#[derive(Debug, thiserror::Error)]
+pub enum CaptureError {
+    /// The buffer does not match the size or format of the capture.
+    #[error("the buffer does not satisfy the capture constraints")]
+    BufferConstraints,
+
+    /// The source of the capture no longer exists or is not visible.
+    #[error("the capture source is not available")]
+    SourceUnavailable,
+
+    /// The source could not be rendered.
+    #[error("failed to render the capture source")]
+    Render(#[source] Box<dyn Error + Send + Sync>),
+}
+
+/// The protocol object a capture is reported to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureFrame {
+    /// An `ext_image_copy_capture_frame_v1`.
+    Ext(ExtImageCopyCaptureFrameV1),
+
+    /// A `zwlr_screencopy_frame_v1`.
+    Wlr(wlr::screencopy::zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1),
+}
+
+/// A capture which has been requested by a client.
+#[derive(Debug)]
+pub struct Capture {
+    pub source: CaptureSource,
+    pub frame: CaptureFrame,
+    pub buffer: WlBuffer,
+
+    /// The area of the source to capture. If not set the entire source is captured.
+    pub region: Option<Rectangle<i32, Physical>>,
+}
+
+#[derive(Debug, Default)]
+pub struct CaptureState {
+    /// Captures which are waiting for the source to be damaged.
+    pending: Vec<Capture>,
+
+    /// Active `ext_image_copy_capture_session_v1` sessions.
+    pub(crate) sessions: Vec<ExtImageCopyCaptureSessionV1>,
+}
+
+impl Aerugo {
+    /// The size of a buffer needed to capture the source.
+    ///
+    /// Returns [`None`] if the source is not available.
+    pub fn capture_size(&self, source: &CaptureSource) -> Option<Size<i32, Physical>> {
+        match source {
+            CaptureSource::Output(output) => self
+                .outputs()
+                .any(|connected| connected == output)
+                .then(|| output_size(output)),
+
+            CaptureSource::Toplevel(id) => {
+                let surface = self.shell.get_state(*id)?.wl_surface()?;
+                let hierarchy = self.scene.get_surface_tree_graph(&surface)?;
+                let size = hierarchy.bounding_box(self.capture_scale(source)).size;
+
+                // A buffer must be at least a single pixel.
+                Some((size.w.max(1), size.h.max(1)).into())
+            }
+        }
+    }
+
+    /// Capture a source.
+    ///
+    /// If `immediate` is false, the capture is completed when the source is next damaged.
+    pub fn capture(&mut self, capture: Capture, immediate: bool) {
+        if immediate {
+            self.complete_capture(capture);
+        } else {
+            self.captures.pending.push(capture);
+        }
+    }
+
+    /// Complete pending captures affected by a frame which was submitted to the output.
+    pub(crate) fn process_captures(&mut self, output: &Output, elements: &[SceneGraphElement]) {
+        let (ready, pending) = std::mem::take(&mut self.captures.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|capture| match &capture.source {
+                CaptureSource::Output(source) => source == output,
+                CaptureSource::Toplevel(id) => {
+                    let surface = self.shell.get_state(*id).and_then(|toplevel| toplevel.wl_surface());
+                    surface.map_or(false, |surface| {
+                        elements.iter().any(|element| element.surface() == Some(&surface))
+                    })
+                }
+            });
+
+        self.captures.pending = pending;
+
+        for capture in ready {
+            self.complete_capture(capture);
+        }
+    }
+
+    /// Fail all pending captures of a source and stop capture sessions of the source.
+    ///
+    /// This should be called when the source is destroyed.
+    pub(crate) fn stop_captures(&mut self, source: &CaptureSource) {
+        let (stopped, pending) = std::mem::take(&mut self.captures.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|capture| &capture.source == source);
+
+        self.captures.pending = pending;
+
+        for capture in stopped {
+            capture.frame.failed(&CaptureError::SourceUnavailable);
+        }
+
+        crate::wayland::ext::image_copy_capture::stop_sessions(self, source);
+    }
+
+    /// The scale the source is rendered at when captured.
+    fn capture_scale(&self, source: &CaptureSource) -> Scale<f64> {
+        match source {
+            CaptureSource::Output(output) => output.current_scale().fractional_scale().into(),
+
+            CaptureSource::Toplevel(id) => {
+                let Some(surface) = self.shell.get_state(*id).and_then(|toplevel| toplevel.wl_surface()) else {
+                    return 1.0.into();
+                };
+
+                // Capture at the highest scale of the outputs the toplevel is on so no detail is lost.
+                let outputs = self.scene.surface_outputs(&surface);
+                highest_scale(outputs.iter().map(|output| output.current_scale().fractional_scale()))
+            }
+        }
+    }
+
+    fn complete_capture(&mut self, capture: Capture) {
+        if !capture.frame.is_alive() {
+            return;
+        }
+
+        let time = self.clock.now();
+
+        match self.render_capture(&capture) {
+            Ok(()) => capture.frame.ready(time.into()),
+            Err(err) => {
+                if let CaptureError::Render(ref err) = err {
+                    tracing::error!(%err, source = ?capture.source, "Failed to capture");
+                }
+
+                capture.frame.failed(&err);
+            }
+        }
+    }
+
+    fn render_capture(&mut self, capture: &Capture) -> Result<(), CaptureError> {
+        let size = self
+            .capture_size(&capture.source)
+            .ok_or(CaptureError::SourceUnavailable)?;
+        let bounds = Rectangle::from_loc_and_size((0, 0), size);
+        let scale = self.capture_scale(&capture.source);
+
+        let (hierarchy, offset, clear_color) = match &capture.source {
+            CaptureSource::Output(output) => (self.scene.get_graph(output), (0, 0).into(), backend::CLEAR_COLOR),
+
+            CaptureSource::Toplevel(id) => {
+                let surface = self
+                    .shell
+                    .get_state(*id)
+                    .and_then(|toplevel| toplevel.wl_surface())
+                    .ok_or(CaptureError::SourceUnavailable)?;
+                let hierarchy = self
+                    .scene
+                    .get_surface_tree_graph(&surface)
+                    .ok_or(CaptureError::SourceUnavailable)?;
+                let offset = hierarchy.bounding_box(scale).loc;
+
+                // Areas the toplevel does not cover are transparent.
+                (Some(hierarchy), offset, [0.0; 4])
+            }
+        };
+
+        let region = match capture.region {
+            Some(region) => region.intersection(bounds).ok_or(CaptureError::BufferConstraints)?,
+            None => bounds,
+        };
+
+        self.backend.capture(
+            hierarchy,
+            Rectangle::from_loc_and_size(region.loc + offset, region.size),
+            scale,
+            clear_color,
+            &capture.buffer,
+        )
+    }
+}
+
+impl CaptureFrame {
+    fn is_alive(&self) -> bool {
+        match self {
+            CaptureFrame::Ext(frame) => frame.is_alive(),
+            CaptureFrame::Wlr(frame) => frame.is_alive(),
+        }
+    }
+
+    fn ready(&self, time: Duration) {
+        match self {
+            CaptureFrame::Ext(frame) => crate::wayland::ext::image_copy_capture::frame_ready(frame, time),
+            CaptureFrame::Wlr(frame) => wlr::screencopy::frame_ready(frame, time),
+        }
+    }
+
+    fn failed(&self, err: &CaptureError) {
+        match self {
+            CaptureFrame::Ext(frame) => crate::wayland::ext::image_copy_capture::frame_failed(frame, err),
+            CaptureFrame::Wlr(frame) => wlr::screencopy::frame_failed(frame, err),
+        }
+    }
+}
+
+/// The highest of the scales, or a scale of 1 if there are no scales.
+fn highest_scale(scales: impl IntoIterator<Item = f64>) -> Scale<f64> {
+    scales.into_iter().reduce(f64::max).unwrap_or(1.0).into()
+}
+
+/// Check whether a shared memory buffer can hold a capture of the specified size.
+pub fn buffer_satisfies_constraints(data: &shm::BufferData, size: Size<i32, Physical>) -> bool {
+    data.width == size.w && data.height == size.h && data.stride >= size.w * 4 && SHM_FORMATS.contains(&data.format)
+}
+
+/// Render part of a hierarchy into a shared memory buffer.
+///
+/// The region is the area of the hierarchy to capture, in the physical coordinate space at the scale, and must be
+/// the same size as the buffer. Renderers implement this by rendering into an offscreen buffer and copying the
+/// framebuffer into the shared memory buffer.
+pub fn render_to_shm<R, T>(
+    renderer: &mut R,
+    hierarchy: Option<Hierarchy<'_>>,
+    region: Rectangle<i32, Physical>,
+    scale: Scale<f64>,
+    clear_color: [f32; 4],
+    buffer: &WlBuffer,
+) -> Result<(), CaptureError>
+where
+    R: Renderer + ImportAll + ImportMem + Offscreen<T> + Bind<T> + ExportMem + 'static,
+    R::TextureId: 'static,
+    R::Error: Send + Sync + 'static,
+{
+    let size = region.size;
+    let data = shm::with_buffer_contents(buffer, |_, _, data| data).map_err(|_| CaptureError::BufferConstraints)?;
+
+    if !buffer_satisfies_constraints(&data, size) {
+        return Err(CaptureError::BufferConstraints);
+    }
+
+    let offscreen = renderer
+        .create_buffer(Fourcc::Argb8888, (size.w, size.h).into())
+        .map_err(|err| CaptureError::Render(err.into()))?;
+    renderer
+        .bind(offscreen)
+        .map_err(|err| CaptureError::Render(err.into()))?;
+
+    let elements: Vec<SceneGraphElement> = hierarchy
+        .map(|hierarchy| hierarchy.render_elements(renderer, -region.loc, scale, 1.0))
+        .unwrap_or_default();
+
+    // A new damage tracker renders every element since there is no previous frame.
+    let mut damage_tracker = OutputDamageTracker::new(size, scale, Transform::Normal);
+    damage_tracker
+        .render_output(renderer, 0, &elements, clear_color)
+        .map_err(|err| CaptureError::Render(err.into()))?;
+
+    let mapping = renderer
+        .copy_framebuffer(Rectangle::from_loc_and_size((0, 0), (size.w, size.h)), Fourcc::Argb8888)
+        .map_err(|err| CaptureError::Render(err.into()))?;
+    let pixels = renderer
+        .map_texture(&mapping)
+        .map_err(|err| CaptureError::Render(err.into()))?;
+
+    let row = size.w as usize * 4;
+    shm::with_buffer_contents_mut(buffer, |ptr, len, data| {
+        let offset = data.offset as usize;
+        let stride = data.stride as usize;
+
+        if offset + stride * (size.h as usize - 1) + row > len {
+            return Err(CaptureError::BufferConstraints);
+        }
+
+        // SAFETY: The pool is mapped for the duration of the closure and the bounds were checked above.
+        let contents = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+
+        for (y, pixels) in pixels.chunks_exact(row).enumerate() {
+            let start = offset + y * stride;
+            contents[start..start + row].copy_from_slice(pixels);
+        }
+
+        Ok(())
+    })
+    .map_err(|_| CaptureError::BufferConstraints)?
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::{utils::Scale, wayland::shm::BufferData};
+    use wayland_server::protocol::wl_shm;
+
+    use super::{buffer_satisfies_constraints, highest_scale};
+
+    fn buffer(width: i32, height: i32, stride: i32, format: wl_shm::Format) -> BufferData {
+        BufferData {
+            offset: 0,
+            width,
+            height,
+            stride,
+            format,
+        }
+    }
+
+    #[test]
+    fn highest_scale_of_outputs() {
+        assert_eq!(highest_scale([1.0, 2.0, 1.5]), Scale::from(2.0));
+        assert_eq!(highest_scale([1.25]), Scale::from(1.25));
+    }
+
+    /// A toplevel which is not on any output is captured at a scale of 1.
+    #[test]
+    fn highest_scale_no_outputs() {
+        assert_eq!(highest_scale(Vec::new()), Scale::from(1.0));
+    }
+
+    #[test]
+    fn buffer_constraints() {
+        let size = (64, 32).into();
+
+        assert!(buffer_satisfies_constraints(
+            &buffer(64, 32, 256, wl_shm::Format::Argb8888),
+            size
+        ));
+        assert!(buffer_satisfies_constraints(
+            &buffer(64, 32, 256, wl_shm::Format::Xrgb8888),
+            size
+        ));
+        // Rows may be padded.
+        assert!(buffer_satisfies_constraints(
+            &buffer(64, 32, 320, wl_shm::Format::Argb8888),
+            size
+        ));
+    }
+
+    #[test]
+    fn buffer_constraints_rejected() {
+        let size = (64, 32).into();
+
+        // Wrong size
+        assert!(!buffer_satisfies_constraints(
+            &buffer(32, 32, 256, wl_shm::Format::Argb8888),
+            size
+        ));
+        assert!(!buffer_satisfies_constraints(
+            &buffer(64, 64, 256, wl_shm::Format::Argb8888),
+            size
+        ));
+        // Rows are too short
+        assert!(!buffer_satisfies_constraints(
+            &buffer(64, 32, 128, wl_shm::Format::Argb8888),
+            size
+        ));
+        // Unsupported format
+        assert!(!buffer_satisfies_constraints(
+            &buffer(64, 32, 256, wl_shm::Format::Rgb565),
+            size
+        ));
+    }
+}
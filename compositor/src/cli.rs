@@ -1,5 +1,6 @@
 //! Command line argument parsing using clap.
 
+use aerugo_comp::backend::{headless::VirtualOutput, BackendSelection, RendererSelection};
 use clap::{Parser, ValueEnum};
 
 /// The Aerugo wayland compositor
@@ -21,6 +22,8 @@ pub struct AerugoArgs {
     ///
     /// The `x11` and `wayland` options both act like `windowed`, but allow specifying whether aerugo is run as an X11
     /// or Wayland client.
+    ///
+    /// `headless`: The compositor is run without a display and renders virtual outputs into memory.
     #[clap(value_enum, default_value_t, short, long)]
     pub backend: Backend,
 
@@ -28,9 +31,18 @@ pub struct AerugoArgs {
     ///
     /// This allows overriding the renderer to use at runtime. This may be useful in case of driver bugs.
     ///
-    /// Right now only the OpenGL ES renderer is supported. In the future a Vulkan renderer will be available.
+    /// The OpenGL ES renderer is used by the X11 backend and the Pixman software renderer is used by the Wayland and
+    /// headless backends. In the future a Vulkan renderer will be available.
     #[clap(value_enum, default_value_t, long)]
     pub renderer: Renderer,
+
+    /// Virtual output created by the headless backend
+    ///
+    /// Outputs are formatted as `<width>x<height>[@<refresh>]`, where the refresh rate is in hertz and defaults to
+    /// 60. This option may be repeated to create multiple outputs. If not specified, a single `1920x1080@60` output
+    /// is created.
+    #[clap(long = "headless-output", value_name = "OUTPUT")]
+    pub headless_outputs: Vec<VirtualOutput>,
     // TODO: WM process to start
     // TODO: How should the WM spawn privileged clients?
 }
@@ -61,6 +73,22 @@ pub enum Backend {
     /// Launch the compositor inside a window as an X11 client.
     #[clap(alias("x"))]
     X11,
+
+    /// Launch the compositor without a display, rendering virtual outputs into memory.
+    Headless,
+}
+
+impl From<Backend> for BackendSelection {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Auto => BackendSelection::Auto,
+            Backend::Kms => BackendSelection::Kms,
+            Backend::Windowed => BackendSelection::Windowed,
+            Backend::Wayland => BackendSelection::Wayland,
+            Backend::X11 => BackendSelection::X11,
+            Backend::Headless => BackendSelection::Headless,
+        }
+    }
 }
 
 /// Enum containing all possible renderer backends
@@ -76,6 +104,19 @@ pub enum Renderer {
     #[clap(alias("gl"))]
     #[clap(alias("gles"))]
     Gles,
+
+    /// Use the Pixman software renderer.
+    Pixman,
     // #[clap(alias("vk"))]
     // Vulkan, // TODO
 }
+
+impl From<Renderer> for RendererSelection {
+    fn from(renderer: Renderer) -> Self {
+        match renderer {
+            Renderer::Default => RendererSelection::Default,
+            Renderer::Gles => RendererSelection::Gles,
+            Renderer::Pixman => RendererSelection::Pixman,
+        }
+    }
+}
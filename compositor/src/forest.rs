@@ -10,6 +10,12 @@ pub enum Error {
 
     #[error("failed to insert because the forest would become cyclic")]
     Cycle,
+
+    #[error("{0:?} and {1:?} are not siblings")]
+    NotSiblings(Index, Index),
+
+    #[error("{0:?} has no parent")]
+    NoParent(Index),
 }
 
 #[derive(Debug)]
@@ -57,22 +63,57 @@ impl<T> Forest<T> {
         self.inner.contains_key(index)
     }
 
+    /// The nodes which do not have a parent.
+    pub fn roots(&self) -> impl Iterator<Item = Index> + '_ {
+        self.inner
+            .iter()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(index, _)| index)
+    }
+
     /// Removes the index from the forest, returning the value stored with the index.
+    ///
+    /// The children of the node are orphaned, becoming the roots of their own trees.
     pub fn remove(&mut self, index: Index) -> Result<T, Error> {
         // Detach the node before removing from the map.
         self.detach(index)?;
-        // TODO: Detach children from the node.
+
+        let children = self.children(index).collect::<Vec<_>>();
+
+        for child in children {
+            let child = self.get_mut(child).unwrap();
+            child.parent = None;
+            child.prev = None;
+            child.next = None;
+        }
 
         let node = self.inner.remove(index).unwrap();
         Ok(node.value)
     }
 
+    /// Removes the index and all of it's descendants from the forest.
+    ///
+    /// The values are returned in post-order, meaning the value of the index is last.
+    pub fn remove_subtree(&mut self, index: Index) -> Result<Vec<T>, Error> {
+        self.detach(index)?;
+
+        let indices = self.postorder_traverse(index).unwrap().collect::<Vec<_>>();
+
+        Ok(indices
+            .into_iter()
+            .map(|index| self.inner.remove(index).unwrap().value)
+            .collect())
+    }
+
     /// Adds makes the `child` a child of the `index`.
     pub fn add_child(&mut self, index: Index, child: Index) -> Result<(), Error> {
         self.is_present(index)?;
         self.is_present(child)?;
         self.check_for_cycles(index, child)?;
 
+        // The child may still be linked to siblings or a parent elsewhere in the forest.
+        self.detach(child)?;
+
         let parent = self.get_mut(index).unwrap();
 
         match parent.first_last_child {
@@ -112,6 +153,30 @@ impl<T> Forest<T> {
         Ok(())
     }
 
+    /// Moves the node to be the last child of the `parent`.
+    ///
+    /// If `parent` is [`None`], the node is detached and becomes a root. Unlike [`Forest::add_child`], the node may
+    /// already have a parent. If an error is returned the forest is left unchanged.
+    pub fn reparent(&mut self, index: Index, parent: Option<Index>) -> Result<(), Error> {
+        self.is_present(index)?;
+
+        if let Some(parent) = parent {
+            self.is_present(parent)?;
+
+            // The node cannot become a child of itself or one of it's descendants.
+            if self.ancestors(parent).any(|ancestor| ancestor == index) {
+                return Err(Error::Cycle);
+            }
+        }
+
+        self.detach(index)?;
+
+        match parent {
+            Some(parent) => self.add_child(parent, index),
+            None => Ok(()),
+        }
+    }
+
     /// Detaches the node from it's parent and siblings.
     ///
     /// The children of the node are not detached.
@@ -119,11 +184,9 @@ impl<T> Forest<T> {
         self.is_present(index)?;
 
         let node = self.get_mut(index).unwrap();
-        let parent = Node::parent(node);
-        node.parent.take();
-
-        let prev_sibling = Node::prev_sibling(node);
-        let next_sibling = Node::next_sibling(node);
+        let parent = node.parent.take();
+        let prev_sibling = node.prev.take();
+        let next_sibling = node.next.take();
 
         match (prev_sibling, next_sibling) {
             // If this node is the only child of it's parent we need to fully detach the parent.
@@ -141,6 +204,8 @@ impl<T> Forest<T> {
                     let last_child = Node::last_child(node).unwrap();
                     node.first_last_child = Some((next, last_child))
                 }
+
+                self.get_mut(next).unwrap().prev = None;
             }
 
             // This node is the last child of the parent
@@ -150,6 +215,8 @@ impl<T> Forest<T> {
                     let first_child = Node::first_child(node).unwrap();
                     node.first_last_child = Some((first_child, prev))
                 }
+
+                self.get_mut(prev).unwrap().next = None;
             }
 
             (Some(prev), Some(next)) => {
@@ -183,6 +250,11 @@ impl<T> Forest<T> {
         self.preorder_traverse(index).map(DfsDescend)
     }
 
+    /// Visit the node and it's descendants, visiting the children of a node before the node.
+    pub fn postorder_traverse(&self, index: Index) -> Option<PostorderTraverse<'_, T>> {
+        self.preorder_traverse(index).map(PostorderTraverse)
+    }
+
     pub fn previous_siblings(&self, index: Index) -> Option<PreviousSiblings<'_, T>> {
         if !self.contains_index(index) {
             return None;
@@ -218,47 +290,153 @@ impl<T> Forest<T> {
         }
     }
 
-    // TODO: Relation related methods
-    // - Raise/lower node as child
+    /// Moves the `node` to be placed before the `sibling`.
+    ///
+    /// The node is detached from it's current parent and siblings and becomes a child of the sibling's parent.
+    /// Roots have no siblings, so the sibling must have a parent.
+    pub fn insert_before(&mut self, sibling: Index, node: Index) -> Result<(), Error> {
+        self.is_present(sibling)?;
+        self.is_present(node)?;
+        self.check_for_sibling_cycles(sibling, node)?;
+
+        if self.get(sibling).unwrap().parent.is_none() {
+            return Err(Error::NoParent(sibling));
+        }
+        self.detach(node)?;
 
-    fn is_present(&self, index: Index) -> Result<(), Error> {
-        if !self.contains_index(index) {
-            return Err(Error::NotPresent(index));
+        let sibling_node = self.get_mut(sibling).unwrap();
+        let parent = sibling_node.parent;
+        let prev = sibling_node.prev.replace(node);
+
+        let moving = self.get_mut(node).unwrap();
+        moving.parent = parent;
+        moving.prev = prev;
+        moving.next = Some(sibling);
+
+        match prev {
+            Some(prev) => self.get_mut(prev).unwrap().next = Some(node),
+
+            // The sibling was the first child, so the node is now the first child.
+            None => {
+                if let Some(parent) = parent {
+                    let parent = self.get_mut(parent).unwrap();
+                    let last_child = Node::last_child(parent).unwrap();
+                    parent.first_last_child = Some((node, last_child));
+                }
+            }
         }
 
         Ok(())
     }
 
-    fn check_for_cycles(&self, index: Index, inserting: Index) -> Result<(), Error> {
-        // 1. If the two nodes are the same, then a cycle is guaranteed.
-        if index == inserting {
-            return Err(Error::Cycle);
+    /// Moves the `node` to be placed after the `sibling`.
+    ///
+    /// The node is detached from it's current parent and siblings and becomes a child of the sibling's parent.
+    /// Roots have no siblings, so the sibling must have a parent.
+    pub fn insert_after(&mut self, sibling: Index, node: Index) -> Result<(), Error> {
+        self.is_present(sibling)?;
+        self.is_present(node)?;
+        self.check_for_sibling_cycles(sibling, node)?;
+
+        if self.get(sibling).unwrap().parent.is_none() {
+            return Err(Error::NoParent(sibling));
         }
+        self.detach(node)?;
+
+        let sibling_node = self.get_mut(sibling).unwrap();
+        let parent = sibling_node.parent;
+        let next = sibling_node.next.replace(node);
 
-        // 2. If the node being inserted has no parents, siblings or children a cycle is impossible.
-        let inserting_node = self.get(inserting).expect("Node needs to be present");
+        let moving = self.get_mut(node).unwrap();
+        moving.parent = parent;
+        moving.prev = Some(sibling);
+        moving.next = next;
 
-        if inserting_node.parent.is_none()
-            && inserting_node.prev.is_none()
-            && inserting_node.next.is_none()
-            && inserting_node.first_last_child.is_none()
-        {
+        match next {
+            Some(next) => self.get_mut(next).unwrap().prev = Some(node),
+
+            // The sibling was the last child, so the node is now the last child.
+            None => {
+                if let Some(parent) = parent {
+                    let parent = self.get_mut(parent).unwrap();
+                    let first_child = Node::first_child(parent).unwrap();
+                    parent.first_last_child = Some((first_child, node));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Swaps the positions of two nodes with the same parent.
+    pub fn swap_siblings(&mut self, a: Index, b: Index) -> Result<(), Error> {
+        self.is_present(a)?;
+        self.is_present(b)?;
+
+        if a == b {
             return Ok(());
         }
 
-        // 3. Ensure the parent and child do not become cyclic.
-        let parent_node = self.get(index).expect("Node needs to be present");
+        let node_a = self.get(a).unwrap();
+        let node_b = self.get(b).unwrap();
 
-        if parent_node.parent == Some(inserting) {
-            return Err(Error::Cycle);
+        if node_a.parent.is_none() || node_a.parent != node_b.parent {
+            return Err(Error::NotSiblings(a, b));
+        }
+
+        // Adjacent nodes only need to move one node.
+        if node_a.next == Some(b) {
+            return self.insert_before(a, b);
         }
 
-        // 4. Make sure the node being inserted does not appear in the parent's child hierarchy
-        if self.dfs_descend(index).unwrap().any(|index| index == inserting) {
+        if node_b.next == Some(a) {
+            return self.insert_before(b, a);
+        }
+
+        let parent = node_a.parent.unwrap();
+        let a_next = node_a.next;
+
+        // Move a to where b is, and then move b to where a was.
+        self.insert_after(b, a)?;
+
+        match a_next {
+            Some(a_next) => self.insert_before(a_next, b),
+            None => {
+                self.detach(b)?;
+                self.add_child(parent, b)
+            }
+        }
+    }
+
+    fn is_present(&self, index: Index) -> Result<(), Error> {
+        if !self.contains_index(index) {
+            return Err(Error::NotPresent(index));
+        }
+
+        Ok(())
+    }
+
+    /// The node followed by the parent of the node, then the parent of the parent and so on.
+    fn ancestors(&self, index: Index) -> impl Iterator<Item = Index> + '_ {
+        std::iter::successors(Some(index), |index| self.get(*index).unwrap().parent)
+    }
+
+    /// Make sure a node placed next to the sibling would not become it's own ancestor.
+    fn check_for_sibling_cycles(&self, sibling: Index, inserting: Index) -> Result<(), Error> {
+        if self.ancestors(sibling).any(|index| index == inserting) {
             return Err(Error::Cycle);
         }
 
-        // 5. TODO: Make sure the node being inserted does not appear in the parent's parents and siblings.
+        Ok(())
+    }
+
+    /// Make sure the node being added as a child is not the parent or one of the parent's ancestors.
+    ///
+    /// The node is detached before being added, so the current parent and siblings of the node do not matter.
+    fn check_for_cycles(&self, index: Index, inserting: Index) -> Result<(), Error> {
+        if self.ancestors(index).any(|ancestor| ancestor == inserting) {
+            return Err(Error::Cycle);
+        }
 
         Ok(())
     }
@@ -427,6 +605,29 @@ impl<T> Clone for DfsDescend<'_, T> {
     }
 }
 
+/// A post-order depth first iterator over nodes in a [`Forest`].
+pub struct PostorderTraverse<'f, T>(PreorderTraverse<'f, T>);
+
+impl<T> Iterator for PostorderTraverse<'_, T> {
+    type Item = Index;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.find_map(|edge| {
+            match edge {
+                Edge::End(index) => Some(index),
+                // Continue pushing nodes onto the stack.
+                Edge::Start(_) => None,
+            }
+        })
+    }
+}
+
+impl<T> Clone for PostorderTraverse<'_, T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
 #[derive(Clone)]
 pub struct PreviousSiblings<'f, T> {
     forest: &'f Forest<T>,
@@ -508,6 +709,30 @@ mod tests {
         assert!(matches!(forest.add_child(b, a), Err(Error::Cycle)));
     }
 
+    #[test]
+    fn roots() {
+        let mut forest = Forest::new();
+        let a = forest.insert(());
+        let b = forest.insert(());
+        let c = forest.insert(());
+        // a -> b
+        forest.add_child(a, b).unwrap();
+
+        let mut roots = forest.roots().collect::<Vec<_>>();
+        roots.sort();
+        let mut expected = vec![a, c];
+        expected.sort();
+        assert_eq!(roots, expected);
+
+        // Removing the parent makes the child a root.
+        forest.remove(a).unwrap();
+        let mut roots = forest.roots().collect::<Vec<_>>();
+        roots.sort();
+        let mut expected = vec![b, c];
+        expected.sort();
+        assert_eq!(roots, expected);
+    }
+
     /// a -> b -> c
     #[test]
     fn preorder_traverse_line() {
@@ -583,4 +808,543 @@ mod tests {
         assert_eq!(children.next(), Some(c));
         assert_eq!(children.next(), None);
     }
+
+    /// Detaching the first child and adding it again makes the node the last child.
+    #[test]
+    fn detach_reattach() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+        let d = forest.insert(3);
+
+        //      a
+        //  /   |   \
+        // b <-> c <-> d
+        forest.add_child(a, b).unwrap();
+        forest.add_child(a, c).unwrap();
+        forest.add_child(a, d).unwrap();
+
+        forest.detach(b).unwrap();
+
+        // b should have no relationships after being detached.
+        let node_b = forest.get(b).unwrap();
+        assert_eq!(Node::parent(node_b), None);
+        assert_eq!(Node::prev_sibling(node_b), None);
+        assert_eq!(Node::next_sibling(node_b), None);
+
+        // c is now the first child.
+        let node_c = forest.get(c).unwrap();
+        assert_eq!(Node::prev_sibling(node_c), None);
+        assert_eq!(Node::first_child(forest.get(a).unwrap()), Some(c));
+
+        //      a
+        //  /   |   \
+        // c <-> d <-> b
+        forest.add_child(a, b).unwrap();
+
+        let node_b = forest.get(b).unwrap();
+        assert_eq!(Node::prev_sibling(node_b), Some(d));
+        assert_eq!(Node::next_sibling(node_b), None);
+        assert_eq!(Node::last_child(forest.get(a).unwrap()), Some(b));
+
+        let children = forest.children(a).collect::<Vec<_>>();
+        assert_eq!(children, [c, d, b]);
+    }
+
+    #[test]
+    fn insert_before() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+        let d = forest.insert(3);
+
+        //    a
+        //  /   \
+        // b <-> c
+        forest.add_child(a, b).unwrap();
+        forest.add_child(a, c).unwrap();
+
+        // Inserting before the first child makes the node the first child.
+        //
+        //        a
+        //    /   |   \
+        //   d <-> b <-> c
+        forest.insert_before(b, d).unwrap();
+
+        let node_d = forest.get(d).unwrap();
+        assert_eq!(Node::parent(node_d), Some(a));
+        assert_eq!(Node::prev_sibling(node_d), None);
+        assert_eq!(Node::next_sibling(node_d), Some(b));
+        assert_eq!(Node::first_child(forest.get(a).unwrap()), Some(d));
+        assert_eq!(Node::prev_sibling(forest.get(b).unwrap()), Some(d));
+
+        // Moving the last child between two children.
+        //
+        //        a
+        //    /   |   \
+        //   d <-> c <-> b
+        forest.insert_before(b, c).unwrap();
+
+        let node_a = forest.get(a).unwrap();
+        assert_eq!(Node::first_child(node_a), Some(d));
+        assert_eq!(Node::last_child(node_a), Some(b));
+        assert_eq!(Node::next_sibling(forest.get(b).unwrap()), None);
+
+        let children = forest.children(a).collect::<Vec<_>>();
+        assert_eq!(children, [d, c, b]);
+
+        let mut prev_siblings = forest.previous_siblings(b).unwrap();
+        assert_eq!(prev_siblings.next(), Some(b));
+        assert_eq!(prev_siblings.next(), Some(c));
+        assert_eq!(prev_siblings.next(), Some(d));
+        assert_eq!(prev_siblings.next(), None);
+    }
+
+    #[test]
+    fn insert_after() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+        let d = forest.insert(3);
+
+        //    a
+        //  /   \
+        // b <-> c
+        forest.add_child(a, b).unwrap();
+        forest.add_child(a, c).unwrap();
+
+        // Inserting after the last child makes the node the last child.
+        //
+        //        a
+        //    /   |   \
+        //   b <-> c <-> d
+        forest.insert_after(c, d).unwrap();
+
+        let node_d = forest.get(d).unwrap();
+        assert_eq!(Node::parent(node_d), Some(a));
+        assert_eq!(Node::prev_sibling(node_d), Some(c));
+        assert_eq!(Node::next_sibling(node_d), None);
+        assert_eq!(Node::last_child(forest.get(a).unwrap()), Some(d));
+
+        // Moving the first child between two children.
+        //
+        //        a
+        //    /   |   \
+        //   c <-> b <-> d
+        forest.insert_after(c, b).unwrap();
+
+        let node_a = forest.get(a).unwrap();
+        assert_eq!(Node::first_child(node_a), Some(c));
+        assert_eq!(Node::last_child(node_a), Some(d));
+        assert_eq!(Node::prev_sibling(forest.get(c).unwrap()), None);
+
+        let children = forest.children(a).collect::<Vec<_>>();
+        assert_eq!(children, [c, b, d]);
+
+        let mut next_siblings = forest.next_siblings(c).unwrap();
+        assert_eq!(next_siblings.next(), Some(c));
+        assert_eq!(next_siblings.next(), Some(b));
+        assert_eq!(next_siblings.next(), Some(d));
+        assert_eq!(next_siblings.next(), None);
+    }
+
+    /// Ensure a node cannot be placed next to one of it's descendants.
+    #[test]
+    fn insert_sibling_cycle() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+
+        // a -> b -> c
+        forest.add_child(a, b).unwrap();
+        forest.add_child(b, c).unwrap();
+
+        assert!(matches!(forest.insert_before(c, a), Err(Error::Cycle)));
+        assert!(matches!(forest.insert_after(c, b), Err(Error::Cycle)));
+        assert!(matches!(forest.insert_after(b, b), Err(Error::Cycle)));
+
+        // The forest is unchanged after a failed insertion.
+        assert_eq!(Node::parent(forest.get(c).unwrap()), Some(b));
+        assert_eq!(Node::parent(forest.get(b).unwrap()), Some(a));
+    }
+
+    /// Ensure a node cannot be placed next to a root, since roots have no siblings.
+    #[test]
+    fn insert_next_to_root() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+
+        // a -> b
+        forest.add_child(a, b).unwrap();
+
+        assert!(matches!(forest.insert_before(a, c), Err(Error::NoParent(_))));
+        assert!(matches!(forest.insert_after(a, b), Err(Error::NoParent(_))));
+
+        // The forest is unchanged after a failed insertion.
+        assert_eq!(Node::next_sibling(forest.get(a).unwrap()), None);
+        assert_eq!(Node::prev_sibling(forest.get(a).unwrap()), None);
+        assert_eq!(Node::parent(forest.get(b).unwrap()), Some(a));
+        assert_eq!(Node::parent(forest.get(c).unwrap()), None);
+    }
+
+    #[test]
+    fn swap_siblings() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+        let d = forest.insert(3);
+        let e = forest.insert(4);
+
+        //           a
+        //    /   /     \   \
+        //   b <-> c <-> d <-> e
+        forest.add_child(a, b).unwrap();
+        forest.add_child(a, c).unwrap();
+        forest.add_child(a, d).unwrap();
+        forest.add_child(a, e).unwrap();
+
+        // Adjacent siblings
+        forest.swap_siblings(b, c).unwrap();
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [c, b, d, e]);
+
+        forest.swap_siblings(e, d).unwrap();
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [c, b, e, d]);
+
+        // The first and last children
+        forest.swap_siblings(c, d).unwrap();
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [d, b, e, c]);
+
+        let node_a = forest.get(a).unwrap();
+        assert_eq!(Node::first_child(node_a), Some(d));
+        assert_eq!(Node::last_child(node_a), Some(c));
+
+        // Siblings separated by another node
+        forest.swap_siblings(b, c).unwrap();
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [d, c, e, b]);
+
+        let mut prev_siblings = forest.previous_siblings(b).unwrap();
+        assert_eq!(prev_siblings.next(), Some(b));
+        assert_eq!(prev_siblings.next(), Some(e));
+        assert_eq!(prev_siblings.next(), Some(c));
+        assert_eq!(prev_siblings.next(), Some(d));
+        assert_eq!(prev_siblings.next(), None);
+
+        // Nodes with different parents cannot be swapped.
+        let f = forest.insert(5);
+        assert!(matches!(forest.swap_siblings(b, f), Err(Error::NotSiblings(_, _))));
+        assert!(matches!(forest.swap_siblings(a, b), Err(Error::NotSiblings(_, _))));
+    }
+
+    /// Removing a node orphans the children of the node.
+    #[test]
+    fn remove_orphans_children() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+        let d = forest.insert(3);
+
+        // a -> b
+        //    /   \
+        //   c <-> d
+        forest.add_child(a, b).unwrap();
+        forest.add_child(b, c).unwrap();
+        forest.add_child(b, d).unwrap();
+
+        assert_eq!(forest.remove(b).unwrap(), 1);
+        assert!(!forest.contains_index(b));
+        assert_eq!(Node::first_child(forest.get(a).unwrap()), None);
+
+        for index in [c, d] {
+            let node = forest.get(index).unwrap();
+            assert_eq!(Node::parent(node), None);
+            assert_eq!(Node::prev_sibling(node), None);
+            assert_eq!(Node::next_sibling(node), None);
+        }
+
+        // The orphans may be added to another node.
+        forest.add_child(a, c).unwrap();
+        forest.add_child(a, d).unwrap();
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [c, d]);
+    }
+
+    #[test]
+    fn remove_subtree() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+        let d = forest.insert(3);
+        let e = forest.insert(4);
+
+        //       a
+        //    /     \
+        //   b <---> e
+        //  / \
+        // c <-> d
+        forest.add_child(a, b).unwrap();
+        forest.add_child(b, c).unwrap();
+        forest.add_child(b, d).unwrap();
+        forest.add_child(a, e).unwrap();
+
+        assert_eq!(forest.remove_subtree(b).unwrap(), [2, 3, 1]);
+
+        for index in [b, c, d] {
+            assert!(!forest.contains_index(index));
+        }
+
+        let node_a = forest.get(a).unwrap();
+        assert_eq!(Node::first_child(node_a), Some(e));
+        assert_eq!(Node::last_child(node_a), Some(e));
+        assert_eq!(Node::prev_sibling(forest.get(e).unwrap()), None);
+    }
+
+    #[test]
+    fn postorder_traverse() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+        let d = forest.insert(3);
+
+        //       a
+        //    /     \
+        //   b <---> d
+        //   |
+        //   c
+        forest.add_child(a, b).unwrap();
+        forest.add_child(b, c).unwrap();
+        forest.add_child(a, d).unwrap();
+
+        let mut iter = forest.postorder_traverse(a).unwrap();
+        assert_eq!(iter.next(), Some(c));
+        assert_eq!(iter.next(), Some(b));
+        assert_eq!(iter.next(), Some(d));
+        assert_eq!(iter.next(), Some(a));
+        assert_eq!(iter.next(), None);
+
+        // Traversing a subtree does not visit the siblings of the subtree.
+        let iter = forest.postorder_traverse(b).unwrap();
+        assert_eq!(iter.collect::<Vec<_>>(), [c, b]);
+    }
+
+    #[test]
+    fn reparent() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+        let d = forest.insert(3);
+
+        //    a
+        //  /   \
+        // b <-> c
+        //       |
+        //       d
+        forest.add_child(a, b).unwrap();
+        forest.add_child(a, c).unwrap();
+        forest.add_child(c, d).unwrap();
+
+        // A node cannot be moved into it's own subtree.
+        assert!(matches!(forest.reparent(c, Some(d)), Err(Error::Cycle)));
+        assert!(matches!(forest.reparent(a, Some(a)), Err(Error::Cycle)));
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [b, c]);
+        assert_eq!(Node::parent(forest.get(d).unwrap()), Some(c));
+
+        // Reparenting to the same parent moves the node to be the last child.
+        forest.reparent(b, Some(a)).unwrap();
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [c, b]);
+
+        //    a
+        //    |
+        //    c
+        //  /   \
+        // d <-> b
+        forest.reparent(b, Some(c)).unwrap();
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [c]);
+        assert_eq!(forest.children(c).collect::<Vec<_>>(), [d, b]);
+        assert_eq!(Node::parent(forest.get(b).unwrap()), Some(c));
+
+        // c and it's children become a separate tree.
+        forest.reparent(c, None).unwrap();
+        assert_eq!(Node::first_child(forest.get(a).unwrap()), None);
+        assert_eq!(Node::parent(forest.get(c).unwrap()), None);
+        assert_eq!(forest.dfs_descend(c).unwrap().collect::<Vec<_>>(), [c, d, b]);
+    }
+
+    /// Ensure a node attached elsewhere in the tree can only be added as a child if the node is not an ancestor.
+    #[test]
+    fn attached_cycle() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+        let d = forest.insert(3);
+
+        //    a
+        //  /   \
+        // b <-> c
+        //       |
+        //       d
+        forest.add_child(a, b).unwrap();
+        forest.add_child(a, c).unwrap();
+        forest.add_child(c, d).unwrap();
+
+        // The grandparent of d.
+        assert!(matches!(forest.add_child(d, a), Err(Error::Cycle)));
+
+        // The sibling of the parent of d is moved.
+        //
+        //    a
+        //    |
+        //    c
+        //    |
+        //    d
+        //    |
+        //    b
+        forest.add_child(d, b).unwrap();
+
+        let node_b = forest.get(b).unwrap();
+        assert_eq!(Node::parent(node_b), Some(d));
+        assert_eq!(Node::prev_sibling(node_b), None);
+        assert_eq!(Node::next_sibling(node_b), None);
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [c]);
+        assert_eq!(forest.children(d).collect::<Vec<_>>(), [b]);
+        assert_eq!(Node::prev_sibling(forest.get(c).unwrap()), None);
+    }
+
+    mod proptests {
+        use proptest::prelude::*;
+
+        use crate::forest::{Forest, Index, Node};
+
+        #[derive(Debug, Clone)]
+        enum Op {
+            Insert,
+            AddChild(usize, usize),
+            Detach(usize),
+            Remove(usize),
+            RemoveSubtree(usize),
+            Reparent(usize, Option<usize>),
+            InsertBefore(usize, usize),
+            InsertAfter(usize, usize),
+            SwapSiblings(usize, usize),
+        }
+
+        fn op() -> impl Strategy<Value = Op> {
+            prop_oneof![
+                Just(Op::Insert),
+                (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::AddChild(a, b)),
+                any::<usize>().prop_map(Op::Detach),
+                any::<usize>().prop_map(Op::Remove),
+                any::<usize>().prop_map(Op::RemoveSubtree),
+                (any::<usize>(), any::<Option<usize>>()).prop_map(|(a, b)| Op::Reparent(a, b)),
+                (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::InsertBefore(a, b)),
+                (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::InsertAfter(a, b)),
+                (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::SwapSiblings(a, b)),
+            ]
+        }
+
+        /// Check the links between every node in the forest are consistent.
+        fn check_links(forest: &Forest<()>) {
+            let len = forest.inner.len();
+
+            for (index, node) in forest.inner.iter() {
+                assert_eq!(Node::index(node), index);
+
+                // Siblings link to each other and share the same parent.
+                if let Some(next) = Node::next_sibling(node) {
+                    let next = forest.get(next).expect("next sibling is present");
+                    assert_eq!(Node::prev_sibling(next), Some(index));
+                    assert_eq!(Node::parent(next), Node::parent(node));
+                }
+
+                if let Some(prev) = Node::prev_sibling(node) {
+                    let prev = forest.get(prev).expect("previous sibling is present");
+                    assert_eq!(Node::next_sibling(prev), Some(index));
+                    assert_eq!(Node::parent(prev), Node::parent(node));
+                }
+
+                // The node is one of the children of the parent, while roots have no siblings.
+                match Node::parent(node) {
+                    Some(parent) => {
+                        assert!(forest.contains_index(parent));
+                        assert_eq!(forest.children(parent).filter(|child| *child == index).count(), 1);
+                    }
+
+                    None => {
+                        assert_eq!(Node::prev_sibling(node), None);
+                        assert_eq!(Node::next_sibling(node), None);
+                    }
+                }
+
+                // The children link to the node and the first and last child are the ends of the sibling list.
+                match node.first_last_child {
+                    Some((first, last)) => {
+                        assert_eq!(Node::prev_sibling(forest.get(first).unwrap()), None);
+                        assert_eq!(Node::next_sibling(forest.get(last).unwrap()), None);
+
+                        let children = forest.next_siblings(first).unwrap().collect::<Vec<_>>();
+                        assert!(children.len() <= len);
+                        assert_eq!(children.last(), Some(&last));
+
+                        for child in children {
+                            assert_eq!(Node::parent(forest.get(child).unwrap()), Some(index));
+                        }
+                    }
+
+                    None => assert_eq!(forest.children(index).count(), 0),
+                }
+
+                // The node is not it's own ancestor.
+                let ancestors =
+                    std::iter::successors(Node::parent(node), |index| Node::parent(forest.get(*index).unwrap()));
+                assert!(ancestors.take(len).all(|ancestor| ancestor != index));
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn links_stay_consistent(ops in prop::collection::vec(op(), 1..64)) {
+                let mut forest = Forest::new();
+                let mut indices = Vec::<Index>::new();
+
+                for _ in 0..4 {
+                    indices.push(forest.insert(()));
+                }
+
+                for op in ops {
+                    indices.retain(|index| forest.contains_index(*index));
+
+                    if indices.is_empty() || matches!(op, Op::Insert) {
+                        indices.push(forest.insert(()));
+                    }
+
+                    let pick = |n: usize| indices[n % indices.len()];
+
+                    // Errors are expected, the forest only needs to remain consistent.
+                    let _ = match op {
+                        Op::Insert => Ok(()),
+                        Op::AddChild(a, b) => forest.add_child(pick(a), pick(b)),
+                        Op::Detach(a) => forest.detach(pick(a)),
+                        Op::Remove(a) => forest.remove(pick(a)),
+                        Op::RemoveSubtree(a) => forest.remove_subtree(pick(a)).map(|_| ()),
+                        Op::Reparent(a, b) => forest.reparent(pick(a), b.map(pick)),
+                        Op::InsertBefore(a, b) => forest.insert_before(pick(a), pick(b)),
+                        Op::InsertAfter(a, b) => forest.insert_after(pick(a), pick(b)),
+                        Op::SwapSiblings(a, b) => forest.swap_siblings(pick(a), pick(b)),
+                    };
+
+                    check_links(&forest);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,297 @@
+//! Frame scheduling
+//!
+//! After a backend submits a frame for an output, the surfaces which are visible in the frame wait until the
+//! backend reports the frame was presented. The frame callbacks and presentation feedback of those surfaces are
+//! then sent using the time the backend presented the frame at.
+//!
+//! Surfaces on the output which are hidden, such as surfaces occluded by an opaque surface above, only receive
+//! frame callbacks once every [`HIDDEN_FRAME_INTERVAL`]. This keeps hidden clients from rendering at the refresh
+//! rate of the output without stalling clients which wait for a frame callback. If the output is idle, the output
+//! is rendered once the interval elapses so hidden surfaces are not left waiting. Toplevels which are hidden on
+//! every output are suspended.
+
+use std::time::Duration;
+
+use calloop::{
+    timer::{TimeoutAction, Timer},
+    RegistrationToken,
+};
+use smithay::{
+    backend::renderer::element::{Element, RenderElementStates},
+    output::Output,
+    reexports::wayland_protocols::wp::presentation_time::server::wp_presentation_feedback,
+    wayland::{
+        compositor::{self, SurfaceAttributes},
+        presentation::{PresentationFeedbackCachedState, PresentationFeedbackCallback},
+    },
+};
+use wayland_server::{protocol::wl_surface::WlSurface, Resource};
+
+use crate::{output::ConnectedOutput, scene::SceneGraphElement, shell::Shell, Aerugo};
+
+/// The interval at which hidden surfaces receive frame callbacks.
+pub const HIDDEN_FRAME_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Frame scheduling state of an output.
+#[derive(Debug, Default)]
+pub struct FrameScheduler {
+    /// Presentation feedback for the surfaces visible in the frame which is waiting to be presented.
+    pending_feedback: Vec<PresentationFeedbackCallback>,
+
+    /// Surfaces visible in the frame which is waiting to be presented, which receive frame callbacks once the
+    /// frame is presented.
+    pending_callbacks: Vec<WlSurface>,
+
+    /// The number of frames which have been presented.
+    sequence: u64,
+
+    /// Surfaces on the output which were not visible in the last frame.
+    hidden: Vec<WlSurface>,
+
+    /// The time hidden surfaces last received frame callbacks.
+    hidden_callbacks: Option<Duration>,
+
+    /// Timer which renders the output when hidden surfaces are due frame callbacks.
+    hidden_timer: Option<RegistrationToken>,
+}
+
+impl FrameScheduler {
+    /// Whether the surface is on the output but was not visible in the last frame.
+    pub fn is_hidden(&self, surface: &WlSurface) -> bool {
+        self.hidden.contains(surface)
+    }
+}
+
+impl Aerugo {
+    /// Notify the compositor that a frame was submitted to the output.
+    ///
+    /// The elements and render element states are the elements rendered and the states returned by the damage
+    /// tracker. Only surfaces which are visible in the frame receive frame callbacks and presentation feedback.
+    ///
+    /// Pending screen captures of the output and of toplevels in the frame are completed.
+    pub fn frame_submitted(&mut self, output: &Output, elements: &[SceneGraphElement], states: &RenderElementStates) {
+        let time = self.clock.now();
+        let Some(scheduler) = self.frame_scheduler_mut(output) else {
+            return;
+        };
+
+        // If the previous frame was never presented, the feedback for the frame will never be presented.
+        for feedback in scheduler.pending_feedback.drain(..) {
+            feedback.discarded();
+        }
+
+        for surface in visible_surfaces(elements, states) {
+            // Surfaces still waiting from the previous frame receive frame callbacks once this frame is presented.
+            if !scheduler.pending_callbacks.contains(surface) {
+                scheduler.pending_callbacks.push(surface.clone());
+            }
+
+            compositor::with_states(surface, |states| {
+                let mut feedback = states.cached_state.current::<PresentationFeedbackCachedState>();
+                scheduler.pending_feedback.extend(feedback.callbacks.drain(..));
+            });
+        }
+
+        self.update_hidden(output, elements, states, time.into());
+
+        // The output was damaged, so captures waiting for damage can be completed.
+        self.process_captures(output, elements);
+    }
+
+    /// Notify the compositor that nothing was damaged and no frame was submitted to the output.
+    ///
+    /// Visible surfaces still receive frame callbacks, since the surfaces may have committed without changing
+    /// their contents.
+    pub fn frame_skipped(&mut self, output: &Output, elements: &[SceneGraphElement], states: &RenderElementStates) {
+        let time = self.clock.now();
+
+        if self.frame_scheduler_mut(output).is_none() {
+            return;
+        }
+
+        for surface in visible_surfaces(elements, states) {
+            send_frame_callbacks(surface, time.into());
+
+            // The commit did not change what is presented, so the content was never presented.
+            discard_feedback(surface);
+        }
+
+        self.update_hidden(output, elements, states, time.into());
+    }
+
+    /// Notify the compositor that the last frame submitted to the output was presented.
+    ///
+    /// The time is the time the frame was presented in the monotonic clock. The flags must only describe how
+    /// the frame was presented if the backend knows so, such as [`Vsync`] if the backend presents at vblank.
+    ///
+    /// [`Vsync`]: wp_presentation_feedback::Kind::Vsync
+    pub fn frame_presented(&mut self, output: &Output, time: Duration, flags: wp_presentation_feedback::Kind) {
+        // The refresh rate of the mode is in millihertz.
+        let refresh = output
+            .current_mode()
+            .map(|mode| Duration::from_nanos(1_000_000_000_000 / mode.refresh.max(1) as u64))
+            .unwrap_or_default();
+
+        let Some(scheduler) = self.frame_scheduler_mut(output) else {
+            return;
+        };
+
+        scheduler.sequence += 1;
+        let sequence = scheduler.sequence;
+
+        for feedback in scheduler.pending_feedback.drain(..) {
+            feedback.presented(output, time, refresh, sequence, flags);
+        }
+
+        for surface in scheduler.pending_callbacks.drain(..) {
+            if surface.is_alive() {
+                send_frame_callbacks(&surface, time);
+            }
+        }
+    }
+
+    /// Notify the compositor that the last frame submitted to the output will never be presented.
+    ///
+    /// The surfaces in the frame still receive frame callbacks, so the clients draw another frame.
+    pub fn frame_discarded(&mut self, output: &Output) {
+        let time = self.clock.now();
+        let Some(scheduler) = self.frame_scheduler_mut(output) else {
+            return;
+        };
+
+        for feedback in scheduler.pending_feedback.drain(..) {
+            feedback.discarded();
+        }
+
+        for surface in scheduler.pending_callbacks.drain(..) {
+            if surface.is_alive() {
+                send_frame_callbacks(&surface, time.into());
+            }
+        }
+    }
+
+    /// Track the surfaces on the output which are hidden and send throttled frame callbacks to those surfaces.
+    fn update_hidden(
+        &mut self,
+        output: &Output,
+        elements: &[SceneGraphElement],
+        states: &RenderElementStates,
+        time: Duration,
+    ) {
+        // Surfaces which are occluded were culled, so those surfaces are not part of the elements.
+        let visible = visible_surfaces(elements, states).collect::<Vec<_>>();
+        let hidden = self
+            .scene
+            .surfaces_on_output(output)
+            .filter(|surface| !visible.contains(surface))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let Some(scheduler) = self.frame_scheduler_mut(output) else {
+            return;
+        };
+
+        let elapsed = scheduler
+            .hidden_callbacks
+            .map_or(true, |last| time.saturating_sub(last) >= HIDDEN_FRAME_INTERVAL);
+
+        if elapsed {
+            scheduler.hidden_callbacks = Some(time);
+
+            for surface in &hidden {
+                send_frame_callbacks(surface, time);
+
+                // Hidden surfaces are never presented.
+                discard_feedback(surface);
+            }
+        }
+
+        let changed =
+            scheduler.hidden.len() != hidden.len() || hidden.iter().any(|surface| !scheduler.hidden.contains(surface));
+        let arm_timer = !hidden.is_empty() && scheduler.hidden_timer.is_none();
+        let last = scheduler.hidden_callbacks.unwrap_or(time);
+        scheduler.hidden = hidden;
+
+        // An idle output does not render, so hidden surfaces would wait for the next frame without the timer.
+        if arm_timer {
+            let timeout = HIDDEN_FRAME_INTERVAL.saturating_sub(time.saturating_sub(last));
+            let timer_output = output.clone();
+            let token = self
+                .r#loop
+                .insert_source(Timer::from_duration(timeout), move |_, _, state| {
+                    if let Some(scheduler) = state.comp.frame_scheduler_mut(&timer_output) {
+                        scheduler.hidden_timer = None;
+                        state.comp.backend.schedule_render(&timer_output);
+                    }
+
+                    TimeoutAction::Drop
+                });
+
+            match token {
+                Ok(token) => {
+                    if let Some(scheduler) = self.frame_scheduler_mut(output) {
+                        scheduler.hidden_timer = Some(token);
+                    }
+                }
+                Err(err) => {
+                    let err = err.error;
+                    tracing::warn!(%err, output = %output.name(), "Failed to insert hidden frame timer");
+                }
+            }
+        }
+
+        if changed {
+            Shell::update_suspended(self);
+        }
+    }
+
+    fn frame_scheduler_mut(&mut self, output: &Output) -> Option<&mut FrameScheduler> {
+        self.outputs
+            .iter_mut()
+            .find(|connected| connected.output() == output)
+            .map(ConnectedOutput::frame_scheduler_mut)
+    }
+}
+
+/// The surfaces which are visible in the frame.
+fn visible_surfaces<'a>(
+    elements: &'a [SceneGraphElement],
+    states: &'a RenderElementStates,
+) -> impl Iterator<Item = &'a WlSurface> {
+    elements
+        .iter()
+        .filter(|element| {
+            states
+                .element_render_state(element.id())
+                .map_or(false, |state| state.visible_area > 0)
+        })
+        .filter_map(SceneGraphElement::surface)
+}
+
+/// Send the frame callbacks of a surface.
+///
+/// Subsurfaces are separate elements, so the frame callbacks of subsurfaces are only sent if the subsurface is
+/// visible.
+fn send_frame_callbacks(surface: &WlSurface, time: Duration) {
+    compositor::with_states(surface, |states| {
+        for callback in states
+            .cached_state
+            .current::<SurfaceAttributes>()
+            .frame_callbacks
+            .drain(..)
+        {
+            callback.done(time.as_millis() as u32);
+        }
+    });
+}
+
+/// Discard the presentation feedback of a surface whose content was not presented.
+fn discard_feedback(surface: &WlSurface) {
+    compositor::with_states(surface, |states| {
+        let mut feedback = states.cached_state.current::<PresentationFeedbackCachedState>();
+
+        for feedback in feedback.callbacks.drain(..) {
+            feedback.discarded();
+        }
+    });
+}
@@ -0,0 +1,178 @@
+//! Input handling
+//!
+//! Backends translate the input they receive into [`InputEvent`]s which are processed here. Input is forwarded
+//! to clients using the default seat.
+
+use smithay::{
+    backend::input::{
+        AbsolutePositionEvent, Axis, AxisSource, ButtonState, Event, InputBackend, InputEvent, KeyboardKeyEvent,
+        PointerAxisEvent, PointerButtonEvent, PointerMotionEvent,
+    },
+    input::{
+        keyboard::FilterResult,
+        pointer::{AxisFrame, ButtonEvent, MotionEvent},
+    },
+    output::Output,
+    utils::{Logical, Point, SERIAL_COUNTER},
+};
+use wayland_server::protocol::wl_surface::WlSurface;
+
+use crate::{output, Aerugo};
+
+/// The name of the default seat.
+pub const DEFAULT_SEAT_NAME: &str = "seat0";
+
+/// Keyboard repeat delay in milliseconds.
+pub const REPEAT_DELAY: i32 = 200;
+
+/// Keyboard repeat rate in characters per second.
+pub const REPEAT_RATE: i32 = 25;
+
+impl Aerugo {
+    /// Process an input event from the backend.
+    ///
+    /// The output is the output the input event originated from. Backends which have no notion of which output
+    /// the input came from should pass the first output.
+    pub fn process_input_event<B: InputBackend>(&mut self, output: &Output, event: InputEvent<B>) {
+        match event {
+            InputEvent::Keyboard { event } => {
+                let Some(keyboard) = self.seat.get_keyboard() else {
+                    return;
+                };
+
+                let serial = SERIAL_COUNTER.next_serial();
+                let time = Event::time_msec(&event);
+
+                // TODO: Compositor keybindings
+                keyboard.input::<(), _>(self, event.key_code(), event.state(), serial, time, |_, _, _| {
+                    FilterResult::Forward
+                });
+            }
+
+            InputEvent::PointerMotion { event } => {
+                let location = self.pointer_location + event.delta();
+                self.pointer_motion(output, location, Event::time_msec(&event));
+            }
+
+            InputEvent::PointerMotionAbsolute { event } => {
+                let geometry = output::geometry(output);
+                let location = geometry.loc.to_f64() + event.position_transformed(geometry.size);
+                self.pointer_motion(output, location, Event::time_msec(&event));
+            }
+
+            InputEvent::PointerButton { event } => {
+                let Some(pointer) = self.seat.get_pointer() else {
+                    return;
+                };
+
+                let serial = SERIAL_COUNTER.next_serial();
+
+                // Keyboard focus follows the surface which was clicked.
+                if event.state() == ButtonState::Pressed {
+                    if let Some(keyboard) = self.seat.get_keyboard() {
+                        keyboard.set_focus(self, pointer.current_focus(), serial);
+                    }
+                }
+
+                pointer.button(
+                    self,
+                    &ButtonEvent {
+                        button: event.button_code(),
+                        state: event.state(),
+                        serial,
+                        time: Event::time_msec(&event),
+                    },
+                );
+                pointer.frame(self);
+            }
+
+            InputEvent::PointerAxis { event } => {
+                let Some(pointer) = self.seat.get_pointer() else {
+                    return;
+                };
+
+                let mut frame = AxisFrame::new(Event::time_msec(&event)).source(event.source());
+
+                for axis in [Axis::Horizontal, Axis::Vertical] {
+                    let discrete = event.amount_discrete(axis);
+                    // Some devices only report discrete scrolling, so convert the steps into a distance.
+                    let amount = event.amount(axis).or(discrete.map(|steps| steps * 15.0));
+
+                    if let Some(amount) = amount {
+                        frame = frame.value(axis, amount);
+                    }
+
+                    if let Some(discrete) = discrete {
+                        frame = frame.discrete(axis, discrete as i32);
+                    }
+
+                    // Kinetic scrolling stops once the finger is lifted.
+                    if event.source() == AxisSource::Finger && amount == Some(0.0) {
+                        frame = frame.stop(axis);
+                    }
+                }
+
+                pointer.axis(self, frame);
+                pointer.frame(self);
+            }
+
+            // TODO: Touch, tablet and gesture input.
+            _ => {}
+        }
+    }
+
+    /// Move the pointer to the specified location in the global space.
+    ///
+    /// The pointer may move onto any output in the layout. If the location is outside of every output, the
+    /// location is clamped to the output the motion originated from.
+    fn pointer_motion(&mut self, output: &Output, location: Point<f64, Logical>, time: u32) {
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+
+        let geometry = self
+            .outputs()
+            .map(output::geometry)
+            .find(|geometry| geometry.to_f64().contains(location))
+            .unwrap_or_else(|| output::geometry(output));
+        let location = Point::from((
+            location.x.clamp(
+                geometry.loc.x as f64,
+                (geometry.loc.x + geometry.size.w - 1).max(0) as f64,
+            ),
+            location.y.clamp(
+                geometry.loc.y as f64,
+                (geometry.loc.y + geometry.size.h - 1).max(0) as f64,
+            ),
+        ));
+        self.pointer_location = location;
+
+        let focus = self.surface_under(location);
+        pointer.motion(
+            self,
+            focus,
+            &MotionEvent {
+                location,
+                serial: SERIAL_COUNTER.next_serial(),
+                time,
+            },
+        );
+        pointer.frame(self);
+    }
+
+    /// Find the surface under the specified location and the location of the surface in the global space.
+    fn surface_under(&self, location: Point<f64, Logical>) -> Option<(WlSurface, Point<i32, Logical>)> {
+        let output = self
+            .outputs()
+            .find(|output| output::geometry(output).to_f64().contains(location))?;
+        let origin = output::geometry(output).loc;
+        let hit = self
+            .scene
+            .get_graph(output)?
+            .surface_under(location - origin.to_f64())?;
+
+        // Smithay computes the surface-local location from the surface location, which is only correct for surfaces
+        // under a scaled branch if the location is derived from the surface-local location of the hit.
+        Some((hit.surface, (location - hit.location).to_i32_round()))
+    }
+}
@@ -11,94 +11,220 @@ use std::{
 
 use calloop::{channel::SyncSender, generic::Generic, EventLoop, Interest, LoopHandle, LoopSignal, Mode, PostAction};
 
-use backend::Backend;
-use smithay::wayland::{compositor::CompositorClientState, socket::ListeningSocketSource};
-use wayland_server::{Display, DisplayHandle};
+use backend::{headless::VirtualOutput, Backend, BackendSelection, RendererSelection, SelectionError};
+use smithay::wayland::{
+    compositor::CompositorClientState,
+    socket::{BindError, ListeningSocketSource},
+};
+use wayland_server::{backend::InitError, Display, DisplayHandle};
+use wm_runtime::{RuntimeMessage, SceneDumpFormat, WmHandle, WmRequest, WmRuntime};
 
 pub mod backend;
+mod capture;
 pub mod forest;
+mod frame;
+mod input;
+mod output;
 mod scene;
 mod shell;
+mod snapshot;
 mod state;
+#[cfg(test)]
+mod testing;
 mod transaction;
 mod wayland;
 
 pub use state::Aerugo;
 
-use crate::state::{ClientData, PrivilegedGlobals};
+use crate::{
+    scene::DumpFormat,
+    shell::Shell,
+    state::{ClientData, PrivilegedGlobals},
+};
 
 type BackendConstructor = Box<
-    dyn FnOnce(LoopHandle<'static, Loop>, DisplayHandle) -> Result<Box<dyn Backend>, Box<dyn Error>> + Send + 'static,
+    dyn FnOnce(LoopHandle<'static, Loop>, DisplayHandle) -> Result<Box<dyn Backend>, StartupError> + Send + 'static,
 >;
 
+/// An error which occurs while starting the server.
+#[derive(Debug, thiserror::Error)]
+pub enum StartupError {
+    /// The event loop could not be created.
+    #[error("failed to create the event loop")]
+    EventLoop(#[source] calloop::Error),
+
+    /// The event loop thread could not be spawned.
+    #[error("failed to spawn the event loop thread")]
+    Thread(#[source] io::Error),
+
+    /// The event loop thread exited before the server started.
+    #[error("the event loop thread exited before the server started")]
+    ThreadExited,
+
+    /// The Wayland display could not be created.
+    #[error("failed to create the Wayland display")]
+    Display(#[source] InitError),
+
+    /// The Wayland socket clients connect to could not be bound.
+    #[error("failed to bind the Wayland socket")]
+    Socket(#[source] BindError),
+
+    /// No backend could be selected for the configuration and environment.
+    #[error(transparent)]
+    BackendSelection(#[from] SelectionError),
+
+    /// The backend failed to initialize.
+    #[error("failed to initialize the backend")]
+    Backend(#[source] Box<dyn Error + Send + Sync>),
+
+    /// The backend failed to initialize the renderer.
+    ///
+    /// This usually indicates there is no usable GPU.
+    #[error("failed to initialize the renderer")]
+    Renderer(#[source] Box<dyn Error + Send + Sync>),
+
+    /// The keymap of the default seat could not be loaded.
+    #[error("failed to load the keymap")]
+    Keymap(#[source] smithay::input::keyboard::Error),
+
+    /// The window manager could not be loaded.
+    #[error("failed to load the window manager")]
+    Wm(#[source] Box<dyn Error + Send + Sync>),
+}
+
 /// Configuration used to create a server instance.
 pub struct Configuration {
-    backend_constructor: BackendConstructor,
+    backend: BackendSelection,
+    renderer: RendererSelection,
+    headless_outputs: Vec<VirtualOutput>,
+    backend_constructor: Option<BackendConstructor>,
+    wm: Option<Vec<u8>>,
 }
 
 impl Configuration {
-    pub fn new<B>(b: B) -> Self
-    where
-        B: FnOnce(LoopHandle<'static, Loop>, DisplayHandle) -> Result<Box<dyn Backend>, Box<dyn Error>>
-            + Send
-            + 'static,
-    {
+    /// Create a configuration which selects the backend and renderer depending on the environment.
+    pub fn new() -> Self {
         Self {
-            backend_constructor: Box::new(b),
+            backend: BackendSelection::Auto,
+            renderer: RendererSelection::Default,
+            headless_outputs: vec![VirtualOutput::default()],
+            backend_constructor: None,
+            wm: None,
         }
     }
 
+    /// Set the backend to create.
+    ///
+    /// By default the backend is selected depending on the environment.
+    pub fn backend(mut self, backend: BackendSelection) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Set the renderer the backend should use.
+    ///
+    /// By default the most optimal renderer the backend supports is used.
+    pub fn renderer(mut self, renderer: RendererSelection) -> Self {
+        self.renderer = renderer;
+        self
+    }
+
+    /// Set the virtual outputs created by the headless backend.
+    ///
+    /// Outputs are created in the specified order. By default a single 1920x1080 output refreshing at 60 Hz is
+    /// created.
+    pub fn headless_outputs(mut self, outputs: impl IntoIterator<Item = VirtualOutput>) -> Self {
+        self.headless_outputs = outputs.into_iter().collect();
+        self
+    }
+
+    /// Use a custom function to create the backend.
+    ///
+    /// This overrides the backend and renderer selection.
+    pub fn backend_constructor<B>(mut self, b: B) -> Self
+    where
+        B: FnOnce(LoopHandle<'static, Loop>, DisplayHandle) -> Result<Box<dyn Backend>, StartupError> + Send + 'static,
+    {
+        self.backend_constructor = Some(Box::new(b));
+        self
+    }
+
+    /// Set the window manager to load.
+    ///
+    /// The bytes are the contents of the window manager's wasm component.
+    pub fn wm(mut self, bytes: impl Into<Vec<u8>>) -> Self {
+        self.wm = Some(bytes.into());
+        self
+    }
+
     // TODO: Socket creation here
 
     /// Creates a server using the configuration.
     ///
     /// This will start the server event loop and return a handle that may be used to stop the server and check
-    /// on the
-    pub fn create_server(self) -> io::Result<AerugoExecutor> {
-        // TODO: io Error is wrong error type
-
+    /// on the server. Any error which occurs while the server is starting is returned.
+    pub fn create_server(self) -> Result<AerugoExecutor, StartupError> {
         // In calloop EventLoop is !Send and !Sync, so we need to send the loop signal from the event loop
         // thread to the caller. We do this with a rendezvous channel (which is why the bound is 0).
         let (send, recv) = mpsc::sync_channel(0);
 
-        let thread = thread::Builder::new().name("Aerugo event loop".into()).spawn(move || {
-            // TODO: Proper typedef
-            let mut r#loop = EventLoop::try_new().expect("Failed to create event loop");
-
-            let signal = r#loop.get_signal();
-            let (send_server, recv_server) = calloop::channel::sync_channel::<ExecutorMessage>(5);
-            send.send((signal, send_server)).expect("Executor thread died");
+        let (backend, renderer, headless_outputs) = (self.backend, self.renderer, self.headless_outputs);
+        let backend_constructor = self.backend_constructor.unwrap_or_else(|| {
+            Box::new(move |r#loop, display| {
+                backend::create_backend(backend, renderer, headless_outputs, r#loop, display)
+            })
+        });
+        let wm = self.wm;
+        let (channel, recv_server) = calloop::channel::sync_channel::<ExecutorMessage>(5);
+
+        let thread = thread::Builder::new()
+            .name("Aerugo event loop".into())
+            .spawn(move || {
+                let (mut r#loop, mut aerugo) = match start(backend_constructor, wm) {
+                    Ok(started) => started,
+                    Err(err) => {
+                        // The caller is waiting for the server to start, so tell the caller why startup failed.
+                        let _ = send.send(Err(err));
+                        return;
+                    }
+                };
+
+                {
+                    let r#loop = r#loop.handle();
+                    r#loop
+                        .insert_source(recv_server, |msg, _, _state| {
+                            if let calloop::channel::Event::Msg(_msg) = msg {
+                                todo!("Handle executor messages")
+                            }
+                        })
+                        .unwrap();
+                }
 
-            let mut aerugo = Loop::new(&r#loop, self.backend_constructor).expect("TODO: Error type");
+                if send.send(Ok(aerugo.signal.clone())).is_err() {
+                    // The caller is gone, so there is nothing to stop the server.
+                    return;
+                }
 
-            {
-                let r#loop = r#loop.handle();
                 r#loop
-                    .insert_source(recv_server, |msg, _, _state| {
-                        if let calloop::channel::Event::Msg(_msg) = msg {
-                            todo!("Handle executor messages")
-                        }
+                    .run(None, &mut aerugo, |state| {
+                        // Send enter and leave events for any surfaces which changed outputs.
+                        state.comp.scene.update_surface_outputs();
+                        // Flush any pending messages to ensure clients can respond to server events.
+                        state.flush_display();
+                        // Check the backend has met any internal shutdown conditions.
+                        state.check_shutdown();
                     })
                     .unwrap();
-            }
 
-            r#loop
-                .run(None, &mut aerugo, |state| {
-                    // Flush any pending messages to ensure clients can respond to server events.
-                    state.flush_display();
-                    // Check the backend has met any internal shutdown conditions.
-                    state.check_shutdown();
-                })
-                .unwrap();
-
-            tracing::info!("Server shutting down");
-        })?;
+                tracing::info!("Server shutting down");
+            })
+            .map_err(StartupError::Thread)?;
 
-        // Get the signal from the oneshot channel so the executor can stop the server.
+        // Get the signal from the rendezvous channel so the executor can stop the server.
         //
-        // There is no need to use try_recv since the event loop is either successfully created or the
-        // thread fails to initialize the event loop
-        let (signal, channel) = recv.recv().expect("TODO: Add error variant");
+        // There is no need to use try_recv since the event loop thread always sends the result of startup
+        // unless the thread panicked.
+        let signal = recv.recv().map_err(|_| StartupError::ThreadExited)??;
 
         Ok(AerugoExecutor {
             thread,
@@ -108,6 +234,19 @@ impl Configuration {
     }
 }
 
+/// Create the event loop and server state on the event loop thread.
+fn start(backend: BackendConstructor, wm: Option<Vec<u8>>) -> Result<(EventLoop<'static, Loop>, Loop), StartupError> {
+    let r#loop = EventLoop::try_new().map_err(StartupError::EventLoop)?;
+    let aerugo = Loop::new(&r#loop, backend, wm)?;
+    Ok((r#loop, aerugo))
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// A handle to an instance of the display server.
 ///
 /// Messages may be sent to the display server's event loop using this type.
@@ -161,23 +300,31 @@ pub struct Loop {
 }
 
 impl Loop {
-    pub fn new(r#loop: &EventLoop<'static, Self>, backend: BackendConstructor) -> Result<Self, ()> {
-        let display = Display::new().expect("Failed to initialize Wayland display");
+    pub fn new(
+        r#loop: &EventLoop<'static, Self>,
+        backend: BackendConstructor,
+        wm: Option<Vec<u8>>,
+    ) -> Result<Self, StartupError> {
+        let display = Display::new().map_err(StartupError::Display)?;
         let signal = r#loop.get_signal();
         let r#loop = r#loop.handle();
 
         let display_handle = display.handle();
 
         // Register the display to the event loop to allow client requests to be processed.
-        register_display_source(display, &r#loop);
+        register_display_source(display, &r#loop)?;
 
         let display = display_handle;
 
         // Register the listening socket so clients can connect
-        register_listening_socket(&r#loop);
+        register_listening_socket(&r#loop)?;
+
+        let backend = backend(r#loop.clone(), display.clone())?;
+        let mut comp = Aerugo::new(&r#loop, display.clone(), backend)?;
 
-        let backend = backend(r#loop.clone(), display.clone()).expect("TODO: Error type");
-        let comp = Aerugo::new(&r#loop, display.clone(), backend);
+        if let Some(wm) = wm {
+            comp.wm = Some(register_wm(&wm, &r#loop)?);
+        }
 
         Ok(Self {
             r#loop,
@@ -207,7 +354,7 @@ impl Loop {
     }
 }
 
-fn register_display_source(display: Display<Aerugo>, r#loop: &LoopHandle<'static, Loop>) {
+fn register_display_source(display: Display<Aerugo>, r#loop: &LoopHandle<'static, Loop>) -> Result<(), StartupError> {
     r#loop
         .insert_source(
             Generic::new(display, Interest::READ, Mode::Level),
@@ -220,11 +367,13 @@ fn register_display_source(display: Display<Aerugo>, r#loop: &LoopHandle<'static
                 Ok(PostAction::Continue)
             },
         )
-        .unwrap();
+        .map_err(|err| StartupError::EventLoop(err.error))?;
+
+    Ok(())
 }
 
-fn register_listening_socket(r#loop: &LoopHandle<'static, Loop>) {
-    let listening_socket = ListeningSocketSource::new_auto().expect("Failed to bind a socket");
+fn register_listening_socket(r#loop: &LoopHandle<'static, Loop>) -> Result<(), StartupError> {
+    let listening_socket = ListeningSocketSource::new_auto().map_err(StartupError::Socket)?;
 
     let socket = listening_socket.socket_name().to_owned();
     tracing::info!("Bound Wayland socket: {:?}", socket);
@@ -246,5 +395,47 @@ fn register_listening_socket(r#loop: &LoopHandle<'static, Loop>) {
                 tracing::error!(%err, "Failed to register client with fd: {info}");
             }
         })
-        .unwrap();
+        .map_err(|err| StartupError::EventLoop(err.error))?;
+
+    Ok(())
+}
+
+fn register_wm(bytes: &[u8], r#loop: &LoopHandle<'static, Loop>) -> Result<WmHandle, StartupError> {
+    let runtime = WmRuntime::new(bytes).map_err(|err| StartupError::Wm(err.into()))?;
+    let handle = runtime.handle();
+
+    r#loop
+        .insert_source(runtime, |msg, _, state| match msg {
+            RuntimeMessage::Request(WmRequest::DumpScene(format)) => {
+                let format = match format {
+                    SceneDumpFormat::Text => DumpFormat::Text,
+                    SceneDumpFormat::Json => DumpFormat::Json,
+                    SceneDumpFormat::Dot => DumpFormat::Dot,
+                };
+
+                tracing::info!("Scene graph:\n{}", state.comp.scene.dump(format));
+            }
+
+            RuntimeMessage::Request(WmRequest::SnapshotDrop(id)) => {
+                state.comp.drop_snapshot(id);
+            }
+
+            RuntimeMessage::Request(WmRequest::ConfigureToplevel { serial, configure }) => {
+                Shell::wm_configure(&mut state.comp, serial, configure);
+            }
+
+            RuntimeMessage::Request(request) => {
+                // TODO: Handle wm requests
+                tracing::debug!(?request, "WM request");
+            }
+
+            RuntimeMessage::Closed => {
+                tracing::warn!("WM runtime closed");
+                // Nothing is left to drop the snapshots given to the WM.
+                state.comp.drop_snapshots();
+            }
+        })
+        .map_err(|err| StartupError::EventLoop(err.error))?;
+
+    Ok(handle)
 }
@@ -1,6 +1,6 @@
 use std::panic;
 
-use aerugo_comp::{backend, Configuration};
+use aerugo_comp::Configuration;
 use clap::Parser;
 use tracing::metadata::LevelFilter;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
@@ -8,7 +8,7 @@ use tracing_subscriber::{EnvFilter, FmtSubscriber};
 mod cli;
 
 fn main() {
-    let _args = cli::AerugoArgs::parse();
+    let args = cli::AerugoArgs::parse();
     let env_filter = EnvFilter::builder()
         .with_default_directive(LevelFilter::DEBUG.into())
         .from_env()
@@ -17,7 +17,14 @@ fn main() {
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let configuration = Configuration::new(backend::default_backend);
+    let mut configuration = Configuration::new()
+        .backend(args.backend.into())
+        .renderer(args.renderer.into());
+
+    if !args.headless_outputs.is_empty() {
+        configuration = configuration.headless_outputs(args.headless_outputs);
+    }
+
     let executor = configuration.create_server().expect("Failed to create server");
 
     if let Err(err) = executor.join() {
@@ -0,0 +1,523 @@
+//! Output management
+//!
+//! Backends announce outputs to the compositor as the outputs are connected and disconnected. Enabling an output
+//! advertises a `wl_output` global to clients and creates the output's node in the scene.
+//!
+//! Outputs are placed in a global space shared by all outputs. Unless an output was given a location by an output
+//! configuration, outputs are arranged from left to right in the order the outputs were connected with the top
+//! edges aligned. Clients learn the layout through `xdg-output` and the WM through the output's geometry.
+//!
+//! Until the WM is able to place toplevels, every enabled output presents a branch in the scene which toplevels
+//! are stacked in when mapped.
+//!
+//! Every change to the mode, location, scale, transform or enabled state of an output goes through
+//! [`Aerugo::configure_outputs`], which notifies the WM and output management clients of the change. Modes changed
+//! by the backend go through [`Aerugo::output_mode_changed`] instead.
+
+use std::{error::Error, time::Duration};
+
+use calloop::timer::{TimeoutAction, Timer};
+use smithay::{
+    output::{Mode, Output, Scale},
+    utils::{Logical, Physical, Point, Rectangle, Size, Transform},
+};
+use wayland_server::backend::GlobalId;
+use wm_runtime::{Geometry, Id, IdType, OutputInfo, WmEvent};
+
+use crate::{
+    capture::CaptureSource,
+    frame::FrameScheduler,
+    scene::{BranchIndex, NodeIndex},
+    wayland::wlr::output_management,
+    Aerugo,
+};
+
+/// How long the `wl_output` global of a disabled output is kept before the global is removed.
+const GLOBAL_REMOVAL_DELAY: Duration = Duration::from_secs(5);
+
+/// An output which has been added to the compositor.
+#[derive(Debug)]
+pub struct ConnectedOutput {
+    output: Output,
+    /// The `wl_output` global, [`None`] if the output is disabled.
+    global: Option<GlobalId>,
+    frame_scheduler: FrameScheduler,
+    /// The id of the output in the WM, if a WM is running and the output is enabled.
+    wm_id: Option<Id>,
+    /// Whether the location of the output was set by an output configuration.
+    location_configured: bool,
+    /// The branch toplevels on the output are placed in, [`None`] if the output is disabled.
+    layout: Option<BranchIndex>,
+}
+
+impl ConnectedOutput {
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    pub fn frame_scheduler(&self) -> &FrameScheduler {
+        &self.frame_scheduler
+    }
+
+    pub fn frame_scheduler_mut(&mut self) -> &mut FrameScheduler {
+        &mut self.frame_scheduler
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.global.is_some()
+    }
+
+    pub fn layout(&self) -> Option<BranchIndex> {
+        self.layout
+    }
+}
+
+/// A change to the state of outputs.
+///
+/// Outputs which are not part of the configuration are left unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct OutputConfiguration {
+    pub outputs: Vec<(Output, OutputChange)>,
+}
+
+/// A change to the state of a single output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputChange {
+    /// Disable the output.
+    Disable,
+
+    /// Enable the output, changing any state which is set.
+    Enable {
+        mode: Option<Mode>,
+        location: Option<Point<i32, Logical>>,
+        transform: Option<Transform>,
+        scale: Option<f64>,
+    },
+}
+
+/// An error which occurs when an output configuration is invalid or could not be applied.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigurationError {
+    /// The output is not connected.
+    #[error("output {0} is not connected")]
+    UnknownOutput(String),
+
+    /// The output was configured more than once.
+    #[error("output {0} was configured more than once")]
+    DuplicateOutput(String),
+
+    /// The output does not advertise the mode.
+    #[error("output {output} does not support the mode {mode:?}")]
+    UnsupportedMode { output: String, mode: Mode },
+
+    /// The scale is not positive.
+    #[error("invalid scale {0}")]
+    InvalidScale(f64),
+
+    /// The backend failed to change the mode of the output.
+    #[error("failed to set the mode of output {output}")]
+    Backend {
+        output: String,
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+}
+
+impl Aerugo {
+    /// Add an output announced by the backend.
+    ///
+    /// The output is enabled.
+    pub fn add_output(&mut self, output: Output) {
+        if self.outputs.iter().any(|connected| connected.output == output) {
+            tracing::warn!(output = %output.name(), "Output was added twice");
+            return;
+        }
+
+        tracing::info!(
+            output = %output.name(),
+            mode = ?output.current_mode(),
+            "Output connected"
+        );
+
+        self.outputs.push(ConnectedOutput {
+            output: output.clone(),
+            global: None,
+            frame_scheduler: FrameScheduler::default(),
+            wm_id: None,
+            location_configured: false,
+            layout: None,
+        });
+
+        self.enable_output(&output);
+        self.arrange_outputs();
+        output_management::outputs_changed(self);
+    }
+
+    /// Remove an output which the backend has disconnected.
+    pub fn remove_output(&mut self, output: &Output) {
+        if !self.outputs.iter().any(|connected| &connected.output == output) {
+            return;
+        }
+
+        tracing::info!(output = %output.name(), "Output disconnected");
+
+        self.disable_output(output);
+        self.outputs.retain(|connected| &connected.output != output);
+        self.arrange_outputs();
+        output_management::outputs_changed(self);
+    }
+
+    /// Change the state of outputs.
+    ///
+    /// The whole configuration is validated before any output is changed. If `test_only` is set, the
+    /// configuration is only validated.
+    pub fn configure_outputs(
+        &mut self,
+        configuration: &OutputConfiguration,
+        test_only: bool,
+    ) -> Result<(), ConfigurationError> {
+        for (index, (output, change)) in configuration.outputs.iter().enumerate() {
+            if !self.outputs.iter().any(|connected| &connected.output == output) {
+                return Err(ConfigurationError::UnknownOutput(output.name()));
+            }
+
+            if configuration.outputs[..index].iter().any(|(other, _)| other == output) {
+                return Err(ConfigurationError::DuplicateOutput(output.name()));
+            }
+
+            let OutputChange::Enable { mode, scale, .. } = change else {
+                continue;
+            };
+
+            if let Some(mode) = mode {
+                // A refresh rate of zero matches any refresh rate.
+                if !output
+                    .modes()
+                    .iter()
+                    .any(|supported| supported.size == mode.size && (mode.refresh == 0 || supported == mode))
+                {
+                    return Err(ConfigurationError::UnsupportedMode {
+                        output: output.name(),
+                        mode: *mode,
+                    });
+                }
+            }
+
+            if let Some(scale) = scale {
+                if !scale.is_finite() || *scale <= 0.0 {
+                    return Err(ConfigurationError::InvalidScale(*scale));
+                }
+            }
+        }
+
+        if test_only {
+            return Ok(());
+        }
+
+        self.apply_configuration(configuration)?;
+        self.arrange_outputs();
+        output_management::outputs_changed(self);
+
+        Ok(())
+    }
+
+    /// Change the mode of an output after the backend changed the mode of the output, such as when the window
+    /// presenting the output was resized.
+    ///
+    /// The mode replaces the previous mode, which the output no longer supports.
+    pub fn output_mode_changed(&mut self, output: &Output, mode: Mode) {
+        if let Some(previous) = output.current_mode().filter(|&previous| previous != mode) {
+            output.delete_mode(previous);
+        }
+
+        output.change_current_state(Some(mode), None, None, None);
+        output.set_preferred(mode);
+
+        self.output_updated(output);
+        // The size of the output changed, so outputs to the right may need to move.
+        self.arrange_outputs();
+        output_management::outputs_changed(self);
+    }
+
+    /// Apply a validated configuration.
+    ///
+    /// The modes are set first, since only the backend may fail. If the backend fails to set a mode, the modes
+    /// which were already set are restored and no output is changed.
+    fn apply_configuration(&mut self, configuration: &OutputConfiguration) -> Result<(), ConfigurationError> {
+        let modes = configuration
+            .outputs
+            .iter()
+            .filter_map(|(output, change)| match change {
+                OutputChange::Enable { mode: Some(mode), .. } => {
+                    let mode = output
+                        .modes()
+                        .into_iter()
+                        .find(|supported| supported.size == mode.size && (mode.refresh == 0 || supported == mode))
+                        .unwrap_or(*mode);
+                    Some((output, mode))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        for (index, &(output, mode)) in modes.iter().enumerate() {
+            if Some(mode) == output.current_mode() {
+                continue;
+            }
+
+            if let Err(source) = self.backend.set_mode(output, mode) {
+                for &(output, _) in &modes[..index] {
+                    if let Some(previous) = output.current_mode() {
+                        if let Err(err) = self.backend.set_mode(output, previous) {
+                            tracing::error!(output = %output.name(), %err, "Failed to restore the mode of output");
+                        }
+                    }
+                }
+
+                return Err(ConfigurationError::Backend {
+                    output: output.name(),
+                    source,
+                });
+            }
+        }
+
+        for (output, change) in &configuration.outputs {
+            let OutputChange::Enable {
+                location,
+                transform,
+                scale,
+                ..
+            } = *change
+            else {
+                self.disable_output(output);
+                continue;
+            };
+
+            let mode = modes
+                .iter()
+                .find(|(configured, _)| *configured == output)
+                .map(|&(_, mode)| mode);
+
+            output.change_current_state(mode, transform, scale.map(Scale::Fractional), location);
+
+            let connected = self
+                .outputs
+                .iter_mut()
+                .find(|connected| &connected.output == output)
+                .unwrap();
+            connected.location_configured |= location.is_some();
+            let enabled = connected.enabled();
+
+            if enabled {
+                self.output_updated(output);
+            } else {
+                self.enable_output(output);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Advertise the output to clients and the WM and add the output to the scene.
+    ///
+    /// Unless the output was given a location, the output is placed to the right of every enabled output.
+    fn enable_output(&mut self, output: &Output) {
+        let x = self
+            .outputs()
+            .map(|enabled| {
+                let geometry = geometry(enabled);
+                geometry.loc.x + geometry.size.w
+            })
+            .max()
+            .unwrap_or(0);
+
+        let Some(connected) = self.outputs.iter_mut().find(|connected| &connected.output == output) else {
+            return;
+        };
+
+        if connected.enabled() {
+            return;
+        }
+
+        if !connected.location_configured {
+            output.change_current_state(None, None, None, Some((x, 0).into()));
+        }
+
+        connected.global = Some(output.create_global::<Self>(&self.display));
+        connected.wm_id = self.wm.as_ref().map(|wm| {
+            let id = wm.alloc_id(IdType::Output);
+            wm.send(WmEvent::NewOutput {
+                output: id,
+                info: output_info(output),
+            });
+            id
+        });
+
+        let layout = self.scene.create_branch();
+        connected.layout = Some(layout);
+
+        self.scene.create_output(output.clone());
+        self.scene.set_output_node(output, NodeIndex::Branch(layout));
+        self.backend.schedule_render(output);
+    }
+
+    /// Remove the output from clients, the WM and the scene.
+    fn disable_output(&mut self, output: &Output) {
+        let Some(connected) = self.outputs.iter_mut().find(|connected| &connected.output == output) else {
+            return;
+        };
+
+        let Some(global) = connected.global.take() else {
+            return;
+        };
+
+        let wm_id = connected.wm_id.take();
+        let layout = connected.layout.take();
+
+        self.stop_captures(&CaptureSource::Output(output.clone()));
+        self.scene.destroy_output(output);
+
+        if let Some(layout) = layout {
+            // Keep the toplevels on the output visible by moving the toplevels to another output.
+            // TODO: Toplevels are not presented again until remapped if no other output is enabled.
+            if let Some(other) = self.outputs.iter().find_map(ConnectedOutput::layout) {
+                self.scene.branch_move_children(layout, other);
+            }
+
+            self.scene.destroy_branch(layout);
+        }
+
+        // Clients may be binding the global while the global is removed, so the global is disabled first and
+        // removed once clients had time to see the global was removed.
+        self.display.disable_global::<Self>(global.clone());
+        let removed = global.clone();
+
+        if self
+            .r#loop
+            .insert_source(Timer::from_duration(GLOBAL_REMOVAL_DELAY), move |_, _, state| {
+                state.comp.display.remove_global::<Self>(removed.clone());
+                TimeoutAction::Drop
+            })
+            .is_err()
+        {
+            self.display.remove_global::<Self>(global);
+        }
+
+        if let (Some(wm), Some(id)) = (self.wm.as_ref(), wm_id) {
+            wm.send(WmEvent::DisconnectOutput(id));
+        }
+    }
+
+    /// Notify the WM the state of the output changed and render the output.
+    fn output_updated(&mut self, output: &Output) {
+        let Some(connected) = self.outputs.iter().find(|connected| &connected.output == output) else {
+            return;
+        };
+
+        if let (Some(wm), Some(id)) = (self.wm.as_ref(), connected.wm_id) {
+            wm.send(WmEvent::UpdateOutput {
+                output: id,
+                info: output_info(output),
+            });
+        }
+
+        // The scale or transform of the output may have changed.
+        self.scene.mark_layout_changed();
+        self.backend.schedule_render(output);
+    }
+
+    /// Arrange the outputs which were not given a location from left to right.
+    ///
+    /// The outputs are placed to the right of any outputs given a location by an output configuration.
+    fn arrange_outputs(&mut self) {
+        let mut x = self
+            .outputs
+            .iter()
+            .filter(|connected| connected.enabled() && connected.location_configured)
+            .map(|connected| {
+                let geometry = geometry(&connected.output);
+                geometry.loc.x + geometry.size.w
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut moved = Vec::new();
+
+        for connected in &self.outputs {
+            if !connected.enabled() || connected.location_configured {
+                continue;
+            }
+
+            let location = Point::<i32, Logical>::from((x, 0));
+            x += geometry(&connected.output).size.w;
+
+            if connected.output.current_location() == location {
+                continue;
+            }
+
+            // Updates the location advertised to clients through xdg-output.
+            connected.output.change_current_state(None, None, None, Some(location));
+            moved.push(connected.output.clone());
+        }
+
+        for output in moved {
+            self.output_updated(&output);
+        }
+    }
+
+    /// Request every output is rendered.
+    pub fn schedule_render(&mut self) {
+        for output in self.outputs().cloned().collect::<Vec<_>>() {
+            self.backend.schedule_render(&output);
+        }
+    }
+
+    /// The outputs which are enabled, in the order the outputs were added.
+    pub fn outputs(&self) -> impl Iterator<Item = &Output> {
+        self.outputs
+            .iter()
+            .filter(|connected| connected.enabled())
+            .map(ConnectedOutput::output)
+    }
+}
+
+/// The area of the global space the output occupies.
+pub fn geometry(output: &Output) -> Rectangle<i32, Logical> {
+    let size = output
+        .current_mode()
+        .map(|mode| {
+            output
+                .current_transform()
+                .transform_size(mode.size)
+                .to_f64()
+                .to_logical(output.current_scale().fractional_scale())
+                .to_i32_round()
+        })
+        .unwrap_or_default();
+
+    Rectangle::from_loc_and_size(output.current_location(), size)
+}
+
+/// The size of the output in physical pixels, after the output transform is applied.
+pub fn output_size(output: &Output) -> Size<i32, Physical> {
+    output
+        .current_mode()
+        .map(|mode| output.current_transform().transform_size(mode.size))
+        .unwrap_or_default()
+}
+
+/// Describe the output to the WM.
+fn output_info(output: &Output) -> OutputInfo {
+    let geometry = geometry(output);
+
+    OutputInfo {
+        name: Some(output.name()),
+        geometry: Geometry {
+            x: geometry.loc.x,
+            y: geometry.loc.y,
+            width: geometry.size.w as u32,
+            height: geometry.size.h as u32,
+        },
+        refresh_rate: output.current_mode().map(|mode| mode.refresh as u32).unwrap_or(0),
+    }
+}
@@ -0,0 +1,545 @@
+//! Debug dumps of the scene graph
+//!
+//! A dump describes the hierarchy presented on each output, followed by the trees which are not presented on any
+//! output. When a window is not visible, the dump shows whether the window is part of a presented hierarchy and
+//! which branches above the window may hide or clip the window.
+
+use std::{fmt, fmt::Write, ops::Deref};
+
+use slotmap::Key;
+use smithay::{
+    backend::renderer::utils::{CommitCounter, RendererSurfaceStateUserData},
+    utils::{Logical, Point, Rectangle, Size},
+    wayland::compositor,
+};
+use wayland_server::Resource;
+
+use crate::forest::{Edge, Index, Node};
+
+use super::{surface_size, Scene, SceneNode};
+
+/// The format of a scene graph dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// An indented tree intended to be read in a log.
+    Text,
+
+    /// A JSON document, for tools which inspect the scene.
+    Json,
+
+    /// A Graphviz DOT graph, which may be rendered using `dot -Tsvg`.
+    Dot,
+}
+
+impl Scene {
+    /// Describe every node in the scene graph.
+    pub fn dump(&self, format: DumpFormat) -> String {
+        let trees = self.dump_trees();
+
+        match format {
+            DumpFormat::Text => text(&trees),
+            DumpFormat::Json => json(&trees),
+            DumpFormat::Dot => dot(&trees),
+        }
+    }
+
+    fn dump_trees(&self) -> Vec<DumpTree> {
+        let mut outputs = self
+            .outputs
+            .iter()
+            .filter_map(|(output, index)| Some((output.name(), self.get_output(*index)?.present)))
+            .collect::<Vec<_>>();
+        outputs.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let presented = outputs
+            .iter()
+            .filter_map(|(_, present)| present.map(Index::from))
+            .collect::<Vec<_>>();
+
+        let mut trees = outputs
+            .into_iter()
+            .map(|(name, present)| DumpTree {
+                output: Some(name),
+                nodes: present.map(|root| self.dump_nodes(root.into())).unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+
+        // Trees which are not presented are the usual suspect when a window is not visible.
+        let unpresented = self.forest.roots().filter(|root| {
+            let output = matches!(self.forest.get(*root).unwrap().deref(), SceneNode::Output(_));
+            !output && !presented.contains(root)
+        });
+
+        trees.extend(unpresented.map(|root| DumpTree {
+            output: None,
+            nodes: self.dump_nodes(root),
+        }));
+
+        trees
+    }
+
+    /// Describe the node and it's descendants, ordered from the root to the leaves.
+    fn dump_nodes(&self, root: Index) -> Vec<DumpNode> {
+        let Some(iter) = self.forest.preorder_traverse(root) else {
+            return Vec::new();
+        };
+
+        let mut depth = 0;
+        let mut nodes = Vec::new();
+
+        for edge in iter {
+            let index = match edge {
+                Edge::Start(index) => index,
+                Edge::End(_) => {
+                    depth -= 1;
+                    continue;
+                }
+            };
+
+            let node = self.forest.get(index).unwrap();
+            // The siblings before a node are below the node.
+            let z = self.forest.previous_siblings(index).unwrap().count() - 1;
+            let parent = if index == root { None } else { Node::parent(node) };
+
+            let (kind, offset, properties) = match node.deref() {
+                SceneNode::Output(_) => unreachable!(),
+
+                SceneNode::SurfaceTree(node) => (
+                    "surface-tree",
+                    node.offset,
+                    vec![
+                        ("root", Value::Index(node.root.0)),
+                        (
+                            "decoration",
+                            node.decoration.map_or(Value::Null, |index| Value::Index(index.0)),
+                        ),
+                    ],
+                ),
+
+                SceneNode::Surface(node) => {
+                    let commit = compositor::with_states(&node.surface, |states| {
+                        let data = states.data_map.get::<RendererSurfaceStateUserData>();
+                        data.map(|data| data.borrow().current_commit())
+                    });
+                    let client = node.surface.client().map(|client| format!("{:?}", client.id()));
+                    let outputs = node.outputs.iter().map(|output| Value::String(output.name()));
+
+                    (
+                        "surface",
+                        node.offset,
+                        vec![
+                            ("surface", Value::String(node.surface.id().to_string())),
+                            ("client", client.map_or(Value::Null, Value::String)),
+                            ("commit", commit.map_or(Value::Null, Value::commit)),
+                            ("size", Value::size(surface_size(&node.surface))),
+                            ("outputs", Value::List(outputs.collect())),
+                        ],
+                    )
+                }
+
+                SceneNode::Branch(node) => (
+                    "branch",
+                    node.offset,
+                    vec![
+                        ("opacity", Value::Float(node.opacity.into())),
+                        ("clip", node.clip.map_or(Value::Null, Value::rectangle)),
+                        ("scale", Value::Float(node.scale)),
+                    ],
+                ),
+
+                SceneNode::SolidColor(node) => (
+                    "solid-color",
+                    node.offset,
+                    vec![
+                        ("size", Value::size(node.size)),
+                        ("color", Value::color(node.color)),
+                        ("commit", Value::commit(node.commit)),
+                    ],
+                ),
+
+                SceneNode::Decoration(node) => (
+                    "decoration",
+                    node.offset,
+                    vec![
+                        ("tree", Value::Index(node.tree.0)),
+                        ("border-width", Value::Int(node.style.border_width.into())),
+                        ("border-color", Value::color(node.style.border_color)),
+                        ("title-bar-height", Value::Int(node.style.title_bar_height.into())),
+                        ("title-bar-color", Value::color(node.style.title_bar_color)),
+                        ("commit", Value::commit(node.commit)),
+                    ],
+                ),
+
+                SceneNode::Snapshot(node) => (
+                    "snapshot",
+                    node.offset(),
+                    vec![
+                        ("size", Value::size(node.size())),
+                        ("scale", Value::Float(node.scale())),
+                        ("surfaces", Value::Int(node.surfaces().len() as i64)),
+                    ],
+                ),
+            };
+
+            nodes.push(DumpNode {
+                index,
+                parent,
+                depth,
+                z,
+                kind,
+                offset,
+                properties,
+            });
+
+            depth += 1;
+        }
+
+        nodes
+    }
+}
+
+/// A tree of nodes in the scene graph.
+struct DumpTree {
+    /// The name of the output the tree is presented on, [`None`] if the tree is not presented.
+    output: Option<String>,
+
+    /// The nodes in the tree, ordered from the root to the leaves.
+    nodes: Vec<DumpNode>,
+}
+
+struct DumpNode {
+    index: Index,
+    /// The parent of the node, [`None`] if the node is the root of the tree.
+    parent: Option<Index>,
+    depth: usize,
+    /// The position of the node among it's siblings, where `0` is the bottom.
+    z: usize,
+    kind: &'static str,
+    offset: Point<i32, Logical>,
+    /// Properties specific to the kind of node.
+    properties: Vec<(&'static str, Value)>,
+}
+
+enum Value {
+    Null,
+    Int(i64),
+    Float(f64),
+    String(String),
+    Index(Index),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn size(size: Size<i32, Logical>) -> Self {
+        Self::List(vec![Self::Int(size.w.into()), Self::Int(size.h.into())])
+    }
+
+    fn rectangle(rect: Rectangle<i32, Logical>) -> Self {
+        Self::List(
+            [rect.loc.x, rect.loc.y, rect.size.w, rect.size.h]
+                .into_iter()
+                .map(|value| Self::Int(value.into()))
+                .collect(),
+        )
+    }
+
+    fn color(color: [f32; 4]) -> Self {
+        Self::List(color.into_iter().map(|channel| Self::Float(channel.into())).collect())
+    }
+
+    fn commit(commit: CommitCounter) -> Self {
+        // The counter is not exposed, but the distance from a new counter is the number of commits.
+        commit
+            .distance(Some(CommitCounter::default()))
+            .map_or(Self::Null, |commits| Self::Int(commits as i64))
+    }
+
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Int(value) => {
+                let _ = write!(out, "{value}");
+            }
+            Value::Float(value) if value.is_finite() => {
+                let _ = write!(out, "{value}");
+            }
+            Value::Float(_) => out.push_str("null"),
+            Value::String(value) => write_json_string(out, value),
+            Value::Index(index) => write_json_string(out, &node_id(*index)),
+            Value::List(values) => {
+                out.push('[');
+
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        out.push(',');
+                    }
+
+                    value.write_json(out);
+                }
+
+                out.push(']');
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("none"),
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Float(value) => write!(f, "{value}"),
+            Value::String(value) => f.write_str(value),
+            Value::Index(index) => f.write_str(&node_id(*index)),
+            Value::List(values) => {
+                f.write_str("[")?;
+
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+
+                    write!(f, "{value}")?;
+                }
+
+                f.write_str("]")
+            }
+        }
+    }
+}
+
+/// A short identifier for a node, such as `4v1`.
+fn node_id(index: Index) -> String {
+    format!("{:?}", index.data())
+}
+
+fn text(trees: &[DumpTree]) -> String {
+    let mut out = String::new();
+
+    for tree in trees {
+        match &tree.output {
+            Some(output) => {
+                let _ = writeln!(out, "output {output}");
+            }
+            None => out.push_str("not presented\n"),
+        }
+
+        if tree.nodes.is_empty() {
+            out.push_str("  (empty)\n");
+        }
+
+        for node in &tree.nodes {
+            let _ = write!(
+                out,
+                "{:indent$}{} {} offset=({}, {}) z={}",
+                "",
+                node.kind,
+                node_id(node.index),
+                node.offset.x,
+                node.offset.y,
+                node.z,
+                indent = (node.depth + 1) * 2,
+            );
+
+            for (name, value) in &node.properties {
+                let _ = write!(out, " {name}={value}");
+            }
+
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+fn json(trees: &[DumpTree]) -> String {
+    let mut out = String::from("{\"trees\":[");
+
+    for (i, tree) in trees.iter().enumerate() {
+        if i != 0 {
+            out.push(',');
+        }
+
+        out.push_str("{\"output\":");
+        match &tree.output {
+            Some(output) => write_json_string(&mut out, output),
+            None => out.push_str("null"),
+        }
+
+        out.push_str(",\"nodes\":[");
+
+        for (i, node) in tree.nodes.iter().enumerate() {
+            if i != 0 {
+                out.push(',');
+            }
+
+            out.push_str("{\"index\":");
+            write_json_string(&mut out, &node_id(node.index));
+            out.push_str(",\"parent\":");
+            node.parent.map_or(Value::Null, Value::Index).write_json(&mut out);
+            let _ = write!(
+                out,
+                ",\"kind\":\"{}\",\"depth\":{},\"z\":{},\"offset\":[{},{}],\"properties\":{{",
+                node.kind, node.depth, node.z, node.offset.x, node.offset.y
+            );
+
+            for (i, (name, value)) in node.properties.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+
+                write_json_string(&mut out, name);
+                out.push(':');
+                value.write_json(&mut out);
+            }
+
+            out.push_str("}}");
+        }
+
+        out.push_str("]}");
+    }
+
+    out.push_str("]}");
+    out
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
+
+fn dot(trees: &[DumpTree]) -> String {
+    let mut out = String::from("digraph scene {\n    node [shape=box];\n");
+
+    for tree in trees {
+        if let Some(output) = &tree.output {
+            let id = format!("output {output}");
+            out.push_str("    ");
+            write_dot_string(&mut out, &id);
+            out.push_str(" [shape=ellipse];\n");
+
+            if let Some(root) = tree.nodes.first() {
+                out.push_str("    ");
+                write_dot_string(&mut out, &id);
+                let _ = writeln!(out, " -> \"{}\";", node_id(root.index));
+            }
+        }
+
+        for node in &tree.nodes {
+            let mut label = format!(
+                "{} {}\noffset=({}, {}) z={}",
+                node.kind,
+                node_id(node.index),
+                node.offset.x,
+                node.offset.y,
+                node.z
+            );
+
+            for (name, value) in &node.properties {
+                let _ = write!(label, "\n{name}={value}");
+            }
+
+            let _ = write!(out, "    \"{}\" [label=", node_id(node.index));
+            write_dot_string(&mut out, &label);
+            out.push_str("];\n");
+
+            if let Some(parent) = node.parent {
+                let _ = writeln!(out, "    \"{}\" -> \"{}\";", node_id(parent), node_id(node.index));
+            }
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Write a quoted DOT string.
+///
+/// Newlines are written as line breaks in labels.
+fn write_dot_string(out: &mut String, value: &str) {
+    out.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::utils::Rectangle;
+
+    use crate::scene::{NodeIndex, Scene};
+
+    use super::{dot, write_dot_string, write_json_string, DumpFormat, DumpTree};
+
+    #[test]
+    fn json_string_escapes() {
+        let value = "quote \" backslash \\ newline \n tab \t bell \u{7} unicode é";
+        let mut out = String::new();
+        write_json_string(&mut out, value);
+
+        assert_eq!(
+            out,
+            r#""quote \" backslash \\ newline \n tab \u0009 bell \u0007 unicode é""#
+        );
+        assert_eq!(serde_json::from_str::<String>(&out).unwrap(), value);
+    }
+
+    #[test]
+    fn json_parses() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        let color = scene.create_solid_color((10, 20).into(), [1.0, 0.0, 0.0, 1.0]);
+        scene.branch_add_child(root, NodeIndex::SolidColor(color)).unwrap();
+        scene.set_branch_clip(root, Some(Rectangle::from_loc_and_size((0, 0), (5, 5))));
+
+        let dump = scene.dump(DumpFormat::Json);
+        let json = serde_json::from_str::<serde_json::Value>(&dump).unwrap();
+
+        let trees = json["trees"].as_array().unwrap();
+        assert_eq!(trees.len(), 1);
+        assert!(trees[0]["output"].is_null());
+
+        let nodes = trees[0]["nodes"].as_array().unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0]["kind"], "branch");
+        assert!(nodes[0]["parent"].is_null());
+        assert_eq!(nodes[0]["properties"]["clip"], serde_json::json!([0, 0, 5, 5]));
+        assert_eq!(nodes[1]["kind"], "solid-color");
+        assert_eq!(nodes[1]["parent"], nodes[0]["index"]);
+        assert_eq!(nodes[1]["properties"]["size"], serde_json::json!([10, 20]));
+    }
+
+    #[test]
+    fn dot_string_escapes() {
+        let mut out = String::new();
+        write_dot_string(&mut out, "a \"b\" \\c\nd");
+        assert_eq!(out, r#""a \"b\" \\c\nd""#);
+
+        let dump = dot(&[DumpTree {
+            output: Some("\"quoted\"".into()),
+            nodes: Vec::new(),
+        }]);
+        assert!(dump.contains(r#""output \"quoted\"" [shape=ellipse];"#));
+    }
+}
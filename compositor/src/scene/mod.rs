@@ -0,0 +1,2692 @@
+//! The Aerugo scene graph
+//!
+//! TODO: Documentation
+
+mod dump;
+mod snapshot;
+
+use std::{
+    ops::{Deref, DerefMut},
+    rc::Rc,
+};
+
+use rustc_hash::FxHashMap;
+use smithay::{
+    backend::renderer::{
+        element::{AsRenderElements, Element, Id, RenderElement, UnderlyingStorage},
+        utils::{CommitCounter, RendererSurfaceState, RendererSurfaceStateUserData},
+        Frame, ImportAll, ImportMem, Renderer,
+    },
+    output::Output,
+    utils::{Buffer, Logical, Physical, Point, Rectangle, Scale, Size, Transform},
+    wayland::{
+        compositor::{self, SubsurfaceCachedState, SurfaceAttributes, SurfaceData, TraversalAction},
+        single_pixel_buffer,
+    },
+};
+use wayland_server::{backend::ObjectId, protocol::wl_surface, Resource};
+
+use crate::{
+    forest::{Edge, Error, Forest, Index, Node},
+    wayland::wp::fractional_scale,
+};
+
+pub use dump::DumpFormat;
+pub use snapshot::SnapshotNode;
+
+use snapshot::SnapshotSurface;
+
+/// A stable index to reference an [`OutputNode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OutputIndex(Index);
+
+/// A stable index to reference an [`SurfaceTreeNode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SurfaceTreeIndex(Index);
+
+/// A stable index to reference a [`SurfaceNode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SurfaceIndex(Index);
+
+/// A stable index to reference a [`BranchNode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BranchIndex(Index);
+
+/// A stable index to reference a [`SolidColorNode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SolidColorIndex(Index);
+
+/// A stable index to reference a [`DecorationNode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DecorationIndex(Index);
+
+/// A stable index to reference a [`SnapshotNode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SnapshotIndex(Index);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeIndex {
+    SurfaceTree(SurfaceTreeIndex),
+    Branch(BranchIndex),
+    SolidColor(SolidColorIndex),
+    Decoration(DecorationIndex),
+    Snapshot(SnapshotIndex),
+}
+
+impl PartialEq<SurfaceTreeIndex> for NodeIndex {
+    fn eq(&self, other: &SurfaceTreeIndex) -> bool {
+        Self::SurfaceTree(*other) == *self
+    }
+}
+
+impl PartialEq<BranchIndex> for NodeIndex {
+    fn eq(&self, other: &BranchIndex) -> bool {
+        Self::Branch(*other) == *self
+    }
+}
+
+impl PartialEq<SolidColorIndex> for NodeIndex {
+    fn eq(&self, other: &SolidColorIndex) -> bool {
+        Self::SolidColor(*other) == *self
+    }
+}
+
+impl PartialEq<DecorationIndex> for NodeIndex {
+    fn eq(&self, other: &DecorationIndex) -> bool {
+        Self::Decoration(*other) == *self
+    }
+}
+
+impl PartialEq<SnapshotIndex> for NodeIndex {
+    fn eq(&self, other: &SnapshotIndex) -> bool {
+        Self::Snapshot(*other) == *self
+    }
+}
+
+#[derive(Debug)]
+pub struct OutputNode {
+    index: OutputIndex,
+    output: Output,
+    present: Option<NodeIndex>,
+}
+
+impl OutputNode {
+    pub fn index(&self) -> OutputIndex {
+        self.index
+    }
+
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+}
+
+/// A node for a surface and it's subsurface tree.
+#[derive(Debug)]
+pub struct SurfaceTreeNode {
+    index: SurfaceTreeIndex,
+    root: SurfaceIndex,
+    base: SurfaceIndex,
+    top: SurfaceIndex,
+    /// The offset of the root surface from the parent.
+    ///
+    /// If decorated, this is the inset of the surface tree from the decorations. The offset of the decorated
+    /// surface tree is the offset of the decorations.
+    offset: Point<i32, Logical>,
+    /// The server side decorations drawn around the surface tree.
+    decoration: Option<DecorationIndex>,
+}
+
+impl SurfaceTreeNode {
+    pub fn index(&self) -> SurfaceTreeIndex {
+        self.index
+    }
+
+    pub fn decoration(&self) -> Option<DecorationIndex> {
+        self.decoration
+    }
+
+    /// The root surface is the parent of all subsurfaces in this subsurface tree.
+    ///
+    /// The root surface will typically be a surface with a role such as `xdg-toplevel`, `layer-shell` and
+    /// `xwayland`.
+    pub fn root(&self) -> SurfaceIndex {
+        self.root
+    }
+
+    /// The base surface is the subsurface at the with the lowest z-index in a subsurface tree.
+    ///
+    /// If there are no subsurfaces below the root surface, then this will be the same as the root surface.
+    pub fn base(&self) -> SurfaceIndex {
+        self.base
+    }
+
+    /// The top surface is the subsurface at the with the highest z-index in a subsurface tree.
+    ///
+    /// If there are no subsurfaces above the root surface, then this will be the same as the root surface.
+    pub fn top(&self) -> SurfaceIndex {
+        self.top
+    }
+}
+
+#[derive(Debug)]
+pub struct SurfaceNode {
+    index: SurfaceIndex,
+    surface: wl_surface::WlSurface,
+    offset: Point<i32, Logical>,
+    /// The outputs the surface was sent enter events for.
+    outputs: Vec<Output>,
+}
+
+#[derive(Debug)]
+pub struct BranchNode {
+    index: BranchIndex,
+    /// The id of the element damaging the descendants while the branch is translucent.
+    id: Id,
+    offset: Point<i32, Logical>,
+    /// The opacity of the branch, multiplied with the opacity of any branches below this branch.
+    opacity: f32,
+    /// Incremented when the opacity changes so the descendants are damaged.
+    commit: CommitCounter,
+    /// The area descendants are visible in, relative to the branch and scaled by the scale of the branch.
+    clip: Option<Rectangle<i32, Logical>>,
+    /// The scale applied to the descendants of the branch.
+    scale: f64,
+}
+
+impl BranchNode {
+    pub fn index(&self) -> BranchIndex {
+        self.index
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    pub fn clip(&self) -> Option<Rectangle<i32, Logical>> {
+        self.clip
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+}
+
+/// A node filled with a solid color, such as a background or a border.
+#[derive(Debug)]
+pub struct SolidColorNode {
+    index: SolidColorIndex,
+    id: Id,
+    size: Size<i32, Logical>,
+    /// The color of the node, with premultiplied alpha.
+    color: [f32; 4],
+    /// Incremented when the color changes so the node is damaged.
+    commit: CommitCounter,
+    offset: Point<i32, Logical>,
+}
+
+impl SolidColorNode {
+    pub fn index(&self) -> SolidColorIndex {
+        self.index
+    }
+
+    pub fn size(&self) -> Size<i32, Logical> {
+        self.size
+    }
+
+    pub fn set_size(&mut self, size: Size<i32, Logical>) {
+        self.size = size;
+    }
+
+    pub fn color(&self) -> [f32; 4] {
+        self.color
+    }
+
+    pub fn set_color(&mut self, color: [f32; 4]) {
+        if self.color != color {
+            self.color = color;
+            self.commit.increment();
+        }
+    }
+}
+
+/// The appearance of server side decorations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecorationStyle {
+    /// The width of the border around the title bar and surface tree.
+    pub border_width: i32,
+
+    /// The color of the border, with premultiplied alpha.
+    pub border_color: [f32; 4],
+
+    /// The height of the title bar above the surface tree.
+    ///
+    /// If the height is zero, there is no title bar.
+    pub title_bar_height: i32,
+
+    /// The color of the title bar, with premultiplied alpha.
+    pub title_bar_color: [f32; 4],
+    // TODO: Title text. This needs a font rasterizer and a way to import the rasterized text into the renderer.
+}
+
+impl Default for DecorationStyle {
+    fn default() -> Self {
+        Self {
+            border_width: 1,
+            border_color: [0.1, 0.1, 0.1, 1.0],
+            title_bar_height: 24,
+            title_bar_color: [0.2, 0.2, 0.2, 1.0],
+        }
+    }
+}
+
+impl DecorationStyle {
+    /// The offset of the surface tree from the top left corner of the decorations.
+    fn surface_tree_offset(&self) -> Point<i32, Logical> {
+        (self.border_width, self.border_width + self.title_bar_height).into()
+    }
+}
+
+/// Server side decorations drawn around a surface tree.
+///
+/// The surface tree is the only child of the node and is offset to make room for the border and title bar. The
+/// decorations are sized to fit the root surface of the tree, so the decorations resize with the surface tree.
+#[derive(Debug)]
+pub struct DecorationNode {
+    index: DecorationIndex,
+    tree: SurfaceTreeIndex,
+    style: DecorationStyle,
+    /// The ids of the title bar and the top, bottom, left and right borders.
+    ids: [Id; 5],
+    /// Incremented when the style changes so the decorations are damaged.
+    commit: CommitCounter,
+    offset: Point<i32, Logical>,
+}
+
+impl DecorationNode {
+    pub fn index(&self) -> DecorationIndex {
+        self.index
+    }
+
+    /// The surface tree the decorations are drawn around.
+    pub fn tree(&self) -> SurfaceTreeIndex {
+        self.tree
+    }
+
+    pub fn style(&self) -> &DecorationStyle {
+        &self.style
+    }
+
+    /// The rectangles making up the decorations of a surface tree with the size, relative to the node.
+    fn rectangles(&self, size: Size<i32, Logical>) -> impl Iterator<Item = (&Id, Rectangle<i32, Logical>, [f32; 4])> {
+        let DecorationStyle {
+            border_width: border,
+            border_color,
+            title_bar_height: title,
+            title_bar_color,
+        } = self.style;
+        let outer = Size::<i32, Logical>::from((size.w + border * 2, size.h + title + border * 2));
+        let [title_id, top, bottom, left, right] = &self.ids;
+
+        [
+            (title_id, (border, border), (size.w, title), title_bar_color),
+            (top, (0, 0), (outer.w, border), border_color),
+            (bottom, (0, outer.h - border), (outer.w, border), border_color),
+            (left, (0, border), (border, outer.h - border * 2), border_color),
+            (
+                right,
+                (outer.w - border, border),
+                (border, outer.h - border * 2),
+                border_color,
+            ),
+        ]
+        .into_iter()
+        .map(|(id, loc, size, color)| (id, Rectangle::from_loc_and_size(loc, size), color))
+        .filter(|(_, rect, _)| !rect.is_empty())
+    }
+}
+
+#[derive(Debug)]
+pub struct Scene {
+    outputs: FxHashMap<Output, OutputIndex>,
+    surface_trees: FxHashMap<ObjectId, SurfaceTreeIndex>,
+    surfaces: FxHashMap<ObjectId, SurfaceIndex>,
+    forest: Forest<SceneNode>,
+    /// Whether the outputs surfaces are on may have changed since the last update.
+    layout_changed: bool,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self {
+            outputs: FxHashMap::default(),
+            surface_trees: FxHashMap::default(),
+            surfaces: FxHashMap::default(),
+            forest: Forest::new(),
+            layout_changed: false,
+        }
+    }
+
+    pub fn create_output(&mut self, output: Output) -> OutputIndex {
+        let index = OutputIndex(self.forest.insert_with(|index| {
+            SceneNode::Output(OutputNode {
+                index: OutputIndex(index),
+                output: output.clone(),
+                present: None,
+            })
+        }));
+
+        self.outputs.insert(output, index);
+        self.layout_changed = true;
+        index
+    }
+
+    pub fn destroy_output(&mut self, output: &Output) {
+        // Disassociating the output from child surfaces needs to occur before we destroy the node.
+        self.unset_output_root(output);
+
+        if let Some(OutputIndex(index)) = self.outputs.remove(output) {
+            let _ = self.forest.remove(index);
+        }
+    }
+
+    /// Notify the scene that something affecting which outputs surfaces are on changed, such as the scale of an
+    /// output.
+    pub fn mark_layout_changed(&mut self) {
+        self.layout_changed = true;
+    }
+
+    pub fn get_output_index(&self, output: &Output) -> Option<OutputIndex> {
+        self.outputs.get(output).cloned()
+    }
+
+    pub fn get_output(&self, index: OutputIndex) -> Option<&OutputNode> {
+        self.forest.get(index.0).map(|node| match node.deref() {
+            SceneNode::Output(node) => node,
+            _ => unreachable!(),
+        })
+    }
+
+    pub fn get_output_mut(&mut self, index: OutputIndex) -> Option<&mut OutputNode> {
+        self.forest.get_mut(index.0).map(|node| match node.deref_mut() {
+            SceneNode::Output(node) => node,
+            _ => unreachable!(),
+        })
+    }
+
+    pub fn set_output_node(&mut self, output: &Output, node: NodeIndex) {
+        let node = self.placed_node(node);
+        self.unset_output_root(output);
+
+        if let Some(index) = self.get_output_index(output) {
+            let output_node = self.get_output_mut(index).unwrap();
+            output_node.present = Some(node);
+        }
+
+        self.layout_changed = true;
+    }
+
+    pub fn get_surface_tree_index(&self, surface: wl_surface::WlSurface) -> Option<SurfaceTreeIndex> {
+        self.surface_trees.get(&surface.id()).cloned()
+    }
+
+    pub fn get_surface_tree(&mut self, index: SurfaceTreeIndex) -> Option<&mut SurfaceTreeNode> {
+        self.forest.get_mut(index.0).map(|node| match node.deref_mut() {
+            SceneNode::SurfaceTree(node) => node,
+            _ => unreachable!(),
+        })
+    }
+
+    pub fn create_surface_tree(&mut self, surface: wl_surface::WlSurface) -> SurfaceTreeIndex {
+        if let Some(index) = self.get_surface_tree_index(surface.clone()) {
+            return index;
+        }
+
+        // Create the surface node for this surface.
+        let root = self.create_surface_node(&surface);
+
+        let index = SurfaceTreeIndex(self.forest.insert_with(|index| {
+            SceneNode::SurfaceTree(SurfaceTreeNode {
+                index: SurfaceTreeIndex(index),
+                root,
+                base: root,
+                top: root,
+                offset: Default::default(),
+                decoration: None,
+            })
+        }));
+
+        self.forest.add_child(index.0, root.0).unwrap();
+        self.surface_trees.insert(surface.id(), index);
+
+        // Initialize the surface tree
+        self.apply_surface_commit(&surface);
+        index
+    }
+
+    /// Destroy a surface tree along with the nodes of every surface in the tree.
+    pub fn destroy_surface_tree(&mut self, index: SurfaceTreeIndex) {
+        let surfaces = self.forest.children(index.0).collect::<Vec<_>>();
+
+        for surface in surfaces {
+            self.destroy_surface_node(SurfaceIndex(surface));
+        }
+
+        if let Ok(SceneNode::SurfaceTree(node)) = self.forest.remove(index.0) {
+            self.surface_trees.retain(|_, tree| *tree != node.index);
+
+            // The decorations have nothing to draw around anymore.
+            if let Some(decoration) = node.decoration {
+                let _ = self.forest.remove(decoration.0);
+                self.clear_output_nodes(NodeIndex::Decoration(decoration));
+            }
+        }
+
+        self.clear_output_nodes(NodeIndex::SurfaceTree(index));
+    }
+
+    pub fn get_surface_index(&self, surface: wl_surface::WlSurface) -> Option<SurfaceIndex> {
+        self.surfaces.get(&surface.id()).cloned()
+    }
+
+    pub fn get_surface(&mut self, index: SurfaceIndex) -> Option<&mut SurfaceNode> {
+        self.forest.get_mut(index.0).map(|node| match node.deref_mut() {
+            SceneNode::Surface(node) => node,
+            _ => unreachable!(),
+        })
+    }
+
+    /// Applies the new surface state to the scene graph.
+    ///
+    /// If the surface has any subsurfaces, the subsurfaces will be adjusted. Subsurfaces which were added are
+    /// given a node, subsurfaces which were removed lose their node and the nodes are restacked to match the
+    /// order set by `place_above` and `place_below`.
+    ///
+    /// The surface may be any surface in a surface tree.
+    pub fn apply_surface_commit(&mut self, surface: &wl_surface::WlSurface) {
+        // TODO: Do we need a commit state to apply since we are transaction based?
+        let mut root = surface.clone();
+
+        while let Some(parent) = compositor::get_parent(&root) {
+            root = parent;
+        }
+
+        let Some(tree) = self.get_surface_tree_index(root.clone()) else {
+            return;
+        };
+
+        // The surfaces in the tree from bottom to top and the offset of each surface from the root surface.
+        let mut surfaces = Vec::new();
+        compositor::with_surface_tree_upward(
+            &root,
+            Point::<i32, Logical>::default(),
+            |_, states, &parent| TraversalAction::DoChildren(subsurface_offset(states, parent)),
+            |surface, states, &parent| surfaces.push((surface.clone(), subsurface_offset(states, parent))),
+            |_, _, _| true,
+        );
+
+        let mut stack = Vec::with_capacity(surfaces.len());
+
+        for (surface, offset) in surfaces {
+            let index = match self.get_surface_index(surface.clone()) {
+                Some(index) => index,
+                None => self.create_surface_node(&surface),
+            };
+
+            self.get_surface(index).unwrap().offset = offset;
+            stack.push(index);
+        }
+
+        let (Some(&base), Some(&top)) = (stack.first(), stack.last()) else {
+            return;
+        };
+
+        // Remove the nodes of subsurfaces which are no longer part of the tree.
+        let (previous, removed) = self
+            .forest
+            .children(tree.0)
+            .map(SurfaceIndex)
+            .partition::<Vec<_>, _>(|index| stack.contains(index));
+
+        for index in removed {
+            self.destroy_surface_node(index);
+        }
+
+        if previous != stack {
+            for index in &stack {
+                let _ = self.forest.detach(index.0);
+                self.forest.add_child(tree.0, index.0).unwrap();
+            }
+        }
+
+        let root = self.get_surface_index(root).unwrap();
+        let node = self.get_surface_tree(tree).unwrap();
+        node.root = root;
+        node.base = base;
+        node.top = top;
+
+        // The size of the surfaces may have changed.
+        self.layout_changed = true;
+    }
+
+    /// Remove a destroyed surface from the scene.
+    ///
+    /// If the surface is the root of a surface tree, the whole tree is destroyed.
+    pub fn surface_destroyed(&mut self, surface: &wl_surface::WlSurface) {
+        if let Some(tree) = self.get_surface_tree_index(surface.clone()) {
+            self.destroy_surface_tree(tree);
+        }
+
+        if let Some(index) = self.get_surface_index(surface.clone()) {
+            self.destroy_surface_node(index);
+        }
+    }
+
+    fn create_surface_node(&mut self, surface: &wl_surface::WlSurface) -> SurfaceIndex {
+        let index = SurfaceIndex(self.forest.insert_with(|index| {
+            SceneNode::Surface(SurfaceNode {
+                index: SurfaceIndex(index),
+                surface: surface.clone(),
+                offset: Default::default(),
+                outputs: Vec::new(),
+            })
+        }));
+
+        self.surfaces.insert(surface.id(), index);
+        index
+    }
+
+    fn destroy_surface_node(&mut self, index: SurfaceIndex) {
+        if let Ok(SceneNode::Surface(node)) = self.forest.remove(index.0) {
+            self.surfaces.remove(&node.surface.id());
+
+            // A subsurface which was removed from the tree is no longer on any output.
+            if node.surface.is_alive() {
+                for output in &node.outputs {
+                    output.leave(&node.surface);
+                }
+            }
+
+            self.layout_changed = true;
+        }
+    }
+
+    /// The outputs a surface is on.
+    pub fn surface_outputs(&self, surface: &wl_surface::WlSurface) -> &[Output] {
+        let Some(index) = self.surfaces.get(&surface.id()) else {
+            return &[];
+        };
+
+        match self.forest.get(index.0).map(Deref::deref) {
+            Some(SceneNode::Surface(node)) => &node.outputs,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The surfaces which are on the output.
+    pub fn surfaces_on_output<'a>(&'a self, output: &'a Output) -> impl Iterator<Item = &'a wl_surface::WlSurface> {
+        self.surfaces
+            .values()
+            .filter_map(|index| match self.forest.get(index.0).map(Deref::deref) {
+                Some(SceneNode::Surface(node)) => Some(node),
+                _ => None,
+            })
+            .filter(move |node| node.outputs.contains(output))
+            .map(|node| &node.surface)
+    }
+
+    /// Send `wl_surface.enter` and `wl_surface.leave` to surfaces which moved onto or off of outputs.
+    ///
+    /// A surface is on an output if any part of the surface is inside the output. Every surface on an output is
+    /// also sent the preferred scale and transform of the outputs the surface is on, since the scale or transform
+    /// of an output may have changed. The preferred scale and transform are only sent if changed. Nothing is done
+    /// unless the layout changed since the last update.
+    pub fn update_surface_outputs(&mut self) {
+        if !std::mem::take(&mut self.layout_changed) {
+            return;
+        }
+
+        let mut visible = FxHashMap::<SurfaceIndex, Vec<Output>>::default();
+
+        for output in self.outputs.keys() {
+            let Some(hierarchy) = self.get_graph(output) else {
+                continue;
+            };
+
+            let scale = Scale::from(output.current_scale().fractional_scale());
+            let bounds = Rectangle::from_loc_and_size((0, 0), crate::output::output_size(output));
+
+            for element in hierarchy.elements((0, 0).into(), scale) {
+                let Some(index) = element.surface().and_then(|surface| self.surfaces.get(&surface.id())) else {
+                    continue;
+                };
+
+                if element.geometry(scale).overlaps(bounds) {
+                    visible.entry(*index).or_default().push(output.clone());
+                }
+            }
+        }
+
+        for index in self.surfaces.values().copied().collect::<Vec<_>>() {
+            let outputs = visible.remove(&index).unwrap_or_default();
+            let node = self.get_surface(index).unwrap();
+
+            if node.outputs != outputs {
+                for output in node.outputs.iter().filter(|output| !outputs.contains(output)) {
+                    output.leave(&node.surface);
+                }
+
+                for output in outputs.iter().filter(|output| !node.outputs.contains(output)) {
+                    output.enter(&node.surface);
+                }
+            }
+
+            if let Some(scale) = fractional_scale::preferred_scale(&outputs) {
+                fractional_scale::send_preferred_scale(&node.surface, scale);
+            }
+
+            // TODO: Pick the transform of the output most of the surface is on.
+            if let Some(output) = outputs.first() {
+                fractional_scale::send_preferred_transform(&node.surface, output.current_transform());
+            }
+
+            node.outputs = outputs;
+        }
+    }
+
+    pub fn create_branch(&mut self) -> BranchIndex {
+        BranchIndex(self.forest.insert_with(|index| {
+            SceneNode::Branch(BranchNode {
+                index: BranchIndex(index),
+                id: Id::new(),
+                offset: (0, 0).into(),
+                opacity: 1.0,
+                commit: CommitCounter::default(),
+                clip: None,
+                scale: 1.0,
+            })
+        }))
+    }
+
+    pub fn get_branch(&mut self, index: BranchIndex) -> Option<&mut BranchNode> {
+        self.forest.get_mut(index.0).map(|node| match node.deref_mut() {
+            SceneNode::Branch(node) => node,
+            _ => unreachable!(),
+        })
+    }
+
+    /// Set the opacity of a branch.
+    ///
+    /// The opacity is clamped between `0.0` (transparent) and `1.0` (opaque). Changing the opacity damages the
+    /// descendants of the branch.
+    pub fn set_branch_opacity(&mut self, index: BranchIndex, opacity: f32) {
+        if let Some(branch) = self.get_branch(index) {
+            let opacity = opacity.clamp(0.0, 1.0);
+
+            if branch.opacity != opacity {
+                branch.opacity = opacity;
+                branch.commit.increment();
+            }
+        }
+    }
+
+    /// Set the area the descendants of a branch are visible in.
+    ///
+    /// The clip is relative to the branch and is scaled by the scale of the branch. If the clip is [`None`],
+    /// descendants are only clipped by the branches above this branch.
+    pub fn set_branch_clip(&mut self, index: BranchIndex, clip: Option<Rectangle<i32, Logical>>) {
+        if let Some(branch) = self.get_branch(index) {
+            branch.clip = clip;
+            self.layout_changed = true;
+        }
+    }
+
+    /// Set the scale applied to the descendants of a branch.
+    ///
+    /// The offsets and sizes of descendants are multiplied by the scale, which is useful for zooming or showing
+    /// smaller previews of surfaces. Scales which are not finite and positive are ignored.
+    pub fn set_branch_scale(&mut self, index: BranchIndex, scale: f64) {
+        if !(scale.is_finite() && scale > 0.0) {
+            return;
+        }
+
+        if let Some(branch) = self.get_branch(index) {
+            branch.scale = scale;
+            self.layout_changed = true;
+        }
+    }
+
+    /// Place the node above the other children of the branch.
+    ///
+    /// If the node is already a child of a branch, the node is moved.
+    pub fn branch_add_child(&mut self, branch: BranchIndex, index: NodeIndex) -> Result<(), Error> {
+        let index = self.placed_node(index);
+        self.layout_changed = true;
+        self.forest.reparent(index.into(), Some(branch.into()))
+    }
+
+    /// Move the children of a branch above the children of another branch.
+    ///
+    /// The children keep their order.
+    pub fn branch_move_children(&mut self, from: BranchIndex, to: BranchIndex) {
+        let children = self.forest.children(from.0).collect::<Vec<_>>();
+
+        for child in children {
+            let _ = self.forest.reparent(child, Some(to.0));
+        }
+
+        self.layout_changed = true;
+    }
+
+    pub fn destroy_branch(&mut self, index: BranchIndex) {
+        let _ = self.forest.remove(index.into());
+        self.clear_output_nodes(NodeIndex::Branch(index));
+    }
+
+    /// Create a node filled with a solid color.
+    ///
+    /// The color has premultiplied alpha.
+    pub fn create_solid_color(&mut self, size: Size<i32, Logical>, color: [f32; 4]) -> SolidColorIndex {
+        SolidColorIndex(self.forest.insert_with(|index| {
+            SceneNode::SolidColor(SolidColorNode {
+                index: SolidColorIndex(index),
+                id: Id::new(),
+                size,
+                color,
+                commit: CommitCounter::default(),
+                offset: (0, 0).into(),
+            })
+        }))
+    }
+
+    pub fn get_solid_color(&mut self, index: SolidColorIndex) -> Option<&mut SolidColorNode> {
+        self.forest.get_mut(index.0).map(|node| match node.deref_mut() {
+            SceneNode::SolidColor(node) => node,
+            _ => unreachable!(),
+        })
+    }
+
+    pub fn destroy_solid_color(&mut self, index: SolidColorIndex) {
+        let _ = self.forest.remove(index.into());
+        self.clear_output_nodes(NodeIndex::SolidColor(index));
+    }
+
+    /// Draw server side decorations around a surface tree.
+    ///
+    /// The decorations take the place of the surface tree in the scene, including if the surface tree is presented
+    /// on an output. While decorated, offsetting, restacking or reparenting the surface tree does so to the
+    /// decorations. If the surface tree is already decorated, the existing decorations are returned.
+    pub fn decorate_surface_tree(&mut self, tree: SurfaceTreeIndex, style: DecorationStyle) -> Option<DecorationIndex> {
+        let node = self.get_surface_tree(tree)?;
+
+        if let Some(decoration) = node.decoration {
+            return Some(decoration);
+        }
+
+        let offset = node.offset;
+        node.offset = style.surface_tree_offset();
+
+        let index = DecorationIndex(self.forest.insert_with(|index| {
+            SceneNode::Decoration(DecorationNode {
+                index: DecorationIndex(index),
+                tree,
+                style,
+                ids: std::array::from_fn(|_| Id::new()),
+                commit: CommitCounter::default(),
+                offset,
+            })
+        }));
+
+        // Place the decorations where the surface tree was.
+        if self.forest.get(tree.0).and_then(Node::parent).is_some() {
+            self.forest.insert_before(tree.0, index.0).unwrap();
+        }
+
+        self.forest.reparent(tree.0, Some(index.0)).unwrap();
+        self.replace_output_nodes(NodeIndex::SurfaceTree(tree), Some(NodeIndex::Decoration(index)));
+        self.get_surface_tree(tree).unwrap().decoration = Some(index);
+        self.layout_changed = true;
+
+        Some(index)
+    }
+
+    /// Remove the server side decorations from a surface tree.
+    ///
+    /// The surface tree takes the place of the decorations in the scene.
+    pub fn undecorate_surface_tree(&mut self, tree: SurfaceTreeIndex) {
+        let Some(decoration) = self.get_surface_tree(tree).and_then(|node| node.decoration.take()) else {
+            return;
+        };
+
+        let offset = self.get_decoration(decoration).unwrap().offset;
+        self.get_surface_tree(tree).unwrap().offset = offset;
+
+        if self.forest.get(decoration.0).and_then(Node::parent).is_some() {
+            self.forest.insert_before(decoration.0, tree.0).unwrap();
+        } else {
+            self.forest.reparent(tree.0, None).unwrap();
+        }
+
+        let _ = self.forest.remove(decoration.0);
+        self.replace_output_nodes(NodeIndex::Decoration(decoration), Some(NodeIndex::SurfaceTree(tree)));
+        self.layout_changed = true;
+    }
+
+    pub fn get_decoration(&mut self, index: DecorationIndex) -> Option<&mut DecorationNode> {
+        self.forest.get_mut(index.0).map(|node| match node.deref_mut() {
+            SceneNode::Decoration(node) => node,
+            _ => unreachable!(),
+        })
+    }
+
+    /// Change the appearance of server side decorations.
+    pub fn set_decoration_style(&mut self, index: DecorationIndex, style: DecorationStyle) {
+        let Some(node) = self.get_decoration(index) else {
+            return;
+        };
+
+        if node.style == style {
+            return;
+        }
+
+        node.style = style;
+        node.commit.increment();
+
+        // Make room for a border or title bar of a different size.
+        let tree = node.tree;
+        self.get_surface_tree(tree).unwrap().offset = style.surface_tree_offset();
+        self.layout_changed = true;
+    }
+
+    /// Sets the offset of the node relative to it's parent.
+    ///
+    /// Offsets are in the logical coordinate space and are scaled by the scale of the output when rendering.
+    pub fn set_node_offset(&mut self, index: NodeIndex, offset: Point<i32, Logical>) {
+        let index = self.placed_node(index);
+        self.layout_changed = true;
+
+        match index {
+            NodeIndex::SurfaceTree(index) => {
+                if let Some(surface_tree) = self.get_surface_tree(index) {
+                    surface_tree.offset = offset;
+                }
+            }
+
+            NodeIndex::Branch(index) => {
+                if let Some(branch) = self.get_branch(index) {
+                    branch.offset = offset;
+                }
+            }
+
+            NodeIndex::SolidColor(index) => {
+                if let Some(solid_color) = self.get_solid_color(index) {
+                    solid_color.offset = offset;
+                }
+            }
+
+            NodeIndex::Decoration(index) => {
+                if let Some(decoration) = self.get_decoration(index) {
+                    decoration.offset = offset;
+                }
+            }
+
+            NodeIndex::Snapshot(index) => {
+                if let Some(snapshot) = self.get_snapshot(index) {
+                    snapshot.set_offset(offset);
+                }
+            }
+        }
+    }
+
+    /// Raise the node one node higher relative to the parent.
+    ///
+    /// This will cause the node to farther above the parent. Roots have no siblings, so roots are not moved.
+    pub fn raise_node(&mut self, index: NodeIndex) {
+        let index = self.placed_node(index);
+        let Some(node) = self.forest.get(index.into()) else {
+            return;
+        };
+
+        if let Some(next) = Node::next_sibling(node) {
+            if self.forest.insert_after(next, index.into()).is_ok() {
+                self.layout_changed = true;
+            }
+        }
+    }
+
+    /// Raise the node to become child node placed highest above the parent.
+    pub fn raise_node_to_top(&mut self, index: NodeIndex) {
+        let index = self.placed_node(index);
+        let Some(parent) = self.forest.get(index.into()).and_then(Node::parent) else {
+            return;
+        };
+
+        let top = Node::last_child(self.forest.get(parent).unwrap()).unwrap();
+
+        if top != index.into() && self.forest.insert_after(top, index.into()).is_ok() {
+            self.layout_changed = true;
+        }
+    }
+
+    /// Lower the node one node relative to other children of it's parent.
+    ///
+    /// This will cause the node to be closer but still above the parent node. Roots have no siblings, so roots are
+    /// not moved.
+    pub fn lower_node(&mut self, index: NodeIndex) {
+        let index = self.placed_node(index);
+        let Some(node) = self.forest.get(index.into()) else {
+            return;
+        };
+
+        if let Some(prev) = Node::prev_sibling(node) {
+            if self.forest.insert_before(prev, index.into()).is_ok() {
+                self.layout_changed = true;
+            }
+        }
+    }
+
+    /// Lower the node to be the lowest node above it's parent.
+    pub fn lower_node_to_bottom(&mut self, index: NodeIndex) {
+        let index = self.placed_node(index);
+        let Some(parent) = self.forest.get(index.into()).and_then(Node::parent) else {
+            return;
+        };
+
+        let bottom = Node::first_child(self.forest.get(parent).unwrap()).unwrap();
+
+        if bottom != index.into() && self.forest.insert_before(bottom, index.into()).is_ok() {
+            self.layout_changed = true;
+        }
+    }
+
+    pub fn get_graph(&self, output: &Output) -> Option<Hierarchy<'_>> {
+        let output = self.get_output_index(output)?;
+        let output = self.get_output(output).unwrap();
+        Some(Hierarchy {
+            scene: self,
+            root: output.present?,
+        })
+    }
+
+    /// Get the hierarchy of a surface tree.
+    pub fn get_surface_tree_graph(&self, surface: &wl_surface::WlSurface) -> Option<Hierarchy<'_>> {
+        let index = self.surface_trees.get(&surface.id())?;
+
+        Some(Hierarchy {
+            scene: self,
+            root: NodeIndex::SurfaceTree(*index),
+        })
+    }
+
+    /// The size of the root surface of a surface tree.
+    pub fn surface_tree_size(&self, tree: SurfaceTreeIndex) -> Size<i32, Logical> {
+        let root = match self.forest.get(tree.0).map(Deref::deref) {
+            Some(SceneNode::SurfaceTree(node)) => node.root,
+            _ => return Size::default(),
+        };
+
+        let Some(SceneNode::Surface(node)) = self.forest.get(root.0).map(Deref::deref) else {
+            unreachable!()
+        };
+
+        // TODO: Use the window geometry of toplevels, which excludes client side shadows.
+        surface_size(&node.surface)
+    }
+
+    /// The node which takes the place of the node in the scene.
+    ///
+    /// A decorated surface tree is placed by the decorations.
+    fn placed_node(&self, index: NodeIndex) -> NodeIndex {
+        if let NodeIndex::SurfaceTree(tree) = index {
+            if let Some(SceneNode::SurfaceTree(node)) = self.forest.get(tree.0).map(Deref::deref) {
+                if let Some(decoration) = node.decoration {
+                    return NodeIndex::Decoration(decoration);
+                }
+            }
+        }
+
+        index
+    }
+
+    /// Stop presenting a destroyed node on any output.
+    fn clear_output_nodes(&mut self, node: NodeIndex) {
+        self.replace_output_nodes(node, None);
+    }
+
+    /// Present a different node on the outputs presenting the node.
+    fn replace_output_nodes(&mut self, node: NodeIndex, replacement: Option<NodeIndex>) {
+        self.layout_changed = true;
+
+        let outputs = self.outputs.values().copied().collect::<Vec<_>>();
+
+        for index in outputs {
+            let output = self.get_output_mut(index).unwrap();
+
+            if output.present == Some(node) {
+                output.present = replacement;
+            }
+        }
+    }
+
+    /// Unsets the node which is the output root.
+    ///
+    /// Leave events are sent to the surfaces which were on the output by the next [`Scene::update_surface_outputs`].
+    fn unset_output_root(&mut self, output: &Output) {
+        if let Some(index) = self.get_output_index(output) {
+            let node = self.get_output_mut(index).unwrap();
+
+            if node.present.take().is_some() {
+                self.layout_changed = true;
+            }
+        }
+    }
+}
+
+pub struct SceneGraphElement {
+    id: Id,
+    kind: ElementKind,
+    /// The location of the element on the output.
+    ///
+    /// Damage is tracked using the location, so moving a node damages the old and new area of the element.
+    location: Point<i32, Physical>,
+    /// The size of the visible part of the element, scaled by the branches above the element.
+    size: Size<f64, Logical>,
+    /// The visible part of the element relative to the element, if the element is clipped.
+    crop: Option<Rectangle<f64, Logical>>,
+    /// The product of the scales of the branches above the element.
+    scale: f64,
+    alpha: f32,
+}
+
+enum ElementKind {
+    Surface(wl_surface::WlSurface),
+    SolidColor {
+        color: [f32; 4],
+        commit: CommitCounter,
+    },
+    Snapshot(Rc<SnapshotSurface>),
+    /// Covers the descendants of a translucent branch and draws nothing, so changing the opacity of the branch
+    /// damages the descendants.
+    Opacity {
+        commit: CommitCounter,
+    },
+}
+
+impl SceneGraphElement {
+    /// The surface the element presents, [`None`] if the element is a solid color node.
+    pub fn surface(&self) -> Option<&wl_surface::WlSurface> {
+        match &self.kind {
+            ElementKind::Surface(surface) => Some(surface),
+            ElementKind::SolidColor { .. } | ElementKind::Snapshot(_) | ElementKind::Opacity { .. } => None,
+        }
+    }
+}
+
+impl Element for SceneGraphElement {
+    fn id(&self) -> &Id {
+        &self.id
+    }
+
+    fn current_commit(&self) -> CommitCounter {
+        let surface = match &self.kind {
+            ElementKind::Surface(surface) => surface,
+            ElementKind::SolidColor { commit, .. } | ElementKind::Opacity { commit } => return *commit,
+            // The contents of a snapshot never change.
+            ElementKind::Snapshot(_) => return CommitCounter::default(),
+        };
+
+        compositor::with_states(surface, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>();
+            data.map(|d| d.borrow().current_commit())
+        })
+        .unwrap_or_default()
+    }
+
+    /// The area of the buffer to sample, cropped by the source rectangle of the surface's viewport.
+    fn src(&self) -> Rectangle<f64, Buffer> {
+        let surface = match &self.kind {
+            ElementKind::Surface(surface) => surface,
+            // A solid color is drawn like a single pixel buffer.
+            ElementKind::SolidColor { .. } | ElementKind::Opacity { .. } => {
+                return Rectangle::from_loc_and_size((0., 0.), (1., 1.))
+            }
+            ElementKind::Snapshot(snapshot) => return snapshot.src(self.crop),
+        };
+
+        compositor::with_states(surface, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>();
+            if let Some(data) = data {
+                let data = data.borrow();
+
+                if let Some(view) = data.view() {
+                    let attributes = states.cached_state.current::<SurfaceAttributes>();
+                    let src = match self.crop {
+                        Some(crop) => crop_src(view.src, view.dst.to_f64(), crop),
+                        None => view.src,
+                    };
+
+                    Some(src.to_buffer(
+                        attributes.buffer_scale as f64,
+                        attributes.buffer_transform.into(),
+                        &data.buffer_size().unwrap().to_f64(),
+                    ))
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        })
+        .unwrap_or_default()
+    }
+
+    /// The area the element covers, sized by the destination size of the surface's viewport if set.
+    fn geometry(&self, scale: Scale<f64>) -> Rectangle<i32, Physical> {
+        Rectangle::from_loc_and_size(self.location, self.size.to_physical(scale).to_i32_round())
+    }
+
+    /// The areas of the element which are fully opaque.
+    ///
+    /// The damage tracker does not draw elements under opaque regions and excludes the damage of those elements.
+    fn opaque_regions(&self, scale: Scale<f64>) -> Vec<Rectangle<i32, Physical>> {
+        // Translucent elements show the elements below.
+        if self.alpha < 1.0 {
+            return Vec::new();
+        }
+
+        let visible = self
+            .crop
+            .unwrap_or_else(|| Rectangle::from_loc_and_size((0.0, 0.0), self.size.downscale(self.scale)));
+
+        let regions = match &self.kind {
+            ElementKind::SolidColor { color, .. } if color[3] >= 1.0 => vec![visible],
+            ElementKind::SolidColor { .. } | ElementKind::Opacity { .. } => Vec::new(),
+
+            ElementKind::Surface(surface) => compositor::with_states(surface, |states| {
+                let Some(data) = states.data_map.get::<RendererSurfaceStateUserData>() else {
+                    return Vec::new();
+                };
+                let data = data.borrow();
+
+                // A single pixel buffer is opaque everywhere if the pixel is opaque.
+                if let Some(color) = single_pixel_color(&data) {
+                    return if color[3] >= 1.0 { vec![visible] } else { Vec::new() };
+                }
+
+                data.opaque_regions()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|region| region.to_f64().intersection(visible))
+                    .collect()
+            }),
+
+            ElementKind::Snapshot(snapshot) => match snapshot.color {
+                Some(color) if color[3] >= 1.0 => vec![visible],
+                Some(_) => Vec::new(),
+                None => snapshot
+                    .opaque_regions
+                    .iter()
+                    .filter_map(|region| region.to_f64().intersection(visible))
+                    .collect(),
+            },
+        };
+
+        regions
+            .into_iter()
+            .map(|region| {
+                let region = Rectangle::from_loc_and_size(
+                    (region.loc - visible.loc).upscale(self.scale),
+                    region.size.upscale(self.scale),
+                );
+
+                // Round inwards, since a partially covered pixel is not opaque.
+                region.to_physical_precise_down(scale)
+            })
+            .filter(|region| !region.is_empty())
+            .collect()
+    }
+
+    /// The damage of the element since the commit, relative to the element.
+    ///
+    /// The damage of a surface is cropped by the surface's viewport and the crop of the element, then scaled to
+    /// the size of the element. Other elements are damaged entirely when changed.
+    fn damage_since(&self, scale: Scale<f64>, commit: Option<CommitCounter>) -> Vec<Rectangle<i32, Physical>> {
+        let full_damage = || {
+            if commit != Some(self.current_commit()) {
+                vec![Rectangle::from_loc_and_size((0, 0), self.geometry(scale).size)]
+            } else {
+                Vec::new()
+            }
+        };
+
+        let ElementKind::Surface(surface) = &self.kind else {
+            return full_damage();
+        };
+
+        compositor::with_states(surface, |states| {
+            let Some(data) = states.data_map.get::<RendererSurfaceStateUserData>() else {
+                return full_damage();
+            };
+            let data = data.borrow();
+
+            let (Some(view), Some(buffer_size)) = (data.view(), data.buffer_size()) else {
+                return full_damage();
+            };
+
+            let attributes = states.cached_state.current::<SurfaceAttributes>();
+            let buffer_scale = attributes.buffer_scale as f64;
+            let transform: Transform = attributes.buffer_transform.into();
+            let buffer_size = buffer_size.to_f64().to_buffer(buffer_scale, transform);
+
+            let src = match self.crop {
+                Some(crop) => crop_src(view.src, view.dst.to_f64(), crop),
+                None => view.src,
+            };
+            let (x, y) = (self.size.w / src.size.w, self.size.h / src.size.h);
+
+            data.damage_since(commit)
+                .iter()
+                .filter_map(|damage| {
+                    let damage = damage
+                        .to_f64()
+                        .to_logical(buffer_scale, transform, &buffer_size)
+                        .intersection(src)?;
+                    let damage = Rectangle::<f64, Logical>::from_loc_and_size(
+                        ((damage.loc.x - src.loc.x) * x, (damage.loc.y - src.loc.y) * y),
+                        (damage.size.w * x, damage.size.h * y),
+                    );
+
+                    // Round outwards, since a partially damaged pixel must be redrawn.
+                    Some(damage.to_physical_precise_up(scale))
+                })
+                .collect()
+        })
+    }
+
+    fn alpha(&self) -> f32 {
+        self.alpha
+    }
+}
+
+impl<R: Renderer + ImportAll> RenderElement<R> for SceneGraphElement
+where
+    R::TextureId: 'static,
+{
+    fn draw<'a>(
+        &self,
+        frame: &mut R::Frame<'a>,
+        src: Rectangle<f64, Buffer>,
+        dst: Rectangle<i32, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+    ) -> Result<(), R::Error> {
+        let surface = match &self.kind {
+            ElementKind::Surface(surface) => surface,
+            ElementKind::SolidColor { color, .. } => {
+                return frame.draw_solid(dst, damage, color.map(|channel| channel * self.alpha))
+            }
+
+            ElementKind::Opacity { .. } => return Ok(()),
+
+            ElementKind::Snapshot(snapshot) => {
+                if let Some(color) = snapshot.color {
+                    return frame.draw_solid(dst, damage, color.map(|channel| channel * self.alpha));
+                }
+
+                let id = frame.id();
+                return snapshot
+                    .with_texture::<R, _>(id, |texture| {
+                        frame.render_texture_from_to(texture, src, dst, damage, snapshot.transform, self.alpha)
+                    })
+                    .unwrap_or(Ok(()));
+            }
+        };
+
+        compositor::with_states(surface, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>();
+            if let Some(data) = data {
+                let data = data.borrow();
+
+                // Single pixel buffers are filled instead of uploading a texture.
+                if let Some(color) = single_pixel_color(&data) {
+                    frame.draw_solid(dst, damage, color.map(|channel| channel * self.alpha))?;
+                } else if let Some(texture) = data.texture::<R>(frame.id()) {
+                    // The renderer surface state does not expose the buffer transform.
+                    let transform: Transform = states
+                        .cached_state
+                        .current::<SurfaceAttributes>()
+                        .buffer_transform
+                        .into();
+                    frame.render_texture_from_to(texture, src, dst, damage, transform, self.alpha)?;
+                } else {
+                    dbg!("Not available");
+                    // warn!("trying to render texture from different renderer");
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    fn underlying_storage(&self, _renderer: &mut R) -> Option<UnderlyingStorage> {
+        let surface = match &self.kind {
+            ElementKind::Surface(surface) => surface,
+            ElementKind::SolidColor { .. } | ElementKind::Opacity { .. } => return None,
+            // The contents of a snapshot are copied into textures owned by the renderer.
+            ElementKind::Snapshot(_) => return None,
+        };
+
+        compositor::with_states(surface, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>();
+            data.and_then(|d| d.borrow().buffer().cloned())
+                .map(UnderlyingStorage::Wayland)
+        })
+    }
+}
+
+/// The color of the single pixel buffer attached to a surface, with premultiplied alpha.
+fn single_pixel_color(data: &RendererSurfaceState) -> Option<[f32; 4]> {
+    let pixel = single_pixel_buffer::get_single_pixel_buffer(data.buffer()?).ok()?;
+    Some([pixel.r, pixel.g, pixel.b, pixel.a].map(|channel| channel as f32 / u32::MAX as f32))
+}
+
+/// Crop the source rectangle of a surface to the visible part of the surface.
+///
+/// The crop is relative to the destination of the surface.
+fn crop_src(
+    src: Rectangle<f64, Logical>,
+    dst: Size<f64, Logical>,
+    crop: Rectangle<f64, Logical>,
+) -> Rectangle<f64, Logical> {
+    let (x, y) = (src.size.w / dst.w, src.size.h / dst.h);
+
+    Rectangle::from_loc_and_size(
+        (src.loc.x + crop.loc.x * x, src.loc.y + crop.loc.y * y),
+        (crop.size.w * x, crop.size.h * y),
+    )
+}
+
+/// Remove elements which are entirely hidden by the opaque regions of the elements above.
+///
+/// The elements are ordered from top to bottom. Occluded surfaces are not imported or drawn, and since the
+/// surfaces are not visible the surfaces do not receive frame callbacks every frame.
+fn cull_occluded(elements: &mut Vec<SceneGraphElement>, scale: Scale<f64>) {
+    // The opaque area of the elements above the current element.
+    let mut opaque: Vec<Rectangle<i32, Physical>> = Vec::new();
+
+    elements.retain(|element| {
+        let geometry = element.geometry(scale);
+
+        if geometry.subtract_rects(opaque.iter().copied()).is_empty() {
+            return false;
+        }
+
+        opaque.extend(
+            element
+                .opaque_regions(scale)
+                .into_iter()
+                .map(|region| Rectangle::from_loc_and_size(region.loc + geometry.loc, region.size)),
+        );
+
+        true
+    });
+}
+
+/// The size of a surface, which is the destination size of the surface's viewport if set.
+fn surface_size(surface: &wl_surface::WlSurface) -> Size<i32, Logical> {
+    compositor::with_states(surface, |states| {
+        let data = states.data_map.get::<RendererSurfaceStateUserData>();
+        data.and_then(|data| data.borrow().view()).map(|view| view.dst)
+    })
+    .unwrap_or_default()
+}
+
+/// The offset of a surface from the root of the surface tree, given the offset of the parent surface.
+fn subsurface_offset(states: &SurfaceData, parent: Point<i32, Logical>) -> Point<i32, Logical> {
+    if states.role == Some("subsurface") {
+        parent + states.cached_state.current::<SubsurfaceCachedState>().location
+    } else {
+        parent
+    }
+}
+
+/// A surface found by [`Hierarchy::surface_under`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurfaceHit {
+    /// The surface under the point.
+    pub surface: wl_surface::WlSurface,
+
+    /// The point in the surface-local coordinate space.
+    pub location: Point<f64, Logical>,
+
+    /// The location of the surface relative to the root of the hierarchy.
+    pub surface_location: Point<i32, Logical>,
+
+    /// The surface tree the surface is part of.
+    ///
+    /// This may be used to find the toplevel the surface belongs to.
+    pub tree: SurfaceTreeIndex,
+}
+
+/// A node placed relative to the root of a hierarchy.
+#[derive(Debug, Clone, Copy)]
+struct PlacedNode {
+    index: Index,
+    /// The number of nodes above the node in the hierarchy.
+    depth: usize,
+    location: Point<f64, Logical>,
+    /// The product of the scales of the branches above the node.
+    scale: f64,
+    /// The product of the opacities of the branches above the node.
+    alpha: f32,
+    /// The area the node is visible in, relative to the root of the hierarchy.
+    clip: Option<Rectangle<f64, Logical>>,
+}
+
+impl PlacedNode {
+    /// The part of the area which is not clipped, relative to the node.
+    ///
+    /// The area is in the coordinate space of the node, before the node is scaled.
+    fn visible(&self, area: Rectangle<f64, Logical>) -> Option<Rectangle<f64, Logical>> {
+        let Some(clip) = self.clip else {
+            return Some(area);
+        };
+
+        let clip = Rectangle::from_loc_and_size(
+            (clip.loc - self.location).downscale(self.scale),
+            clip.size.downscale(self.scale),
+        );
+        area.intersection(clip)
+    }
+}
+
+/// A translucent branch whose descendants are being placed by [`Hierarchy::elements`].
+struct TranslucentBranch {
+    depth: usize,
+    id: Id,
+    commit: CommitCounter,
+    alpha: f32,
+    /// The number of elements placed before the branch.
+    first_element: usize,
+}
+
+impl TranslucentBranch {
+    /// Place an element above the elements of the descendants, covering the elements of the descendants.
+    fn push_element(self, elements: &mut Vec<SceneGraphElement>, scale: Scale<f64>) {
+        let Some(area) = elements[self.first_element..]
+            .iter()
+            .map(|element| element.geometry(scale))
+            .reduce(|area, geometry| area.merge(geometry))
+        else {
+            return;
+        };
+
+        elements.push(SceneGraphElement {
+            id: self.id,
+            kind: ElementKind::Opacity { commit: self.commit },
+            location: area.loc,
+            size: area.size.to_f64().to_logical(scale),
+            crop: None,
+            scale: 1.0,
+            alpha: self.alpha,
+        });
+    }
+}
+
+pub struct Hierarchy<'scene> {
+    scene: &'scene Scene,
+    root: NodeIndex,
+}
+
+impl Hierarchy<'_> {
+    /// Find the topmost surface which accepts input at the point.
+    ///
+    /// The point is relative to the root of the hierarchy. Surfaces are tested from top to bottom and only accept
+    /// input inside of the surface's input region. Solid color nodes do not accept input, and surfaces do not
+    /// accept input where the surface is clipped by a branch.
+    pub fn surface_under(&self, point: Point<f64, Logical>) -> Option<SurfaceHit> {
+        self.nodes().into_iter().rev().find_map(|placed| {
+            let node = self.scene.forest.get(placed.index).unwrap();
+            let SceneNode::Surface(surface_node) = node.deref() else {
+                return None;
+            };
+
+            if placed.clip.map_or(false, |clip| !clip.contains(point)) {
+                return None;
+            }
+
+            let surface = &surface_node.surface;
+            let local = (point - placed.location).downscale(placed.scale);
+            let contains = compositor::with_states(surface, |states| {
+                let data = states.data_map.get::<RendererSurfaceStateUserData>();
+                data.map_or(false, |data| {
+                    data.borrow()
+                        .contains_point(&states.cached_state.current::<SurfaceAttributes>(), local)
+                })
+            });
+
+            if !contains {
+                return None;
+            }
+
+            // Surface nodes are always children of the node for the surface tree.
+            let tree = SurfaceTreeIndex(Node::parent(node).unwrap());
+
+            Some(SurfaceHit {
+                surface: surface.clone(),
+                location: local,
+                surface_location: placed.location.to_i32_round(),
+                tree,
+            })
+        })
+    }
+
+    /// The elements in the hierarchy, ordered from top to bottom.
+    ///
+    /// The offsets of nodes are scaled to place the elements in the physical coordinate space. The surfaces are
+    /// not imported into a renderer, and elements occluded by the elements above are included.
+    fn elements(&self, location: Point<i32, Physical>, scale: Scale<f64>) -> Vec<SceneGraphElement> {
+        let mut elements = Vec::new();
+        // The translucent branches above the current node.
+        let mut translucent: Vec<TranslucentBranch> = Vec::new();
+
+        for placed in self.nodes() {
+            // The descendants of a translucent branch were placed once a node which is not a descendant is reached.
+            while translucent.last().map_or(false, |branch| branch.depth >= placed.depth) {
+                let branch = translucent.pop().unwrap();
+                branch.push_element(&mut elements, scale);
+            }
+
+            let first_element = elements.len();
+
+            // Place an element covering the area of the node, in the coordinate space of the node.
+            let mut push = |id: Id, kind: ElementKind, area: Rectangle<f64, Logical>| {
+                // Elements which are entirely clipped are not rendered.
+                let Some(visible) = placed.visible(area) else {
+                    return;
+                };
+
+                let crop =
+                    (visible != area).then(|| Rectangle::from_loc_and_size(visible.loc - area.loc, visible.size));
+                let offset = placed.location + visible.loc.upscale(placed.scale);
+
+                elements.push(SceneGraphElement {
+                    id,
+                    kind,
+                    location: location + offset.to_physical(scale).to_i32_round(),
+                    size: visible.size.upscale(placed.scale),
+                    crop,
+                    scale: placed.scale,
+                    alpha: placed.alpha,
+                });
+            };
+
+            match self.scene.forest.get(placed.index).unwrap().deref() {
+                SceneNode::Output(_) => unreachable!(),
+                SceneNode::SurfaceTree(_) => (),
+
+                SceneNode::Branch(node) => {
+                    if node.opacity < 1.0 {
+                        translucent.push(TranslucentBranch {
+                            depth: placed.depth,
+                            id: node.id.clone(),
+                            commit: node.commit,
+                            alpha: placed.alpha * node.opacity,
+                            first_element,
+                        });
+                    }
+                }
+
+                SceneNode::Surface(node) => push(
+                    Id::from_wayland_resource(&node.surface),
+                    ElementKind::Surface(node.surface.clone()),
+                    Rectangle::from_loc_and_size((0.0, 0.0), surface_size(&node.surface).to_f64()),
+                ),
+
+                SceneNode::SolidColor(node) => push(
+                    node.id.clone(),
+                    ElementKind::SolidColor {
+                        color: node.color,
+                        commit: node.commit,
+                    },
+                    Rectangle::from_loc_and_size((0.0, 0.0), node.size.to_f64()),
+                ),
+
+                SceneNode::Snapshot(node) => {
+                    for surface in node.surfaces() {
+                        push(
+                            surface.id.clone(),
+                            ElementKind::Snapshot(surface.clone()),
+                            Rectangle::from_loc_and_size(surface.offset.to_f64(), surface.size.to_f64()),
+                        );
+                    }
+                }
+
+                // The decorations are below the surface tree since the tree is a child of the decorations.
+                SceneNode::Decoration(node) => {
+                    let size = self.scene.surface_tree_size(node.tree);
+
+                    for (id, rect, color) in node.rectangles(size) {
+                        push(
+                            id.clone(),
+                            ElementKind::SolidColor {
+                                color,
+                                commit: node.commit,
+                            },
+                            rect.to_f64(),
+                        );
+                    }
+                }
+            }
+        }
+
+        while let Some(branch) = translucent.pop() {
+            branch.push_element(&mut elements, scale);
+        }
+
+        // Children are stacked from bottom to top, but smithay expects the render elements top to bottom.
+        elements.reverse();
+        elements
+    }
+
+    /// Every node in the hierarchy placed relative to the root, ordered from bottom to top.
+    ///
+    /// The opacity, clip and scale of branches are inherited by every node below the branch.
+    fn nodes(&self) -> Vec<PlacedNode> {
+        let Some(iter) = self.scene.forest.preorder_traverse(self.root.into()) else {
+            return Vec::new();
+        };
+
+        // The placement of each node on the path from the root to the current node.
+        let mut stack: Vec<PlacedNode> = Vec::new();
+        let mut nodes = Vec::new();
+
+        for edge in iter {
+            let index = match edge {
+                Edge::Start(index) => index,
+                Edge::End(_) => {
+                    stack.pop();
+                    continue;
+                }
+            };
+
+            let parent = stack.last().copied().unwrap_or(PlacedNode {
+                index,
+                depth: 0,
+                location: Point::default(),
+                scale: 1.0,
+                alpha: 1.0,
+                clip: None,
+            });
+
+            let node = self.scene.forest.get(index).unwrap();
+            let offset = match node.deref() {
+                SceneNode::Output(_) => unreachable!(),
+                SceneNode::SurfaceTree(node) => node.offset,
+                SceneNode::Branch(node) => node.offset,
+                SceneNode::Surface(node) => node.offset,
+                SceneNode::SolidColor(node) => node.offset,
+                SceneNode::Decoration(node) => node.offset,
+                SceneNode::Snapshot(node) => node.offset(),
+            };
+
+            let mut placed = PlacedNode {
+                index,
+                depth: stack.len(),
+                location: parent.location + offset.to_f64().upscale(parent.scale),
+                ..parent
+            };
+
+            if let SceneNode::Branch(branch) = node.deref() {
+                placed.scale *= branch.scale;
+                placed.alpha *= branch.opacity;
+
+                // The clip is in the coordinate space of the descendants, so the clip is scaled with the branch.
+                if let Some(clip) = branch.clip {
+                    let clip = Rectangle::from_loc_and_size(
+                        placed.location + clip.loc.to_f64().upscale(placed.scale),
+                        clip.size.to_f64().upscale(placed.scale),
+                    );
+
+                    placed.clip = Some(match parent.clip {
+                        Some(parent) => parent.intersection(clip).unwrap_or_default(),
+                        None => clip,
+                    });
+                }
+            }
+
+            stack.push(placed);
+            nodes.push(placed);
+        }
+
+        nodes
+    }
+
+    /// The smallest rectangle containing every element in the hierarchy when rendered at the scale.
+    pub fn bounding_box(&self, scale: Scale<f64>) -> Rectangle<i32, Physical> {
+        self.elements((0, 0).into(), scale)
+            .iter()
+            .map(|element| element.geometry(scale))
+            .reduce(|bbox, geometry| bbox.merge(geometry))
+            .unwrap_or_default()
+    }
+}
+
+impl<R: Renderer + ImportAll + ImportMem> AsRenderElements<R> for Hierarchy<'_>
+where
+    R::TextureId: 'static,
+{
+    type RenderElement = SceneGraphElement;
+
+    fn render_elements<C: From<Self::RenderElement>>(
+        &self,
+        renderer: &mut R,
+        location: Point<i32, Physical>,
+        scale: Scale<f64>,
+        alpha: f32,
+    ) -> Vec<C> {
+        let mut elements = self.elements(location, scale);
+
+        for elem in &mut elements {
+            elem.alpha *= alpha;
+        }
+
+        // Only rendering skips occluded elements. Surfaces which are occluded are still on the output.
+        cull_occluded(&mut elements, scale);
+
+        elements
+            .into_iter()
+            .map(|elem| {
+                // Solid colors and single pixel buffers are filled, so there is nothing to import.
+                let import = elem.surface().filter(|surface| {
+                    compositor::with_states(surface, |states| {
+                        let data = states.data_map.get::<RendererSurfaceStateUserData>();
+                        data.map_or(true, |data| single_pixel_color(&data.borrow()).is_none())
+                    })
+                });
+
+                if let Some(surface) = import {
+                    smithay::backend::renderer::utils::import_surface_tree(renderer, surface)
+                        .expect("Failed to import");
+                }
+
+                // Snapshots keep the textures of the snapshot, since the surfaces may no longer exist.
+                if let ElementKind::Snapshot(snapshot) = &elem.kind {
+                    snapshot.import(renderer);
+                }
+
+                elem
+            })
+            .map(C::from)
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+enum SceneNode {
+    Output(OutputNode),
+    SurfaceTree(SurfaceTreeNode),
+    Surface(SurfaceNode),
+    Branch(BranchNode),
+    SolidColor(SolidColorNode),
+    Decoration(DecorationNode),
+    Snapshot(SnapshotNode),
+}
+
+impl From<BranchIndex> for Index {
+    fn from(value: BranchIndex) -> Self {
+        value.0
+    }
+}
+
+impl From<SurfaceTreeIndex> for Index {
+    fn from(value: SurfaceTreeIndex) -> Self {
+        value.0
+    }
+}
+
+impl From<SurfaceIndex> for Index {
+    fn from(value: SurfaceIndex) -> Self {
+        value.0
+    }
+}
+
+impl From<SolidColorIndex> for Index {
+    fn from(value: SolidColorIndex) -> Self {
+        value.0
+    }
+}
+
+impl From<DecorationIndex> for Index {
+    fn from(value: DecorationIndex) -> Self {
+        value.0
+    }
+}
+
+impl From<SnapshotIndex> for Index {
+    fn from(value: SnapshotIndex) -> Self {
+        value.0
+    }
+}
+
+impl From<NodeIndex> for Index {
+    fn from(value: NodeIndex) -> Self {
+        match value {
+            NodeIndex::SurfaceTree(index) => index.into(),
+            NodeIndex::Branch(index) => index.into(),
+            NodeIndex::SolidColor(index) => index.into(),
+            NodeIndex::Decoration(index) => index.into(),
+            NodeIndex::Snapshot(index) => index.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::{
+        backend::renderer::element::Element,
+        output::{Output, PhysicalProperties, Subpixel},
+        utils::{Logical, Physical, Rectangle, Scale},
+    };
+    use wayland_client::protocol::wl_surface::WlSurface;
+
+    use crate::testing::{TestClient, TestServer};
+
+    use super::{
+        cull_occluded, BranchIndex, DecorationIndex, DecorationStyle, ElementKind, Hierarchy, Node, NodeIndex, Scene,
+        SceneGraphElement, SolidColorIndex, SurfaceHit, SurfaceIndex, SurfaceTreeIndex,
+    };
+
+    const OPAQUE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+    const BORDER: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+    const TITLE_BAR: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const STYLE: DecorationStyle = DecorationStyle {
+        border_width: 2,
+        border_color: BORDER,
+        title_bar_height: 10,
+        title_bar_color: TITLE_BAR,
+    };
+
+    fn solid_color(scene: &mut Scene, parent: BranchIndex, offset: (i32, i32), size: (i32, i32)) -> SolidColorIndex {
+        let index = scene.create_solid_color(size.into(), OPAQUE);
+        scene.branch_add_child(parent, NodeIndex::SolidColor(index)).unwrap();
+        scene.set_node_offset(NodeIndex::SolidColor(index), offset.into());
+        index
+    }
+
+    fn elements(scene: &Scene, root: BranchIndex) -> Vec<SceneGraphElement> {
+        let hierarchy = Hierarchy {
+            scene,
+            root: NodeIndex::Branch(root),
+        };
+        hierarchy.elements((0, 0).into(), Scale::from(1.0))
+    }
+
+    /// The elements of solid color nodes, ignoring the elements damaging translucent branches.
+    fn solid_elements(scene: &Scene, root: BranchIndex) -> Vec<SceneGraphElement> {
+        elements(scene, root)
+            .into_iter()
+            .filter(|element| matches!(element.kind, ElementKind::SolidColor { .. }))
+            .collect()
+    }
+
+    fn geometry(element: &SceneGraphElement) -> Rectangle<i32, Physical> {
+        element.geometry(Scale::from(1.0))
+    }
+
+    /// Create a surface tree for a new surface of the client.
+    fn surface_tree(server: &mut TestServer, client: &mut TestClient) -> (WlSurface, SurfaceTreeIndex) {
+        let root = client.create_surface();
+        client.roundtrip(server);
+
+        let surface = client.server_surface(server, &root);
+        let tree = server.comp().scene.create_surface_tree(surface);
+        (root, tree)
+    }
+
+    /// Create a surface tree with a buffer of the size and add the tree to the branch.
+    fn mapped_surface_tree(
+        server: &mut TestServer,
+        client: &mut TestClient,
+        branch: BranchIndex,
+        offset: (i32, i32),
+        size: (i32, i32),
+    ) -> (WlSurface, SurfaceTreeIndex) {
+        let (root, tree) = surface_tree(server, client);
+        let buffer = client.create_buffer(size.0, size.1, 0xffffffff);
+        root.attach(Some(&buffer), 0, 0);
+        root.commit();
+        client.roundtrip(server);
+
+        let scene = &mut server.comp().scene;
+        scene.branch_add_child(branch, NodeIndex::SurfaceTree(tree)).unwrap();
+        scene.set_node_offset(NodeIndex::SurfaceTree(tree), offset.into());
+        (root, tree)
+    }
+
+    fn surface_under(server: &mut TestServer, root: BranchIndex, point: (f64, f64)) -> Option<SurfaceHit> {
+        let hierarchy = Hierarchy {
+            scene: &server.comp().scene,
+            root: NodeIndex::Branch(root),
+        };
+        hierarchy.surface_under(point.into())
+    }
+
+    /// The rectangles of the decorations, relative to the decorations.
+    fn decoration_rectangles(
+        scene: &mut Scene,
+        decoration: DecorationIndex,
+    ) -> Vec<(Rectangle<i32, Logical>, [f32; 4])> {
+        let tree = scene.get_decoration(decoration).unwrap().tree();
+        let size = scene.surface_tree_size(tree);
+        let node = scene.get_decoration(decoration).unwrap();
+        node.rectangles(size).map(|(_, rect, color)| (rect, color)).collect()
+    }
+
+    fn rectangle(x: i32, y: i32, width: i32, height: i32) -> Rectangle<i32, Logical> {
+        Rectangle::from_loc_and_size((x, y), (width, height))
+    }
+
+    /// The nodes of the client's surfaces.
+    fn surface_indices(server: &mut TestServer, client: &TestClient, surfaces: &[&WlSurface]) -> Vec<SurfaceIndex> {
+        surfaces
+            .iter()
+            .map(|surface| {
+                let surface = client.server_surface(server, surface);
+                server.comp().scene.get_surface_index(surface).unwrap()
+            })
+            .collect()
+    }
+
+    /// The nodes of the surfaces in the surface tree from bottom to top.
+    fn stack(scene: &Scene, tree: SurfaceTreeIndex) -> Vec<SurfaceIndex> {
+        scene.forest.children(tree.0).map(SurfaceIndex).collect()
+    }
+
+    #[test]
+    fn inherited_opacity() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        let child = scene.create_branch();
+        scene.branch_add_child(root, NodeIndex::Branch(child)).unwrap();
+        let color = solid_color(&mut scene, child, (0, 0), (10, 10));
+
+        scene.set_branch_opacity(root, 0.5);
+        scene.set_branch_opacity(child, 0.5);
+
+        let hierarchy = Hierarchy {
+            scene: &scene,
+            root: NodeIndex::Branch(root),
+        };
+        let placed = hierarchy
+            .nodes()
+            .into_iter()
+            .find(|placed| placed.index == color.into())
+            .unwrap();
+        assert_eq!(placed.alpha, 0.25);
+
+        let elements = solid_elements(&scene, root);
+        assert_eq!(elements.len(), 1);
+        assert_eq!(elements[0].alpha(), 0.25);
+        // A translucent element does not hide the elements below.
+        assert!(elements[0].opaque_regions(Scale::from(1.0)).is_empty());
+    }
+
+    /// The opacity is clamped and an opaque branch is not drawn translucent.
+    #[test]
+    fn opacity_clamped() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        solid_color(&mut scene, root, (0, 0), (10, 10));
+
+        scene.set_branch_opacity(root, 2.0);
+        assert_eq!(scene.get_branch(root).unwrap().opacity(), 1.0);
+
+        scene.set_branch_opacity(root, -1.0);
+        assert_eq!(scene.get_branch(root).unwrap().opacity(), 0.0);
+    }
+
+    /// Changing the opacity of a branch damages the area of the descendants.
+    #[test]
+    fn opacity_damage() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        let child = scene.create_branch();
+        scene.branch_add_child(root, NodeIndex::Branch(child)).unwrap();
+        solid_color(&mut scene, child, (10, 10), (10, 10));
+        solid_color(&mut scene, root, (50, 50), (10, 10));
+
+        scene.set_branch_opacity(child, 0.5);
+
+        let opacity = |scene: &Scene| {
+            elements(scene, root)
+                .into_iter()
+                .find(|element| matches!(element.kind, ElementKind::Opacity { .. }))
+                .unwrap()
+        };
+
+        let before = opacity(&scene);
+        // Only the descendants of the translucent branch are covered.
+        assert_eq!(geometry(&before), Rectangle::from_loc_and_size((10, 10), (10, 10)));
+
+        let commit = before.current_commit();
+        assert!(before.damage_since(Scale::from(1.0), Some(commit)).is_empty());
+
+        // Setting the same opacity again does not damage.
+        scene.set_branch_opacity(child, 0.5);
+        assert_eq!(opacity(&scene).current_commit(), commit);
+
+        scene.set_branch_opacity(child, 0.25);
+        let after = opacity(&scene);
+        assert_eq!(after.id(), before.id());
+        assert_eq!(
+            after.damage_since(Scale::from(1.0), Some(commit)),
+            vec![Rectangle::from_loc_and_size((0, 0), (10, 10))]
+        );
+    }
+
+    #[test]
+    fn inherited_scale() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        let child = scene.create_branch();
+        scene.branch_add_child(root, NodeIndex::Branch(child)).unwrap();
+        scene.set_node_offset(NodeIndex::Branch(child), (10, 10).into());
+        solid_color(&mut scene, child, (5, 5), (10, 10));
+
+        scene.set_branch_scale(root, 2.0);
+        scene.set_branch_scale(child, 0.5);
+
+        // The offset of the child branch is scaled by the root, and the offset of the solid color by both.
+        let elements = solid_elements(&scene, root);
+        assert_eq!(elements.len(), 1);
+        assert_eq!(geometry(&elements[0]), Rectangle::from_loc_and_size((25, 25), (10, 10)));
+
+        scene.set_branch_scale(child, 1.0);
+        let elements = solid_elements(&scene, root);
+        assert_eq!(geometry(&elements[0]), Rectangle::from_loc_and_size((30, 30), (20, 20)));
+    }
+
+    #[test]
+    fn invalid_scale_ignored() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        scene.set_branch_scale(root, 2.0);
+
+        for scale in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            scene.set_branch_scale(root, scale);
+            assert_eq!(scene.get_branch(root).unwrap().scale(), 2.0);
+        }
+    }
+
+    #[test]
+    fn clip() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        solid_color(&mut scene, root, (10, 10), (10, 10));
+        // Entirely outside of the clip.
+        solid_color(&mut scene, root, (30, 30), (10, 10));
+
+        scene.set_branch_clip(root, Some(Rectangle::from_loc_and_size((0, 0), (15, 15))));
+
+        let elements = solid_elements(&scene, root);
+        assert_eq!(elements.len(), 1);
+        assert_eq!(geometry(&elements[0]), Rectangle::from_loc_and_size((10, 10), (5, 5)));
+        assert_eq!(
+            elements[0].crop,
+            Some(Rectangle::from_loc_and_size((0.0, 0.0), (5.0, 5.0)))
+        );
+    }
+
+    /// The clips of nested branches intersect, and a clip is scaled by the branch.
+    #[test]
+    fn inherited_clip() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        let child = scene.create_branch();
+        scene.branch_add_child(root, NodeIndex::Branch(child)).unwrap();
+        let color = solid_color(&mut scene, child, (5, 5), (10, 10));
+
+        scene.set_branch_clip(root, Some(Rectangle::from_loc_and_size((0, 0), (25, 25))));
+        scene.set_branch_scale(child, 2.0);
+        scene.set_branch_clip(child, Some(Rectangle::from_loc_and_size((0, 0), (10, 20))));
+
+        let hierarchy = Hierarchy {
+            scene: &scene,
+            root: NodeIndex::Branch(root),
+        };
+        let placed = hierarchy
+            .nodes()
+            .into_iter()
+            .find(|placed| placed.index == color.into())
+            .unwrap();
+        assert_eq!(
+            placed.clip,
+            Some(Rectangle::from_loc_and_size((0.0, 0.0), (20.0, 25.0)))
+        );
+
+        let elements = solid_elements(&scene, root);
+        assert_eq!(elements.len(), 1);
+        assert_eq!(geometry(&elements[0]), Rectangle::from_loc_and_size((10, 10), (10, 15)));
+        assert_eq!(
+            elements[0].crop,
+            Some(Rectangle::from_loc_and_size((0.0, 0.0), (5.0, 7.5)))
+        );
+    }
+
+    #[test]
+    fn opaque_regions() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        solid_color(&mut scene, root, (10, 10), (10, 10));
+        let translucent = solid_color(&mut scene, root, (30, 30), (10, 10));
+        scene
+            .get_solid_color(translucent)
+            .unwrap()
+            .set_color([0.5, 0.5, 0.5, 0.5]);
+
+        let elements = solid_elements(&scene, root);
+        // The regions are relative to the element.
+        assert_eq!(
+            elements[1].opaque_regions(Scale::from(1.0)),
+            vec![Rectangle::from_loc_and_size((0, 0), (10, 10))]
+        );
+        assert_eq!(
+            elements[1].opaque_regions(Scale::from(2.0)),
+            vec![Rectangle::from_loc_and_size((0, 0), (20, 20))]
+        );
+        assert!(elements[0].opaque_regions(Scale::from(1.0)).is_empty());
+
+        // A translucent branch makes every descendant translucent.
+        scene.set_branch_opacity(root, 0.5);
+        let elements = solid_elements(&scene, root);
+        assert!(elements[1].opaque_regions(Scale::from(1.0)).is_empty());
+    }
+
+    /// Only the visible part of a clipped element is opaque, and the region is scaled with the element.
+    #[test]
+    fn opaque_regions_clipped_and_scaled() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        solid_color(&mut scene, root, (0, 0), (10, 10));
+
+        scene.set_branch_clip(root, Some(Rectangle::from_loc_and_size((0, 0), (5, 10))));
+        let elements = solid_elements(&scene, root);
+        assert_eq!(
+            elements[0].opaque_regions(Scale::from(1.0)),
+            vec![Rectangle::from_loc_and_size((0, 0), (5, 10))]
+        );
+
+        scene.set_branch_clip(root, None);
+        scene.set_branch_scale(root, 2.0);
+        let elements = solid_elements(&scene, root);
+        assert_eq!(
+            elements[0].opaque_regions(Scale::from(1.0)),
+            vec![Rectangle::from_loc_and_size((0, 0), (20, 20))]
+        );
+    }
+
+    #[test]
+    fn cull_hidden_elements() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        let hidden = solid_color(&mut scene, root, (0, 0), (10, 10));
+        let partial = solid_color(&mut scene, root, (15, 15), (10, 10));
+        let top = solid_color(&mut scene, root, (0, 0), (20, 20));
+
+        let mut elements = elements(&scene, root);
+        cull_occluded(&mut elements, Scale::from(1.0));
+
+        let ids = elements.iter().map(|element| element.id().clone()).collect::<Vec<_>>();
+        let mut id = |index: SolidColorIndex| scene.get_solid_color(index).unwrap().id.clone();
+        assert_eq!(ids, vec![id(top), id(partial)]);
+        assert!(!ids.contains(&id(hidden)));
+    }
+
+    #[test]
+    fn cull_translucent_keeps_below() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        solid_color(&mut scene, root, (0, 0), (10, 10));
+        let top = solid_color(&mut scene, root, (0, 0), (10, 10));
+        scene.get_solid_color(top).unwrap().set_color([0.5, 0.5, 0.5, 0.5]);
+
+        let mut elements = elements(&scene, root);
+        cull_occluded(&mut elements, Scale::from(1.0));
+        assert_eq!(elements.len(), 2);
+    }
+
+    /// Occluded elements are only culled when rendering, so the surfaces stay on the output.
+    #[test]
+    fn occluded_elements_listed() {
+        let mut scene = Scene::new();
+        let root = scene.create_branch();
+        solid_color(&mut scene, root, (0, 0), (10, 10));
+        solid_color(&mut scene, root, (0, 0), (10, 10));
+
+        assert_eq!(elements(&scene, root).len(), 2);
+    }
+
+    /// Subsurfaces are given nodes when the parent is committed and stacked above the parent in the order the
+    /// subsurfaces were created.
+    #[test]
+    fn subsurfaces_added() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let (root, tree) = surface_tree(&mut server, &mut client);
+
+        let first = client.create_surface();
+        let second = client.create_surface();
+        client.create_subsurface(&first, &root);
+        client.create_subsurface(&second, &root);
+        root.commit();
+        client.roundtrip(&mut server);
+
+        let surfaces = surface_indices(&mut server, &client, &[&root, &first, &second]);
+        let scene = &mut server.comp().scene;
+        assert_eq!(stack(scene, tree), surfaces);
+
+        let node = scene.get_surface_tree(tree).unwrap();
+        assert_eq!(node.root(), surfaces[0]);
+        assert_eq!(node.base(), surfaces[0]);
+        assert_eq!(node.top(), surfaces[2]);
+    }
+
+    #[test]
+    fn subsurfaces_restacked() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let (root, tree) = surface_tree(&mut server, &mut client);
+
+        let first = client.create_surface();
+        let second = client.create_surface();
+        let first_subsurface = client.create_subsurface(&first, &root);
+        let second_subsurface = client.create_subsurface(&second, &root);
+        root.commit();
+        client.roundtrip(&mut server);
+
+        let [root_index, first_index, second_index] = surface_indices(&mut server, &client, &[&root, &first, &second])
+            .try_into()
+            .unwrap();
+
+        second_subsurface.place_below(&root);
+        root.commit();
+        client.roundtrip(&mut server);
+
+        let scene = &mut server.comp().scene;
+        assert_eq!(stack(scene, tree), [second_index, root_index, first_index]);
+        let node = scene.get_surface_tree(tree).unwrap();
+        assert_eq!(node.root(), root_index);
+        assert_eq!(node.base(), second_index);
+        assert_eq!(node.top(), first_index);
+
+        first_subsurface.place_above(&second);
+        root.commit();
+        client.roundtrip(&mut server);
+
+        let scene = &mut server.comp().scene;
+        assert_eq!(stack(scene, tree), [second_index, first_index, root_index]);
+        let node = scene.get_surface_tree(tree).unwrap();
+        assert_eq!(node.base(), second_index);
+        assert_eq!(node.top(), root_index);
+    }
+
+    /// The offset of each surface is relative to the root surface, including subsurfaces of subsurfaces.
+    #[test]
+    fn subsurface_positions() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let (root, _) = surface_tree(&mut server, &mut client);
+
+        let child = client.create_surface();
+        let grandchild = client.create_surface();
+        let child_subsurface = client.create_subsurface(&child, &root);
+        let grandchild_subsurface = client.create_subsurface(&grandchild, &child);
+        child_subsurface.set_position(10, 20);
+        grandchild_subsurface.set_position(5, 5);
+        grandchild.commit();
+        child.commit();
+        root.commit();
+        client.roundtrip(&mut server);
+
+        let surfaces = surface_indices(&mut server, &client, &[&root, &child, &grandchild]);
+        let scene = &mut server.comp().scene;
+        let offsets = surfaces
+            .into_iter()
+            .map(|index| scene.get_surface(index).unwrap().offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, [(0, 0).into(), (10, 20).into(), (15, 25).into()]);
+
+        child_subsurface.set_position(-10, 0);
+        child.commit();
+        root.commit();
+        client.roundtrip(&mut server);
+
+        let surfaces = surface_indices(&mut server, &client, &[&child, &grandchild]);
+        let scene = &mut server.comp().scene;
+        assert_eq!(scene.get_surface(surfaces[0]).unwrap().offset, (-10, 0).into());
+        assert_eq!(scene.get_surface(surfaces[1]).unwrap().offset, (-5, 5).into());
+    }
+
+    /// Destroying a subsurface removes the nodes of the surface and the subsurfaces of the surface.
+    #[test]
+    fn subsurfaces_removed() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let (root, tree) = surface_tree(&mut server, &mut client);
+
+        let first = client.create_surface();
+        let nested = client.create_surface();
+        let second = client.create_surface();
+        let first_subsurface = client.create_subsurface(&first, &root);
+        client.create_subsurface(&nested, &first);
+        client.create_subsurface(&second, &root);
+        first.commit();
+        root.commit();
+        client.roundtrip(&mut server);
+        assert_eq!(stack(&server.comp().scene, tree).len(), 4);
+
+        first_subsurface.destroy();
+        root.commit();
+        client.roundtrip(&mut server);
+
+        let remaining = surface_indices(&mut server, &client, &[&root, &second]);
+        let removed = [&first, &nested].map(|surface| client.server_surface(&server, surface));
+        let scene = &mut server.comp().scene;
+        assert_eq!(stack(scene, tree), remaining);
+        assert_eq!(scene.get_surface_tree(tree).unwrap().top(), remaining[1]);
+
+        for surface in removed {
+            assert_eq!(scene.get_surface_index(surface), None);
+        }
+    }
+
+    /// The topmost surface under the point is found, and the location is relative to the surface.
+    #[test]
+    fn surface_under_topmost() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let root = server.comp().scene.create_branch();
+        let (below, below_tree) = mapped_surface_tree(&mut server, &mut client, root, (0, 0), (100, 100));
+        let (above, above_tree) = mapped_surface_tree(&mut server, &mut client, root, (50, 50), (100, 100));
+
+        let hit = surface_under(&mut server, root, (75.0, 75.0)).unwrap();
+        assert_eq!(hit.surface, client.server_surface(&server, &above));
+        assert_eq!(hit.tree, above_tree);
+        assert_eq!(hit.location, (25.0, 25.0).into());
+        assert_eq!(hit.surface_location, (50, 50).into());
+
+        let hit = surface_under(&mut server, root, (25.0, 25.0)).unwrap();
+        assert_eq!(hit.surface, client.server_surface(&server, &below));
+        assert_eq!(hit.tree, below_tree);
+        assert_eq!(hit.location, (25.0, 25.0).into());
+
+        // Raising the lower surface tree puts the tree on top.
+        server
+            .comp()
+            .scene
+            .raise_node_to_top(NodeIndex::SurfaceTree(below_tree));
+        let hit = surface_under(&mut server, root, (75.0, 75.0)).unwrap();
+        assert_eq!(hit.surface, client.server_surface(&server, &below));
+    }
+
+    /// Subsurfaces are found at the offset of the subsurface and belong to the surface tree of the root surface.
+    #[test]
+    fn surface_under_subsurface() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let root = server.comp().scene.create_branch();
+        let (surface, tree) = mapped_surface_tree(&mut server, &mut client, root, (100, 100), (100, 100));
+
+        let child = client.create_surface();
+        let subsurface = client.create_subsurface(&child, &surface);
+        subsurface.set_position(10, 20);
+        let buffer = client.create_buffer(20, 20, 0xffffffff);
+        child.attach(Some(&buffer), 0, 0);
+        child.commit();
+        surface.commit();
+        client.roundtrip(&mut server);
+
+        let hit = surface_under(&mut server, root, (115.0, 125.0)).unwrap();
+        assert_eq!(hit.surface, client.server_surface(&server, &child));
+        assert_eq!(hit.tree, tree);
+        assert_eq!(hit.location, (5.0, 5.0).into());
+        assert_eq!(hit.surface_location, (110, 120).into());
+
+        let hit = surface_under(&mut server, root, (105.0, 105.0)).unwrap();
+        assert_eq!(hit.surface, client.server_surface(&server, &surface));
+        assert_eq!(hit.location, (5.0, 5.0).into());
+    }
+
+    /// Input outside the input region of a surface goes to the surface below.
+    #[test]
+    fn surface_under_input_region() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let root = server.comp().scene.create_branch();
+        let (below, _) = mapped_surface_tree(&mut server, &mut client, root, (0, 0), (100, 100));
+        let (above, _) = mapped_surface_tree(&mut server, &mut client, root, (0, 0), (100, 100));
+
+        let region = client.create_region(&[(0, 0, 50, 50)]);
+        above.set_input_region(Some(&region));
+        above.commit();
+        client.roundtrip(&mut server);
+
+        let hit = surface_under(&mut server, root, (25.0, 25.0)).unwrap();
+        assert_eq!(hit.surface, client.server_surface(&server, &above));
+
+        let hit = surface_under(&mut server, root, (75.0, 75.0)).unwrap();
+        assert_eq!(hit.surface, client.server_surface(&server, &below));
+    }
+
+    /// Surfaces do not accept input where a branch clips the surface.
+    #[test]
+    fn surface_under_clipped() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let root = server.comp().scene.create_branch();
+        let (surface, _) = mapped_surface_tree(&mut server, &mut client, root, (0, 0), (100, 100));
+        server
+            .comp()
+            .scene
+            .set_branch_clip(root, Some(Rectangle::from_loc_and_size((0, 0), (50, 50))));
+
+        let hit = surface_under(&mut server, root, (25.0, 25.0)).unwrap();
+        assert_eq!(hit.surface, client.server_surface(&server, &surface));
+        assert_eq!(surface_under(&mut server, root, (75.0, 75.0)), None);
+    }
+
+    /// The location is in the coordinate space of the surface, before the surface is scaled.
+    #[test]
+    fn surface_under_scaled() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let root = server.comp().scene.create_branch();
+        let (surface, _) = mapped_surface_tree(&mut server, &mut client, root, (10, 10), (100, 100));
+        server.comp().scene.set_branch_scale(root, 2.0);
+
+        let hit = surface_under(&mut server, root, (120.0, 70.0)).unwrap();
+        assert_eq!(hit.surface, client.server_surface(&server, &surface));
+        assert_eq!(hit.location, (50.0, 25.0).into());
+        assert_eq!(hit.surface_location, (20, 20).into());
+
+        // The scaled surface covers 200x200 starting from 20x20.
+        assert_eq!(surface_under(&mut server, root, (230.0, 100.0)), None);
+    }
+
+    /// Points outside of any surface, or only over solid colors, are not under a surface.
+    #[test]
+    fn surface_under_miss() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let root = server.comp().scene.create_branch();
+        mapped_surface_tree(&mut server, &mut client, root, (0, 0), (100, 100));
+        solid_color(&mut server.comp().scene, root, (200, 0), (100, 100));
+
+        assert_eq!(surface_under(&mut server, root, (150.0, 50.0)), None);
+        assert_eq!(surface_under(&mut server, root, (250.0, 50.0)), None);
+        assert_eq!(surface_under(&mut server, root, (-1.0, 50.0)), None);
+
+        // A surface without a buffer does not accept input.
+        let (_, tree) = surface_tree(&mut server, &mut client);
+        let scene = &mut server.comp().scene;
+        scene.branch_add_child(root, NodeIndex::SurfaceTree(tree)).unwrap();
+        scene.set_node_offset(NodeIndex::SurfaceTree(tree), (400, 0).into());
+        assert_eq!(surface_under(&mut server, root, (450.0, 50.0)), None);
+    }
+
+    /// The decorations are sized to fit the surface tree and resize with the surface tree.
+    #[test]
+    fn decoration_rectangles() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let root = server.comp().scene.create_branch();
+        let (surface, tree) = mapped_surface_tree(&mut server, &mut client, root, (0, 0), (100, 50));
+        let decoration = server.comp().scene.decorate_surface_tree(tree, STYLE).unwrap();
+
+        assert_eq!(
+            decoration_rectangles(&mut server.comp().scene, decoration),
+            [
+                (rectangle(2, 2, 100, 10), TITLE_BAR),
+                (rectangle(0, 0, 104, 2), BORDER),
+                (rectangle(0, 62, 104, 2), BORDER),
+                (rectangle(0, 2, 2, 60), BORDER),
+                (rectangle(102, 2, 2, 60), BORDER),
+            ]
+        );
+
+        let buffer = client.create_buffer(200, 100, 0xffffffff);
+        surface.attach(Some(&buffer), 0, 0);
+        surface.commit();
+        client.roundtrip(&mut server);
+
+        assert_eq!(
+            decoration_rectangles(&mut server.comp().scene, decoration),
+            [
+                (rectangle(2, 2, 200, 10), TITLE_BAR),
+                (rectangle(0, 0, 204, 2), BORDER),
+                (rectangle(0, 112, 204, 2), BORDER),
+                (rectangle(0, 2, 2, 110), BORDER),
+                (rectangle(202, 2, 2, 110), BORDER),
+            ]
+        );
+    }
+
+    /// Parts of the decorations with no size are not drawn.
+    #[test]
+    fn decoration_empty_rectangles() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let root = server.comp().scene.create_branch();
+        let (_, tree) = mapped_surface_tree(&mut server, &mut client, root, (0, 0), (100, 50));
+
+        let scene = &mut server.comp().scene;
+        let no_title_bar = DecorationStyle {
+            title_bar_height: 0,
+            ..STYLE
+        };
+        let decoration = scene.decorate_surface_tree(tree, no_title_bar).unwrap();
+        let rectangles = decoration_rectangles(scene, decoration);
+        assert_eq!(rectangles.len(), 4);
+        assert!(rectangles.iter().all(|&(_, color)| color == BORDER));
+
+        let no_border = DecorationStyle {
+            border_width: 0,
+            ..STYLE
+        };
+        scene.set_decoration_style(decoration, no_border);
+        assert_eq!(
+            decoration_rectangles(scene, decoration),
+            [(rectangle(0, 0, 100, 10), TITLE_BAR)]
+        );
+    }
+
+    /// The surface tree is inset from the decorations by the border and title bar, and offsetting the surface tree
+    /// moves the decorations instead.
+    #[test]
+    fn decoration_inset() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let root = server.comp().scene.create_branch();
+        let (surface, tree) = mapped_surface_tree(&mut server, &mut client, root, (30, 40), (100, 50));
+
+        let scene = &mut server.comp().scene;
+        let decoration = scene.decorate_surface_tree(tree, STYLE).unwrap();
+        assert_eq!(scene.get_surface_tree(tree).unwrap().offset, (2, 12).into());
+        assert_eq!(scene.get_decoration(decoration).unwrap().offset, (30, 40).into());
+
+        let hit = surface_under(&mut server, root, (35.0, 55.0)).unwrap();
+        assert_eq!(hit.surface, client.server_surface(&server, &surface));
+        assert_eq!(hit.location, (3.0, 3.0).into());
+        assert_eq!(hit.surface_location, (32, 52).into());
+
+        let scene = &mut server.comp().scene;
+        scene.set_node_offset(NodeIndex::SurfaceTree(tree), (0, 0).into());
+        assert_eq!(scene.get_decoration(decoration).unwrap().offset, (0, 0).into());
+        assert_eq!(scene.get_surface_tree(tree).unwrap().offset, (2, 12).into());
+
+        // A different style changes the inset.
+        let style = DecorationStyle {
+            border_width: 4,
+            title_bar_height: 0,
+            ..STYLE
+        };
+        scene.set_decoration_style(decoration, style);
+        assert_eq!(scene.get_surface_tree(tree).unwrap().offset, (4, 4).into());
+
+        let hit = surface_under(&mut server, root, (5.0, 5.0)).unwrap();
+        assert_eq!(hit.location, (1.0, 1.0).into());
+    }
+
+    /// Removing the decorations puts the surface tree back in the place of the decorations.
+    #[test]
+    fn undecorate_restores_tree() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+        let root = server.comp().scene.create_branch();
+        let (_, tree) = mapped_surface_tree(&mut server, &mut client, root, (30, 40), (100, 50));
+
+        let scene = &mut server.comp().scene;
+        let above = solid_color(scene, root, (0, 0), (10, 10));
+        let output = Output::new(
+            "TEST-1".into(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "Aerugo".into(),
+                model: "Test".into(),
+            },
+        );
+        let output_index = scene.create_output(output.clone());
+        scene.set_output_node(&output, NodeIndex::SurfaceTree(tree));
+
+        let decoration = scene.decorate_surface_tree(tree, STYLE).unwrap();
+        assert_eq!(
+            scene.forest.children(root.0).collect::<Vec<_>>(),
+            [decoration.0, above.0]
+        );
+        assert_eq!(Node::parent(scene.forest.get(tree.0).unwrap()), Some(decoration.0));
+        assert_eq!(
+            scene.get_output(output_index).unwrap().present,
+            Some(NodeIndex::Decoration(decoration))
+        );
+
+        // Decorating again returns the existing decorations.
+        assert_eq!(scene.decorate_surface_tree(tree, STYLE), Some(decoration));
+
+        // The surface tree keeps the offset the decorations were moved to.
+        scene.set_node_offset(NodeIndex::SurfaceTree(tree), (50, 60).into());
+        scene.undecorate_surface_tree(tree);
+
+        assert_eq!(scene.forest.children(root.0).collect::<Vec<_>>(), [tree.0, above.0]);
+        assert_eq!(Node::parent(scene.forest.get(tree.0).unwrap()), Some(root.0));
+        assert!(scene.get_decoration(decoration).is_none());
+
+        let node = scene.get_surface_tree(tree).unwrap();
+        assert_eq!(node.offset, (50, 60).into());
+        assert_eq!(node.decoration(), None);
+        assert_eq!(
+            scene.get_output(output_index).unwrap().present,
+            Some(NodeIndex::SurfaceTree(tree))
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! Snapshots of surface trees
+//!
+//! A snapshot copies the contents of the buffers a surface tree had committed when the snapshot was taken. Later
+//! commits do not change the snapshot and the snapshot stays valid after the surfaces are unmapped or destroyed,
+//! which allows a WM to animate a toplevel after the toplevel was closed. Since the contents are copied, the
+//! client may reuse the buffers right away.
+//!
+//! Only shared memory buffers in the `Argb8888` and `Xrgb8888` formats and single pixel buffers are copied.
+//! Surfaces using dmabufs or other formats are left out of the snapshot, so the snapshot of a toplevel rendered
+//! with the GPU may be missing surfaces or be empty.
+
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    fmt,
+    ops::{Deref, DerefMut},
+    rc::Rc,
+};
+
+use rustc_hash::FxHashMap;
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{element::Id, utils::RendererSurfaceStateUserData, ImportAll, ImportMem, Renderer},
+    },
+    utils::{Buffer, Logical, Point, Rectangle, Size, Transform},
+    wayland::{
+        compositor::{self, SurfaceAttributes},
+        shm::{self, BufferAccessError},
+    },
+};
+use wayland_server::protocol::{wl_buffer::WlBuffer, wl_shm, wl_surface};
+
+use super::{crop_src, single_pixel_color, Hierarchy, NodeIndex, Scene, SceneNode, SnapshotIndex, SurfaceTreeIndex};
+
+/// The contents of a surface tree at the time the snapshot was taken.
+pub struct SnapshotNode {
+    index: SnapshotIndex,
+    offset: Point<i32, Logical>,
+    /// The surfaces in the snapshot, ordered from bottom to top.
+    surfaces: Vec<Rc<SnapshotSurface>>,
+    /// The size of the root surface.
+    size: Size<i32, Logical>,
+    /// The number of buffer pixels per logical pixel of the root surface.
+    scale: f64,
+}
+
+impl fmt::Debug for SnapshotNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SnapshotNode")
+            .field("index", &self.index)
+            .field("offset", &self.offset)
+            .field("surfaces", &self.surfaces.len())
+            .field("size", &self.size)
+            .field("scale", &self.scale)
+            .finish()
+    }
+}
+
+impl SnapshotNode {
+    pub fn index(&self) -> SnapshotIndex {
+        self.index
+    }
+
+    pub fn size(&self) -> Size<i32, Logical> {
+        self.size
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub(super) fn offset(&self) -> Point<i32, Logical> {
+        self.offset
+    }
+
+    pub(super) fn set_offset(&mut self, offset: Point<i32, Logical>) {
+        self.offset = offset;
+    }
+
+    pub(super) fn surfaces(&self) -> &[Rc<SnapshotSurface>] {
+        &self.surfaces
+    }
+}
+
+/// The state of a surface at the time a snapshot was taken.
+pub(super) struct SnapshotSurface {
+    pub(super) id: Id,
+    /// The offset of the surface from the root surface of the tree.
+    pub(super) offset: Point<i32, Logical>,
+    /// The destination size of the surface.
+    pub(super) size: Size<i32, Logical>,
+    /// The color of a single pixel buffer, which is drawn without a texture.
+    pub(super) color: Option<[f32; 4]>,
+    pub(super) opaque_regions: Vec<Rectangle<i32, Logical>>,
+    pub(super) transform: Transform,
+    /// The pixels copied from the buffer, without padding between rows. Empty if the buffer is a single pixel buffer.
+    pixels: Vec<u8>,
+    format: Fourcc,
+    /// The size of the copied pixels.
+    pixels_size: Size<i32, Buffer>,
+    buffer_scale: i32,
+    buffer_size: Size<i32, Logical>,
+    src: Rectangle<f64, Logical>,
+    /// Textures imported from the buffer, by the type and id of the renderer.
+    textures: RefCell<FxHashMap<(TypeId, usize), Box<dyn Any>>>,
+}
+
+impl fmt::Debug for SnapshotSurface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SnapshotSurface")
+            .field("id", &self.id)
+            .field("offset", &self.offset)
+            .field("size", &self.size)
+            .field("color", &self.color)
+            .field("transform", &self.transform)
+            .field("buffer_scale", &self.buffer_scale)
+            .field("buffer_size", &self.buffer_size)
+            .field("src", &self.src)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SnapshotSurface {
+    /// Capture the current state of a surface, [`None`] if the surface has no buffer or the contents of the buffer
+    /// could not be copied.
+    fn new(surface: &wl_surface::WlSurface, offset: Point<i32, Logical>) -> Option<Self> {
+        compositor::with_states(surface, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>()?;
+            let data = data.borrow();
+            let view = data.view()?;
+            let attributes = states.cached_state.current::<SurfaceAttributes>();
+            let color = single_pixel_color(&data);
+
+            let (pixels, format, pixels_size) = match color {
+                Some(_) => (Vec::new(), Fourcc::Argb8888, Size::default()),
+                None => copy_shm(data.buffer()?)?,
+            };
+
+            Some(Self {
+                id: Id::new(),
+                offset,
+                size: view.dst,
+                color,
+                opaque_regions: data.opaque_regions().unwrap_or_default().to_vec(),
+                transform: attributes.buffer_transform.into(),
+                pixels,
+                format,
+                pixels_size,
+                buffer_scale: attributes.buffer_scale,
+                buffer_size: data.buffer_size()?,
+                src: view.src,
+                textures: RefCell::default(),
+            })
+        })
+    }
+
+    /// The number of buffer pixels per logical pixel.
+    fn scale(&self) -> f64 {
+        self.buffer_scale as f64 * self.src.size.w / self.size.w.max(1) as f64
+    }
+
+    /// The area of the buffer to sample, cropped to the visible part of the surface.
+    pub(super) fn src(&self, crop: Option<Rectangle<f64, Logical>>) -> Rectangle<f64, Buffer> {
+        let src = match crop {
+            Some(crop) => crop_src(self.src, self.size.to_f64(), crop),
+            None => self.src,
+        };
+
+        src.to_buffer(self.buffer_scale as f64, self.transform, &self.buffer_size.to_f64())
+    }
+
+    /// Import the copied pixels into the renderer, unless the pixels were already imported.
+    pub(super) fn import<R>(&self, renderer: &mut R)
+    where
+        R: Renderer + ImportAll + ImportMem,
+        R::TextureId: 'static,
+    {
+        // Single pixel buffers are filled instead of uploading a texture.
+        if self.color.is_some() {
+            return;
+        }
+
+        let key = (TypeId::of::<R>(), renderer.id());
+
+        if self.textures.borrow().contains_key(&key) {
+            return;
+        }
+
+        // The pixels never change, so the pixels are imported once.
+        match renderer.import_memory(&self.pixels, self.format, self.pixels_size, false) {
+            Ok(texture) => {
+                self.textures.borrow_mut().insert(key, Box::new(texture));
+            }
+
+            Err(err) => tracing::warn!(%err, "Failed to import snapshot"),
+        }
+    }
+
+    /// Use the texture imported into the renderer with the id.
+    pub(super) fn with_texture<R, T>(&self, id: usize, f: impl FnOnce(&R::TextureId) -> T) -> Option<T>
+    where
+        R: Renderer,
+        R::TextureId: 'static,
+    {
+        let textures = self.textures.borrow();
+        let texture = textures.get(&(TypeId::of::<R>(), id))?.downcast_ref::<R::TextureId>()?;
+        Some(f(texture))
+    }
+}
+
+/// Copy the pixels of a shared memory buffer.
+///
+/// The pixels are returned with the format and size of the buffer. [`None`] is returned if the buffer is not a
+/// shared memory buffer or the format is not supported.
+// TODO: Copy dmabufs once dmabufs can be imported.
+fn copy_shm(buffer: &WlBuffer) -> Option<(Vec<u8>, Fourcc, Size<i32, Buffer>)> {
+    let copied = shm::with_buffer_contents(buffer, |ptr, len, data| {
+        let format = match data.format {
+            wl_shm::Format::Argb8888 => Fourcc::Argb8888,
+            wl_shm::Format::Xrgb8888 => Fourcc::Xrgb8888,
+            format => {
+                tracing::warn!(?format, "Unsupported snapshot buffer format");
+                return None;
+            }
+        };
+
+        let offset = data.offset as usize;
+        let stride = data.stride as usize;
+        let row = data.width as usize * 4;
+        let height = data.height as usize;
+
+        if height == 0 || stride < row || offset + stride * (height - 1) + row > len {
+            tracing::warn!(offset, stride, height, len, "Snapshot buffer is outside of the pool");
+            return None;
+        }
+
+        // SAFETY: The pool is mapped for the duration of the closure and the bounds were checked above.
+        let contents = unsafe { std::slice::from_raw_parts(ptr, len) };
+        let mut pixels = Vec::with_capacity(row * height);
+
+        for y in 0..height {
+            let start = offset + y * stride;
+            pixels.extend_from_slice(&contents[start..start + row]);
+        }
+
+        Some((pixels, format, (data.width, data.height).into()))
+    });
+
+    match copied {
+        Ok(copied) => copied,
+        Err(BufferAccessError::NotManaged) => {
+            tracing::warn!("Skipping surface in snapshot: dmabufs cannot be copied");
+            None
+        }
+        Err(err) => {
+            tracing::warn!(%err, "Failed to copy snapshot buffer");
+            None
+        }
+    }
+}
+
+impl Scene {
+    /// Take a snapshot of the surfaces in a surface tree.
+    ///
+    /// Surfaces without a buffer are not part of the snapshot. Server side decorations around the surface tree
+    /// are not part of the snapshot either.
+    pub fn create_snapshot(&mut self, tree: SurfaceTreeIndex) -> Option<SnapshotIndex> {
+        let root = match self.forest.get(tree.0).map(Deref::deref) {
+            Some(SceneNode::SurfaceTree(node)) => node.root,
+            _ => return None,
+        };
+
+        let hierarchy = Hierarchy {
+            scene: self,
+            root: NodeIndex::SurfaceTree(tree),
+        };
+        let nodes = hierarchy.nodes();
+        // The surfaces are placed relative to the root surface rather than to the parent of the tree.
+        let origin = nodes.first()?.location;
+
+        let mut scale = 1.0;
+        let mut surfaces = Vec::new();
+
+        for placed in nodes {
+            let SceneNode::Surface(node) = self.forest.get(placed.index).unwrap().deref() else {
+                continue;
+            };
+
+            let Some(surface) = SnapshotSurface::new(&node.surface, (placed.location - origin).to_i32_round()) else {
+                continue;
+            };
+
+            if node.index == root {
+                scale = surface.scale();
+            }
+
+            surfaces.push(Rc::new(surface));
+        }
+
+        let size = self.surface_tree_size(tree);
+
+        Some(SnapshotIndex(self.forest.insert_with(|index| {
+            SceneNode::Snapshot(SnapshotNode {
+                index: SnapshotIndex(index),
+                offset: (0, 0).into(),
+                surfaces,
+                size,
+                scale,
+            })
+        })))
+    }
+
+    pub fn get_snapshot(&mut self, index: SnapshotIndex) -> Option<&mut SnapshotNode> {
+        self.forest.get_mut(index.0).map(|node| match node.deref_mut() {
+            SceneNode::Snapshot(node) => node,
+            _ => unreachable!(),
+        })
+    }
+
+    /// Destroy a snapshot, releasing the buffers of the snapshot.
+    pub fn destroy_snapshot(&mut self, index: SnapshotIndex) {
+        let _ = self.forest.remove(index.into());
+        self.clear_output_nodes(NodeIndex::Snapshot(index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        scene::{NodeIndex, Scene, SceneNode, SnapshotIndex},
+        testing::{TestClient, TestServer},
+    };
+
+    use super::SnapshotNode;
+
+    fn empty_snapshot(scene: &mut Scene) -> SnapshotIndex {
+        SnapshotIndex(scene.forest.insert_with(|index| {
+            SceneNode::Snapshot(SnapshotNode {
+                index: SnapshotIndex(index),
+                offset: (0, 0).into(),
+                surfaces: Vec::new(),
+                size: (10, 10).into(),
+                scale: 1.0,
+            })
+        }))
+    }
+
+    /// Destroying a snapshot when the WM drops the snapshot removes the node from the scene.
+    #[test]
+    fn destroy_frees_node() {
+        let mut scene = Scene::new();
+        let branch = scene.create_branch();
+        let snapshot = empty_snapshot(&mut scene);
+        scene.branch_add_child(branch, NodeIndex::Snapshot(snapshot)).unwrap();
+
+        scene.destroy_snapshot(snapshot);
+
+        assert!(scene.get_snapshot(snapshot).is_none());
+        assert_eq!(scene.forest.children(branch.0).count(), 0);
+    }
+
+    /// Surfaces are placed relative to the root surface and surfaces without a buffer are left out.
+    #[test]
+    fn create_snapshot_offsets() {
+        let mut server = TestServer::new();
+        let mut client = TestClient::new(&mut server);
+
+        let root = client.create_surface();
+        let child = client.create_surface();
+        let unmapped = client.create_surface();
+        let child_subsurface = client.create_subsurface(&child, &root);
+        let unmapped_subsurface = client.create_subsurface(&unmapped, &root);
+        child_subsurface.set_position(10, 20);
+        unmapped_subsurface.set_position(50, 5);
+        client.roundtrip(&mut server);
+
+        let surface = client.server_surface(&server, &root);
+        let tree = server.comp().scene.create_surface_tree(surface);
+        let branch = server.comp().scene.create_branch();
+        let scene = &mut server.comp().scene;
+        scene.branch_add_child(branch, NodeIndex::SurfaceTree(tree)).unwrap();
+        scene.set_node_offset(NodeIndex::SurfaceTree(tree), (30, 40).into());
+
+        let buffer = client.create_buffer(20, 10, 0xffff0000);
+        child.attach(Some(&buffer), 0, 0);
+        child.commit();
+        unmapped.commit();
+        let buffer = client.create_buffer(100, 50, 0xffffffff);
+        root.attach(Some(&buffer), 0, 0);
+        root.commit();
+        client.roundtrip(&mut server);
+
+        let scene = &mut server.comp().scene;
+        let snapshot = scene.create_snapshot(tree).unwrap();
+        let node = scene.get_snapshot(snapshot).unwrap();
+
+        assert_eq!(node.size(), (100, 50).into());
+        assert_eq!(node.scale(), 1.0);
+
+        let surfaces = node
+            .surfaces()
+            .iter()
+            .map(|surface| (surface.offset, surface.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            surfaces,
+            vec![((0, 0).into(), (100, 50).into()), ((10, 20).into(), (20, 10).into())]
+        );
+    }
+}
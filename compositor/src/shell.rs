@@ -66,27 +66,40 @@ If the clients fail to commit the previous transaction states, should the WM's n
 client state, and cancel the previous transaction?
 */
 
-use std::{fmt, num::NonZeroU64, sync::Arc};
+use std::{cell::Cell, fmt, num::NonZeroU64, sync::Arc};
 
 use rustc_hash::FxHashMap;
 use smithay::{
     backend::renderer::utils::with_renderer_surface_state,
+    reexports::wayland_protocols::xdg::{
+        decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode as DecorationMode, shell::server::xdg_toplevel,
+    },
     utils::{Logical, Serial, Size},
     wayland::{
-        compositor::{self, SurfaceAttributes, TraversalAction},
+        compositor::{self, BufferAssignment, SurfaceAttributes, TraversalAction},
         shell::{
             wlr_layer,
-            xdg::{ToplevelSurface, XdgToplevelSurfaceData},
+            xdg::{ToplevelState, ToplevelSurface, XdgToplevelSurfaceData},
         },
     },
     xwayland::X11Surface,
 };
 use wayland_server::{backend::ObjectId, protocol::wl_surface::WlSurface, Client, DisplayHandle, Resource};
+use wm_runtime::{
+    ConfigureUpdate, DecorationMode as WmDecorationMode, Features, Id, IdType, ToplevelUpdate, WmEvent,
+    WmToplevelConfigure,
+};
 
 use crate::{
-    wayland::ext::foreign_toplevel::{
-        ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
-        ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
+    capture::CaptureSource,
+    output::{self, ConnectedOutput},
+    scene::{DecorationStyle, NodeIndex, SurfaceTreeIndex},
+    wayland::{
+        ext::foreign_toplevel::{
+            ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
+            ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
+        },
+        xdg_decoration,
     },
     Aerugo,
 };
@@ -184,6 +197,14 @@ pub struct Toplevel {
 
     /// Foreign handles to this toplevel.
     handles: FxHashMap<ObjectId, ToplevelHandles>,
+
+    /// Whether the toplevel was configured with the suspended state because it is hidden.
+    suspended: bool,
+
+    /// The id of the toplevel in the WM, if the toplevel was announced to the WM.
+    ///
+    /// This is [`None`] once the WM was told the toplevel was closed.
+    wm_id: Option<Id>,
     // TODO: xdg-foreign id?
 }
 
@@ -263,6 +284,15 @@ impl Toplevel {
         }
     }
 
+    /// Whether the current state of the toplevel uses server side decorations.
+    pub fn server_side_decorations(&self) -> bool {
+        match &self.surface {
+            Surface::Toplevel(toplevel) => toplevel.current_state().decoration_mode == Some(DecorationMode::ServerSide),
+            // TODO: Xwayland?
+            Surface::XWayland(_) => false,
+        }
+    }
+
     pub fn wl_surface(&self) -> Option<WlSurface> {
         match &self.surface {
             Surface::Toplevel(toplevel) => Some(toplevel.wl_surface().clone()),
@@ -270,6 +300,57 @@ impl Toplevel {
         }
     }
 
+    /// Set whether the toplevel is suspended, sending a configure if the state changed.
+    ///
+    /// Toplevels which do not support the suspended state are not configured.
+    pub fn set_suspended(&mut self, suspended: bool) {
+        if self.suspended == suspended {
+            return;
+        }
+
+        self.suspended = suspended;
+
+        match &self.surface {
+            Surface::Toplevel(toplevel) => {
+                // The suspended state was added in version 6 of xdg-shell.
+                if toplevel.xdg_toplevel().version() >= 6 {
+                    self.configure(|_| {});
+                }
+            }
+
+            // TODO: Xwayland?
+            Surface::XWayland(_) => (),
+        }
+    }
+
+    /// Send a configure with the pending state changed by the closure.
+    ///
+    /// Every configure is sent through this function, so state the compositor manages, such as the suspended
+    /// state, is part of every configure and is never sent alongside state the WM has not submitted.
+    fn configure(&self, update: impl FnOnce(&mut ToplevelState)) {
+        match &self.surface {
+            Surface::Toplevel(toplevel) => {
+                let suspended = self.suspended && toplevel.xdg_toplevel().version() >= 6;
+
+                toplevel.with_pending_state(|state| {
+                    update(state);
+
+                    if suspended {
+                        state.states.set(xdg_toplevel::State::Suspended);
+                    } else {
+                        state.states.unset(xdg_toplevel::State::Suspended);
+                    }
+                });
+
+                // TODO: Track the serial so the WM can be told once the client acks the configure.
+                toplevel.send_configure();
+            }
+
+            // TODO: Configure XWayland surfaces.
+            Surface::XWayland(_) => {}
+        }
+    }
+
     pub fn update_state(&mut self) {
         todo!()
     }
@@ -306,17 +387,30 @@ struct Mapped {
     serial: Serial,
 }
 
+/// Associates a surface with the toplevel the surface is the role of.
+///
+/// The id is unset when the toplevel becomes new again, since the data map cannot remove data.
+#[derive(Default)]
 struct AerugoToplevelData {
-    toplevel_id: ToplevelId,
+    toplevel_id: Cell<Option<ToplevelId>>,
 }
 
 impl Shell {
     pub fn get_toplevel_id(surface: &WlSurface) -> Option<ToplevelId> {
         compositor::with_states(surface, |data| {
-            data.data_map.get::<AerugoToplevelData>().map(|data| data.toplevel_id)
+            data.data_map
+                .get::<AerugoToplevelData>()
+                .and_then(|data| data.toplevel_id.get())
         })
     }
 
+    fn set_toplevel_id(surface: &WlSurface, id: Option<ToplevelId>) {
+        compositor::with_states(surface, |data| {
+            data.data_map.insert_if_missing(AerugoToplevelData::default);
+            data.data_map.get::<AerugoToplevelData>().unwrap().toplevel_id.set(id);
+        });
+    }
+
     pub fn new() -> Self {
         Shell {
             pending_toplevels: Vec::new(),
@@ -326,6 +420,34 @@ impl Shell {
         }
     }
 
+    /// Handle a commit before the buffer of the surface is applied.
+    ///
+    /// Once the buffer is applied, the contents of a toplevel which is being unmapped are gone. So the WM is given
+    /// a snapshot of the toplevel and told the toplevel was closed here.
+    pub fn pre_commit(comp: &mut Aerugo, surface: &WlSurface) {
+        let Some(id) = Shell::get_toplevel_id(surface) else {
+            return;
+        };
+
+        // A toplevel which was never mapped is not unmapped, like in `Shell::toplevel_commit`.
+        let mapped = comp
+            .shell
+            .toplevels
+            .get(&id)
+            .map_or(false, |toplevel| !matches!(toplevel.current, State::NotYetMapped));
+
+        let removed = compositor::with_states(surface, |states| {
+            matches!(
+                states.cached_state.current::<SurfaceAttributes>().buffer,
+                Some(BufferAssignment::Removed)
+            )
+        });
+
+        if mapped && removed {
+            Shell::close_in_wm(comp, id);
+        }
+    }
+
     pub fn commit(comp: &mut Aerugo, surface: &WlSurface) {
         // Handle commit for each type of role.
         Shell::toplevel_commit(comp, surface);
@@ -341,6 +463,7 @@ impl Shell {
                 .position(|toplevel| toplevel.wl_surface() == surface)
             {
                 let toplevel = comp.shell.pending_toplevels.remove(toplevel_index);
+                Shell::initial_commit(comp, toplevel);
             }
 
             return;
@@ -362,6 +485,13 @@ impl Shell {
                 // TODO: Include app_id, remove toplevel debug impl
                 tracing::debug!(?toplevel, "Unmap toplevel");
                 let toplevel = comp.shell.toplevels.remove(&id).unwrap();
+                Shell::set_toplevel_id(surface, None);
+                comp.stop_captures(&CaptureSource::Toplevel(id));
+
+                // The surface tree is created again when the toplevel is mapped again.
+                if let Some(tree) = comp.scene.get_surface_tree_index(surface.clone()) {
+                    comp.scene.destroy_surface_tree(tree);
+                }
 
                 // Notify clients the toplevel is being unmapped.
                 for handle in toplevel.handles.values() {
@@ -383,6 +513,185 @@ impl Shell {
             let app_id = toplevel.app_id().unwrap_or_default();
             tracing::warn!(%id, %app_id, "Killing client: toplevel not configured");
         }
+
+        // The toplevel is mapped once the client attaches a buffer.
+        if has_buffer && matches!(toplevel.current, State::NotYetMapped) {
+            let serial = compositor::with_states(surface, |states| {
+                states
+                    .data_map
+                    .get::<XdgToplevelSurfaceData>()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .current_serial
+            });
+
+            let tree = comp.scene.create_surface_tree(surface.clone());
+            let toplevel = comp.shell.toplevels.get_mut(&id).unwrap();
+            toplevel.current = State::Mapped(Mapped {
+                size: comp.scene.surface_tree_size(tree),
+                serial: serial.unwrap_or_else(|| Serial::from(0)),
+            });
+
+            tracing::debug!(%id, app_id = toplevel.app_id().unwrap_or_default(), "Map toplevel");
+            Shell::present_toplevel(comp, tree);
+        }
+
+        let toplevel = comp.shell.toplevels.get(&id).unwrap();
+
+        // Draw decorations once the toplevel has committed the state with server side decorations.
+        let server_side = toplevel.server_side_decorations();
+
+        if let Some(tree) = comp.scene.get_surface_tree_index(surface.clone()) {
+            if server_side {
+                comp.scene.decorate_surface_tree(tree, DecorationStyle::default());
+            } else {
+                comp.scene.undecorate_surface_tree(tree);
+            }
+        }
+
+        if let (Some(wm_id), Some(wm)) = (toplevel.wm_id, comp.wm.as_ref()) {
+            wm.send(WmEvent::CommittedToplevel {
+                toplevel: wm_id,
+                snapshot: None,
+            });
+        }
+    }
+
+    /// Handle the initial commit of a new toplevel.
+    ///
+    /// The toplevel is sent the initial configure and is announced to foreign toplevel clients.
+    fn initial_commit(comp: &mut Aerugo, toplevel: ToplevelSurface) {
+        let surface = toplevel.wl_surface().clone();
+        let has_buffer = with_renderer_surface_state(&surface, |state| state.buffer().is_some());
+
+        let (app_id, title) = compositor::with_states(&surface, |states| {
+            let data = states.data_map.get::<XdgToplevelSurfaceData>().unwrap().lock().unwrap();
+            (data.app_id.clone(), data.title.clone())
+        });
+        // Log an empty app id if the toplevel has none.
+        let log_app_id = app_id.clone().unwrap_or_default();
+
+        // Ensure the toplevel has no attached buffer during initial commit
+        if has_buffer {
+            // TODO: Send UnconfiguredBuffer
+            tracing::warn!(app_id = %log_app_id, "Killing client: attached buffer during initial commit");
+        }
+
+        // TODO: Remove this temporary configure and make the WM send the configure.
+        toplevel.send_configure();
+
+        let id = comp.shell.next_toplevel_id;
+
+        tracing::debug!(%id, app_id = %log_app_id, "Initial commit of toplevel");
+
+        comp.shell.next_toplevel_id = comp
+            .shell
+            .next_toplevel_id
+            .checked_add(1)
+            .expect("u64 overflow (unlikely)");
+
+        Shell::set_toplevel_id(&surface, Some(id));
+
+        // The WM is told about the toplevel once the initial state of the toplevel is sent.
+        let wm_id = comp.wm.as_ref().map(|wm| {
+            let wm_id = wm.alloc_id(IdType::Toplevel);
+            let mut features = Features::default();
+
+            if xdg_decoration::supports_server_side(&surface) {
+                features |= Features::SERVER_SIDE_DECORATIONS;
+            }
+
+            wm.send(WmEvent::NewToplevel {
+                toplevel: wm_id,
+                features,
+            });
+            wm.send(WmEvent::UpdateToplevel {
+                toplevel: wm_id,
+                update: ToplevelUpdate {
+                    app_id,
+                    title,
+                    ..Default::default()
+                },
+            });
+
+            wm_id
+        });
+
+        let toplevel = comp.shell.toplevels.entry(id).or_insert(Toplevel {
+            id,
+            surface: Surface::Toplevel(toplevel),
+            current: State::default(),
+            pending: None,
+            handles: FxHashMap::default(),
+            suspended: false,
+            wm_id,
+        });
+
+        let mut new_instances = Vec::with_capacity(comp.shell.foreign_toplevel_instances.len());
+
+        // Create the foreign toplevel handles
+        for instance in comp.shell.foreign_toplevel_instances.values() {
+            // Create all toplevel handle instances to ensure that extension protocols do not refer to handles
+            // that were not yet created.
+            if let Some(client) = instance.instance.client() {
+                new_instances.push(toplevel.create_handle(comp.generation, &instance.instance, &comp.display, &client));
+            }
+        }
+
+        // Describe the toplevel.
+        for new in new_instances {
+            toplevel.initialize_handle(&new);
+        }
+    }
+
+    /// Present a mapped toplevel above the other toplevels on the output the pointer is on.
+    ///
+    /// If the pointer is not on any output, the toplevel is presented on the first output.
+    // TODO: Let the WM place toplevels once the WM can build views.
+    fn present_toplevel(comp: &mut Aerugo, tree: SurfaceTreeIndex) {
+        let pointer = comp.pointer_location;
+        let enabled = || comp.outputs.iter().filter(|connected| connected.enabled());
+        let layout = enabled()
+            .find(|connected| output::geometry(connected.output()).to_f64().contains(pointer))
+            .or_else(|| enabled().next())
+            .and_then(ConnectedOutput::layout);
+
+        if let Some(layout) = layout {
+            let _ = comp.scene.branch_add_child(layout, NodeIndex::SurfaceTree(tree));
+        }
+    }
+
+    /// Configure a toplevel with the state the WM submitted.
+    ///
+    /// State the WM did not set is left as is.
+    pub fn wm_configure(comp: &mut Aerugo, serial: u32, configure: WmToplevelConfigure) {
+        let Some(toplevel) = comp
+            .shell
+            .toplevels
+            .values()
+            .find(|toplevel| toplevel.wm_id == Some(configure.toplevel_id))
+        else {
+            tracing::debug!(serial, ?configure, "WM configured unknown toplevel");
+            return;
+        };
+
+        if let (Surface::Toplevel(surface), Some(mode)) = (&toplevel.surface, configure.decorations) {
+            let mode = match mode {
+                WmDecorationMode::ClientSide => DecorationMode::ClientSide,
+                WmDecorationMode::ServerSide => DecorationMode::ServerSide,
+            };
+
+            xdg_decoration::set_wm_mode(surface, Some(mode));
+        }
+
+        toplevel.configure(|state| {
+            if let ConfigureUpdate::Update(size) = configure.size {
+                state.size = size.map(|size| (size.width as i32, size.height as i32).into());
+            }
+
+            // TODO: Parent, states and bounds.
+        });
     }
 
     // pub fn commit(comp: &mut Aerugo, surface: &WlSurface) {
@@ -442,6 +751,8 @@ impl Shell {
     //             current: State::default(),
     //             pending: None,
     //             handles: FxHashMap::default(),
+    //             suspended: false,
+    //             wm_id: None,
     //         });
 
     //         let mut new_instances = Vec::with_capacity(comp.shell.foreign_toplevel_instances.len());
@@ -566,6 +877,41 @@ impl Shell {
     //     }
     // }
 
+    /// Suspend toplevels which are hidden on every output the toplevel is on and resume toplevels which are
+    /// visible again.
+    ///
+    /// Toplevels which are not on any output are left alone, since the WM decides whether those are presented.
+    pub fn update_suspended(comp: &mut Aerugo) {
+        for toplevel in comp.shell.toplevels.values_mut() {
+            let Some(surface) = toplevel.wl_surface() else {
+                continue;
+            };
+
+            let mut on_output = false;
+            let mut visible = false;
+
+            // A toplevel is visible if any of the subsurfaces are visible, such as a video over a hidden parent.
+            compositor::with_surface_tree_downward(
+                &surface,
+                (),
+                |_, _, _| TraversalAction::DoChildren(()),
+                |surface, _, _| {
+                    for output in comp.scene.surface_outputs(surface) {
+                        on_output = true;
+                        visible |= comp
+                            .outputs
+                            .iter()
+                            .find(|connected| connected.output() == output)
+                            .map_or(false, |connected| !connected.frame_scheduler().is_hidden(surface));
+                    }
+                },
+                |_, _, _| true,
+            );
+
+            toplevel.set_suspended(on_output && !visible);
+        }
+    }
+
     pub fn remove_toplevel(comp: &mut Aerugo, surface: &WlSurface) {
         // Remove toplevels that are pending
         comp.shell
@@ -576,12 +922,48 @@ impl Shell {
             let remove = toplevel.wl_surface().as_ref() == Some(surface);
             remove.then_some(*key)
         }) {
+            Shell::close_in_wm(comp, id);
+
             let toplevel = comp.shell.toplevels.remove(&id).unwrap();
+            Shell::set_toplevel_id(surface, None);
+            comp.stop_captures(&CaptureSource::Toplevel(id));
+
+            for handle in toplevel.handles.values() {
+                handle.handle.closed();
+            }
+
             let app_id = toplevel.app_id();
             tracing::debug!(id, app_id, "Removed toplevel");
         }
     }
 
+    /// Give the WM a snapshot of the toplevel and tell the WM the toplevel was closed.
+    ///
+    /// The snapshot is taken from the surface tree, so this must be called before the surface tree is destroyed
+    /// or the buffer is removed.
+    fn close_in_wm(comp: &mut Aerugo, id: ToplevelId) {
+        let Some(toplevel) = comp.shell.toplevels.get_mut(&id) else {
+            return;
+        };
+
+        let (Some(wm_id), Some(surface)) = (toplevel.wm_id.take(), toplevel.wl_surface()) else {
+            return;
+        };
+
+        let snapshot = comp.create_wm_snapshot(&surface);
+
+        if let Some(wm) = comp.wm.as_ref() {
+            if snapshot.is_some() {
+                wm.send(WmEvent::CommittedToplevel {
+                    toplevel: wm_id,
+                    snapshot,
+                });
+            }
+
+            wm.send(WmEvent::ClosedToplevel(wm_id));
+        }
+    }
+
     pub fn get_state(&self, id: ToplevelId) -> Option<&Toplevel> {
         self.toplevels.get(&id)
     }
@@ -590,24 +972,3 @@ impl Shell {
         self.toplevels.get_mut(&id)
     }
 }
-
-pub fn send_frames_surface_tree(surface: &WlSurface, time: u32) {
-    compositor::with_surface_tree_downward(
-        surface,
-        (),
-        |_, _, &()| TraversalAction::DoChildren(()),
-        |_surf, states, &()| {
-            // the surface may not have any user_data if it is a subsurface and has not
-            // yet been commited
-            for callback in states
-                .cached_state
-                .current::<SurfaceAttributes>()
-                .frame_callbacks
-                .drain(..)
-            {
-                callback.done(time);
-            }
-        },
-        |_, _, &()| true,
-    );
-}
@@ -0,0 +1,49 @@
+//! Snapshots given to the WM
+//!
+//! The WM receives a snapshot of a toplevel when the toplevel is unmapped or destroyed. The snapshot keeps a copy of
+//! the contents of the toplevel's surface tree, so the WM may present the snapshot after the toplevel is gone, such
+//! as while animating closing the toplevel. The snapshot is freed when the WM drops the snapshot.
+
+use wayland_server::protocol::wl_surface::WlSurface;
+use wm_runtime::{Id, IdType, Size, SnapshotInfo};
+
+use crate::Aerugo;
+
+impl Aerugo {
+    /// Take a snapshot of the surface tree of a surface to give to the WM.
+    ///
+    /// Returns [`None`] if there is no WM, since nothing would free the snapshot.
+    pub(crate) fn create_wm_snapshot(&mut self, surface: &WlSurface) -> Option<(Id, SnapshotInfo)> {
+        let wm = self.wm.as_ref()?;
+        let tree = self.scene.get_surface_tree_index(surface.clone())?;
+        let index = self.scene.create_snapshot(tree)?;
+
+        let snapshot = self.scene.get_snapshot(index).unwrap();
+        let size = snapshot.size();
+        let info = SnapshotInfo {
+            size: Size {
+                width: size.w.max(0) as u32,
+                height: size.h.max(0) as u32,
+            },
+            scale: snapshot.scale() as f32,
+        };
+
+        let id = wm.alloc_id(IdType::Snapshot);
+        self.snapshots.insert(id, index);
+        Some((id, info))
+    }
+
+    /// Free a snapshot which the WM dropped.
+    pub(crate) fn drop_snapshot(&mut self, id: Id) {
+        if let Some(index) = self.snapshots.remove(&id) {
+            self.scene.destroy_snapshot(index);
+        }
+    }
+
+    /// Free every snapshot given to the WM, such as when the WM exits.
+    pub(crate) fn drop_snapshots(&mut self) {
+        for (_, index) in self.snapshots.drain() {
+            self.scene.destroy_snapshot(index);
+        }
+    }
+}
@@ -5,62 +5,117 @@ use std::{
 
 use bitflags::bitflags;
 use calloop::LoopHandle;
+use rustc_hash::FxHashMap;
 use smithay::{
-    input::SeatState,
-    output::{Output, PhysicalProperties},
+    input::{keyboard::XkbConfig, Seat, SeatState},
+    utils::{Clock, Logical, Monotonic, Point},
     wayland::{
         compositor::{CompositorClientState, CompositorState},
-        shell::xdg::XdgShellState,
+        fractional_scale::FractionalScaleManagerState,
+        output::OutputManagerState,
+        presentation::PresentationState,
+        shell::xdg::{decoration::XdgDecorationState, XdgShellState},
+        single_pixel_buffer::SinglePixelBufferState,
+        viewporter::ViewporterState,
     },
 };
 use wayland_server::{
     backend::{ClientId, DisconnectReason},
     Client, DisplayHandle,
 };
+use wm_runtime::{Id, WmHandle};
 
 use crate::{
     backend::Backend,
-    scene::Scene,
+    capture::CaptureState,
+    input,
+    output::ConnectedOutput,
+    scene::{Scene, SnapshotIndex},
     shell::Shell,
-    wayland::{ext::foreign_toplevel::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1, versions},
-    Loop,
+    wayland::{
+        ext::{
+            foreign_toplevel::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
+            image_capture_source::{
+                ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
+                ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+            },
+            image_copy_capture::ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+        },
+        versions,
+        wlr::{
+            output_management::{zwlr_output_manager_v1::ZwlrOutputManagerV1, OutputManagementState},
+            screencopy::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+        },
+    },
+    Loop, StartupError,
 };
 
 #[derive(Debug)]
 pub struct Aerugo {
+    pub r#loop: LoopHandle<'static, Loop>,
     pub display: DisplayHandle,
     pub shell: Shell,
     pub scene: Scene,
-    // This is not what I want in the future, but is for testing.
-    pub output: Output,
+    pub outputs: Vec<ConnectedOutput>,
     pub backend: Box<dyn Backend>,
     pub wl_compositor: CompositorState,
     pub xdg_shell: XdgShellState,
+    pub xdg_decoration: XdgDecorationState,
+    pub presentation: PresentationState,
+    pub fractional_scale: FractionalScaleManagerState,
+    pub viewporter: ViewporterState,
+    pub single_pixel_buffer: SinglePixelBufferState,
+    pub clock: Clock<Monotonic>,
     pub seat_state: SeatState<Self>,
+    pub seat: Seat<Self>,
+    pub captures: CaptureState,
+    pub output_manager: OutputManagerState,
+    pub output_management: OutputManagementState,
+    pub wm: Option<WmHandle>,
+    /// Snapshots given to the WM which the WM has not dropped yet.
+    pub snapshots: FxHashMap<Id, SnapshotIndex>,
+    pub pointer_location: Point<f64, Logical>,
     pub generation: u64,
 }
 
 impl Aerugo {
-    pub fn new(_loop: &LoopHandle<'static, Loop>, display: DisplayHandle, backend: Box<dyn Backend>) -> Self {
+    pub fn new(
+        r#loop: &LoopHandle<'static, Loop>,
+        display: DisplayHandle,
+        backend: Box<dyn Backend>,
+    ) -> Result<Self, StartupError> {
         // Initialize common globals
-        let seat_state = SeatState::new();
-        let wl_compositor = CompositorState::new::<Self>(&display);
+        let mut seat_state = SeatState::new();
+        let mut seat = seat_state.new_wl_seat(&display, input::DEFAULT_SEAT_NAME);
+        // TODO: Keymap configuration
+        seat.add_keyboard(XkbConfig::default(), input::REPEAT_DELAY, input::REPEAT_RATE)
+            .map_err(StartupError::Keymap)?;
+        seat.add_pointer();
+
+        let wl_compositor = CompositorState::new_v6::<Self>(&display);
         let xdg_shell = XdgShellState::new::<Self>(&display);
+        let xdg_decoration = XdgDecorationState::new::<Self>(&display);
+        let output_manager = OutputManagerState::new_with_xdg_output::<Self>(&display);
+        let clock = Clock::<Monotonic>::new();
+        let presentation = PresentationState::new::<Self>(&display, Monotonic::ID as u32);
+        let fractional_scale = FractionalScaleManagerState::new::<Self>(&display);
+        let viewporter = ViewporterState::new::<Self>(&display);
+        let single_pixel_buffer = SinglePixelBufferState::new::<Self>(&display);
         let _foreign_toplevel_list =
             display.create_global::<Self, ExtForeignToplevelListV1, _>(versions::EXT_FOREIGN_TOPLEVEL_LIST_V1, ());
-        let output = Output::new(
-            "Test output".into(),
-            PhysicalProperties {
-                size: (0, 0).into(),
-                subpixel: smithay::output::Subpixel::Unknown,
-                make: String::new(),
-                model: String::new(),
-            },
+        let _output_capture_source = display
+            .create_global::<Self, ExtOutputImageCaptureSourceManagerV1, _>(versions::EXT_IMAGE_CAPTURE_SOURCE_V1, ());
+        let _toplevel_capture_source = display.create_global::<Self, ExtForeignToplevelImageCaptureSourceManagerV1, _>(
+            versions::EXT_IMAGE_CAPTURE_SOURCE_V1,
+            (),
         );
-        output.create_global::<Self>(&display);
-
-        let mut scene = Scene::new();
-        scene.create_output(output.clone());
+        let _image_copy_capture =
+            display.create_global::<Self, ExtImageCopyCaptureManagerV1, _>(versions::EXT_IMAGE_COPY_CAPTURE_V1, ());
+        let _screencopy = display.create_global::<Self, ZwlrScreencopyManagerV1, _>(versions::ZWLR_SCREENCOPY_V1, ());
+        let _output_management =
+            display.create_global::<Self, ZwlrOutputManagerV1, _>(versions::ZWLR_OUTPUT_MANAGER_V1, ());
+        // Outputs are added to the scene when the backend announces the outputs.
+        let scene = Scene::new();
 
         let shell = Shell::new();
 
@@ -71,17 +126,31 @@ impl Aerugo {
             // If the system time is messed up, pick some predefined generation timestamp.
             .unwrap_or(u64::MAX);
 
-        Self {
+        Ok(Self {
+            r#loop: r#loop.clone(),
             display,
             wl_compositor,
             xdg_shell,
+            xdg_decoration,
+            presentation,
+            fractional_scale,
+            viewporter,
+            single_pixel_buffer,
+            clock,
             seat_state,
+            seat,
+            captures: CaptureState::default(),
+            output_manager,
+            output_management: OutputManagementState::default(),
+            wm: None,
+            snapshots: FxHashMap::default(),
+            pointer_location: Point::default(),
             shell,
             scene,
-            output,
+            outputs: Vec::new(),
             backend,
             generation,
-        }
+        })
     }
 }
 
@@ -119,6 +188,15 @@ bitflags! {
 
         /// Whether the `aerugo-shell-v1` protocol is available.
         const AERUGO_SHELL = 0x40;
+
+        /// Whether screen capture globals are available.
+        ///
+        /// This enables the `ext-image-copy-capture-v1` protocol along with the capture source protocols and the
+        /// `zwlr-screencopy-v1` protocol.
+        const SCREEN_CAPTURE = 0x80;
+
+        /// Whether the `zwlr-output-management-v1` protocol is available.
+        const OUTPUT_MANAGEMENT = 0x100;
     }
 }
 
@@ -0,0 +1,311 @@
+//! Helpers for tests which need a server with a connected client.
+//!
+//! The server uses the headless backend and is dispatched by the test instead of an event loop thread, so no
+//! display, GPU or listening socket is needed. The client is connected to the server over a socket pair and uses
+//! `wayland-client`, which lets tests create real surfaces and subsurfaces with buffers.
+
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    os::{fd::AsFd, unix::net::UnixStream},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use calloop::EventLoop;
+use smithay::wayland::compositor::CompositorClientState;
+use wayland_client::{
+    backend::WaylandError,
+    delegate_noop,
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_callback::{self, WlCallback},
+        wl_compositor::WlCompositor,
+        wl_region::WlRegion,
+        wl_registry::{self, WlRegistry},
+        wl_shm::{self, WlShm},
+        wl_shm_pool::WlShmPool,
+        wl_subcompositor::WlSubcompositor,
+        wl_subsurface::WlSubsurface,
+        wl_surface::WlSurface,
+    },
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+};
+use wayland_server::{protocol::wl_surface, Client, Display};
+
+use crate::{
+    backend::headless::{self, VirtualOutput},
+    state::{ClientData, PrivilegedGlobals},
+    Aerugo, Loop,
+};
+
+/// A server using the headless backend.
+pub struct TestServer {
+    pub event_loop: EventLoop<'static, Loop>,
+    pub display: Display<Aerugo>,
+    pub state: Loop,
+}
+
+impl TestServer {
+    /// Create a server without any outputs.
+    pub fn new() -> Self {
+        Self::with_outputs([])
+    }
+
+    /// Create a server with the virtual outputs.
+    pub fn with_outputs(outputs: impl IntoIterator<Item = VirtualOutput>) -> Self {
+        let event_loop = EventLoop::try_new().unwrap();
+        let display = Display::<Aerugo>::new().unwrap();
+        let handle = event_loop.handle();
+        let backend = headless::Backend::new(handle.clone(), display.handle(), outputs).unwrap();
+        let comp = Aerugo::new(&handle, display.handle(), Box::new(backend)).unwrap();
+        let state = Loop {
+            r#loop: handle,
+            signal: event_loop.get_signal(),
+            comp,
+            display: display.handle(),
+        };
+
+        let mut server = Self {
+            event_loop,
+            display,
+            state,
+        };
+
+        // Announce the outputs.
+        server.dispatch();
+        server
+    }
+
+    pub fn comp(&mut self) -> &mut Aerugo {
+        &mut self.state.comp
+    }
+
+    /// Process pending events and client requests once, like an iteration of the event loop.
+    pub fn dispatch(&mut self) {
+        self.event_loop.dispatch(Duration::ZERO, &mut self.state).unwrap();
+        self.display.dispatch_clients(&mut self.state.comp).unwrap();
+        self.state.comp.scene.update_surface_outputs();
+        self.display.flush_clients().unwrap();
+    }
+}
+
+/// A client connected to a [`TestServer`].
+pub struct TestClient {
+    pub connection: Connection,
+    pub queue: EventQueue<ClientState>,
+    pub state: ClientState,
+    pub compositor: WlCompositor,
+    pub subcompositor: WlSubcompositor,
+    pub shm: WlShm,
+    /// The client on the server side of the connection.
+    pub client: Client,
+}
+
+/// The state of a [`TestClient`].
+#[derive(Debug, Default)]
+pub struct ClientState {
+    /// The name, interface and version of each global.
+    globals: Vec<(u32, String, u32)>,
+    /// The number of `wl_display.sync` requests the server responded to.
+    syncs: usize,
+}
+
+impl TestClient {
+    /// Connect a client to the server and bind the globals used to create surfaces.
+    pub fn new(server: &mut TestServer) -> Self {
+        let (stream, server_stream) = UnixStream::pair().unwrap();
+        let client = server
+            .display
+            .handle()
+            .insert_client(
+                server_stream,
+                Arc::new(ClientData {
+                    globals: PrivilegedGlobals::all(),
+                    compositor: CompositorClientState::default(),
+                }),
+            )
+            .unwrap();
+
+        let connection = Connection::from_socket(stream).unwrap();
+        let mut queue = connection.new_event_queue();
+        let mut state = ClientState::default();
+        let qh = queue.handle();
+
+        // Learn the names of the globals before binding the globals.
+        let registry = connection.display().get_registry(&qh, ());
+        roundtrip(&connection, &mut queue, &mut state, server);
+
+        let mut client = Self {
+            compositor: state.bind(&registry, &qh, 6),
+            subcompositor: state.bind(&registry, &qh, 1),
+            shm: state.bind(&registry, &qh, 1),
+            connection,
+            queue,
+            state,
+            client,
+        };
+
+        client.roundtrip(server);
+        client
+    }
+
+    pub fn qh(&self) -> QueueHandle<ClientState> {
+        self.queue.handle()
+    }
+
+    /// Exchange messages with the server until the server processed every request sent so far.
+    pub fn roundtrip(&mut self, server: &mut TestServer) {
+        roundtrip(&self.connection, &mut self.queue, &mut self.state, server);
+    }
+
+    pub fn create_surface(&self) -> WlSurface {
+        self.compositor.create_surface(&self.qh(), ())
+    }
+
+    pub fn create_subsurface(&self, surface: &WlSurface, parent: &WlSurface) -> WlSubsurface {
+        self.subcompositor.get_subsurface(surface, parent, &self.qh(), ())
+    }
+
+    /// Create a region from rectangles formatted as `(x, y, width, height)`.
+    pub fn create_region(&self, rectangles: &[(i32, i32, i32, i32)]) -> WlRegion {
+        let region = self.compositor.create_region(&self.qh(), ());
+
+        for &(x, y, width, height) in rectangles {
+            region.add(x, y, width, height);
+        }
+
+        region
+    }
+
+    /// Create an `Argb8888` shared memory buffer filled with the pixel.
+    ///
+    /// The pixel is formatted as `0xAARRGGBB`.
+    pub fn create_buffer(&self, width: i32, height: i32, pixel: u32) -> WlBuffer {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        // The file is unlinked once created, the pool keeps the memory alive.
+        let path = std::env::temp_dir().join(format!(
+            "aerugo-test-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let pixels = pixel.to_le_bytes().repeat((width * height) as usize);
+        file.write_all(&pixels).unwrap();
+
+        let pool = self.shm.create_pool(file.as_fd(), pixels.len() as i32, &self.qh(), ());
+        let buffer = pool.create_buffer(0, width, height, width * 4, wl_shm::Format::Argb8888, &self.qh(), ());
+        pool.destroy();
+        buffer
+    }
+
+    /// The server side of a surface created by the client.
+    pub fn server_surface(&self, server: &TestServer, surface: &WlSurface) -> wl_surface::WlSurface {
+        self.client
+            .object_from_protocol_id(&server.display.handle(), surface.id().protocol_id())
+            .unwrap()
+    }
+}
+
+impl ClientState {
+    fn bind<I: Proxy + 'static>(&self, registry: &WlRegistry, qh: &QueueHandle<Self>, version: u32) -> I
+    where
+        Self: Dispatch<I, ()>,
+    {
+        let &(name, _, available) = self
+            .globals
+            .iter()
+            .find(|(_, interface, _)| interface == I::interface().name)
+            .unwrap_or_else(|| panic!("{} is not advertised", I::interface().name));
+
+        registry.bind(name, version.min(available), qh, ())
+    }
+}
+
+fn roundtrip(
+    connection: &Connection,
+    queue: &mut EventQueue<ClientState>,
+    state: &mut ClientState,
+    server: &mut TestServer,
+) {
+    let syncs = state.syncs;
+    connection.display().sync(&queue.handle(), ());
+
+    for _ in 0..100 {
+        connection.flush().unwrap();
+        server.dispatch();
+
+        if let Some(guard) = queue.prepare_read() {
+            match guard.read() {
+                Ok(_) => (),
+                Err(WaylandError::Io(err)) if err.kind() == ErrorKind::WouldBlock => (),
+                Err(err) => panic!("failed to read events: {err}"),
+            }
+        }
+
+        queue.dispatch_pending(state).unwrap();
+
+        if state.syncs > syncs {
+            return;
+        }
+    }
+
+    panic!("the server did not respond to the client");
+}
+
+impl Dispatch<WlRegistry, ()> for ClientState {
+    fn event(
+        state: &mut Self,
+        _registry: &WlRegistry,
+        event: wl_registry::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } => state.globals.push((name, interface, version)),
+            wl_registry::Event::GlobalRemove { name } => state.globals.retain(|(global, _, _)| *global != name),
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<WlCallback, ()> for ClientState {
+    fn event(
+        state: &mut Self,
+        _callback: &WlCallback,
+        event: wl_callback::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            state.syncs += 1;
+        }
+    }
+}
+
+delegate_noop!(ClientState: WlCompositor);
+delegate_noop!(ClientState: WlSubcompositor);
+delegate_noop!(ClientState: WlSubsurface);
+delegate_noop!(ClientState: WlShmPool);
+delegate_noop!(ClientState: WlRegion);
+delegate_noop!(ClientState: ignore WlShm);
+delegate_noop!(ClientState: ignore WlSurface);
+delegate_noop!(ClientState: ignore WlBuffer);
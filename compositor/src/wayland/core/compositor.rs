@@ -2,11 +2,12 @@ use std::borrow::Cow;
 
 use smithay::{
     backend::renderer::utils::on_commit_buffer_handler,
-    wayland::compositor::{self, CompositorClientState, CompositorHandler, CompositorState},
+    output::Output,
+    wayland::compositor::{self, CompositorClientState, CompositorHandler, CompositorState, TraversalAction},
 };
 use wayland_server::{protocol::wl_surface::WlSurface, Client};
 
-use crate::{shell::Shell, state::ClientData, Aerugo};
+use crate::{scene::Scene, shell::Shell, state::ClientData, wayland::wp::viewporter, Aerugo};
 
 impl CompositorHandler for Aerugo {
     fn compositor_state(&mut self) -> &mut CompositorState {
@@ -14,11 +15,20 @@ impl CompositorHandler for Aerugo {
     }
 
     fn commit(&mut self, surface: &WlSurface) {
+        // The buffer handler releases a removed buffer, so the shell must see the contents of a toplevel being
+        // unmapped first.
+        Shell::pre_commit(self, surface);
+
         // Let Smithay perform buffer management for us.
         //
         // on_commit_buffer_handler will manage the buffer, damage and opaque regions.
         on_commit_buffer_handler::<Self>(surface);
 
+        // The client is disconnected if the viewport crops outside of the buffer.
+        if !viewporter::validate_viewport(surface) {
+            return;
+        }
+
         // If the surface is sync the parent needs to be committed to apply the pending state.
         //
         // The parent surface will always return `false`
@@ -33,9 +43,27 @@ impl CompositorHandler for Aerugo {
             surface = Cow::Owned(parent);
         }
 
+        // The surface tree may be moved off of or unmapped from the outputs it was on.
+        let mut outputs = surface_tree_outputs(&self.scene, &surface);
+
         // Commit the root surface state in the shell. This will complete any transactions that are in flight
         // and are waiting for the acked state to be applied.
         Shell::commit(self, &surface);
+
+        // Subsurfaces may have been added, removed, moved or restacked.
+        self.scene.apply_surface_commit(&surface);
+        self.scene.update_surface_outputs();
+
+        for output in surface_tree_outputs(&self.scene, &surface) {
+            if !outputs.contains(&output) {
+                outputs.push(output);
+            }
+        }
+
+        // Only the outputs the surface tree is or was on need to be rendered.
+        for output in outputs {
+            self.backend.schedule_render(&output);
+        }
     }
 
     fn client_compositor_state<'a>(&self, client: &'a Client) -> &'a CompositorClientState {
@@ -43,8 +71,36 @@ impl CompositorHandler for Aerugo {
     }
 
     fn destroyed(&mut self, surface: &WlSurface) {
-        Shell::remove_toplevel(self, surface)
+        let outputs = self.scene.surface_outputs(surface).to_vec();
+
+        Shell::remove_toplevel(self, surface);
+        self.scene.surface_destroyed(surface);
+
+        for output in outputs {
+            self.backend.schedule_render(&output);
+        }
     }
 }
 
+/// The outputs any surface in the surface tree is on.
+fn surface_tree_outputs(scene: &Scene, surface: &WlSurface) -> Vec<Output> {
+    let mut outputs = Vec::<Output>::new();
+
+    compositor::with_surface_tree_downward(
+        surface,
+        (),
+        |_, _, _| TraversalAction::DoChildren(()),
+        |surface, _, _| {
+            for output in scene.surface_outputs(surface) {
+                if !outputs.contains(output) {
+                    outputs.push(output.clone());
+                }
+            }
+        },
+        |_, _, _| true,
+    );
+
+    outputs
+}
+
 smithay::delegate_compositor!(Aerugo);
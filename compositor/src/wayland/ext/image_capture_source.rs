@@ -0,0 +1,152 @@
+//! Implementation for the `ext-image-capture-source-v1` protocol.
+
+#![allow(non_upper_case_globals, non_camel_case_types)]
+
+// ext-image-capture-source-v1 is not yet part of wayland-protocols so we need to generate it
+
+use smithay::output::Output;
+use wayland_server::{Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource};
+
+use crate::{
+    capture::CaptureSource, shell::ToplevelId, wayland::ext::foreign_toplevel::ext_foreign_toplevel_handle_v1, Aerugo,
+    ClientData, PrivilegedGlobals,
+};
+
+use self::{
+    ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
+    ext_image_capture_source_v1::ExtImageCaptureSourceV1,
+    ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+};
+
+use smithay::reexports::wayland_server::{self, protocol::wl_output};
+
+#[allow(non_upper_case_globals)]
+pub mod __interfaces {
+    use crate::wayland::ext::foreign_toplevel::__interfaces::*;
+    use smithay::reexports::wayland_server::{backend as wayland_backend, protocol::__interfaces::*};
+    wayland_scanner::generate_interfaces!("../protocols/ext-image-capture-source-v1.xml");
+}
+use self::__interfaces::*;
+
+wayland_scanner::generate_server_code!("../protocols/ext-image-capture-source-v1.xml");
+
+/// The source of a capture source object.
+///
+/// The source is [`None`] if the output was already destroyed when the source was created.
+pub type CaptureSourceData = Option<CaptureSource>;
+
+impl GlobalDispatch<ExtOutputImageCaptureSourceManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtOutputImageCaptureSourceManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::SCREEN_CAPTURE))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ExtOutputImageCaptureSourceManagerV1, ()> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ExtOutputImageCaptureSourceManagerV1,
+        request: ext_output_image_capture_source_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            ext_output_image_capture_source_manager_v1::Request::CreateSource { source, output } => {
+                let output = Output::from_resource(&output).map(CaptureSource::Output);
+                init.init::<_, CaptureSourceData>(source, output);
+            }
+
+            ext_output_image_capture_source_manager_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl GlobalDispatch<ExtForeignToplevelImageCaptureSourceManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtForeignToplevelImageCaptureSourceManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::SCREEN_CAPTURE))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ExtForeignToplevelImageCaptureSourceManagerV1, ()> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ExtForeignToplevelImageCaptureSourceManagerV1,
+        request: ext_foreign_toplevel_image_capture_source_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            ext_foreign_toplevel_image_capture_source_manager_v1::Request::CreateSource {
+                source,
+                toplevel_handle,
+            } => {
+                // If the toplevel was closed, sessions created from the source are stopped immediately.
+                let toplevel = toplevel_handle
+                    .data::<ToplevelId>()
+                    .copied()
+                    .map(CaptureSource::Toplevel);
+                init.init::<_, CaptureSourceData>(source, toplevel);
+            }
+
+            ext_foreign_toplevel_image_capture_source_manager_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ExtImageCaptureSourceV1, CaptureSourceData> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ExtImageCaptureSourceV1,
+        request: ext_image_capture_source_v1::Request,
+        _data: &CaptureSourceData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            // Sessions created from the source are not affected by destroying the source.
+            ext_image_capture_source_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
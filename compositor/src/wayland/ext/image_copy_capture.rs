@@ -0,0 +1,440 @@
+//! Implementation for the `ext-image-copy-capture-v1` protocol.
+
+#![allow(non_upper_case_globals, non_camel_case_types)]
+
+// ext-image-copy-capture-v1 is not yet part of wayland-protocols so we need to generate it
+
+use std::{sync::Mutex, time::Duration};
+
+use smithay::utils::{Physical, Size};
+use wayland_server::{backend::ClientId, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource};
+
+use crate::{
+    capture::{Capture, CaptureError, CaptureFrame, CaptureSource, SHM_FORMATS},
+    wayland::ext::image_capture_source::{ext_image_capture_source_v1, CaptureSourceData},
+    Aerugo, ClientData, PrivilegedGlobals,
+};
+
+use self::{
+    ext_image_copy_capture_cursor_session_v1::ExtImageCopyCaptureCursorSessionV1,
+    ext_image_copy_capture_frame_v1::ExtImageCopyCaptureFrameV1,
+    ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+    ext_image_copy_capture_session_v1::ExtImageCopyCaptureSessionV1,
+};
+
+use smithay::reexports::wayland_server::{
+    self,
+    protocol::{wl_buffer, wl_output, wl_pointer, wl_shm},
+};
+
+#[allow(non_upper_case_globals)]
+pub mod __interfaces {
+    use crate::wayland::ext::image_capture_source::__interfaces::*;
+    use smithay::reexports::wayland_server::{backend as wayland_backend, protocol::__interfaces::*};
+    wayland_scanner::generate_interfaces!("../protocols/ext-image-copy-capture-v1.xml");
+}
+use self::__interfaces::*;
+
+wayland_scanner::generate_server_code!("../protocols/ext-image-copy-capture-v1.xml");
+
+#[derive(Debug)]
+pub struct SessionData {
+    /// The source being captured, [`None`] if the source was unavailable when the session was created.
+    source: Option<CaptureSource>,
+    inner: Mutex<SessionState>,
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    /// The buffer size which was last sent to the client.
+    size: Size<i32, Physical>,
+
+    /// The frame which is currently being used with the session.
+    frame: Option<ExtImageCopyCaptureFrameV1>,
+
+    /// Whether a frame has been captured during the session.
+    captured: bool,
+
+    stopped: bool,
+}
+
+#[derive(Debug)]
+pub struct FrameData {
+    session: ExtImageCopyCaptureSessionV1,
+    inner: Mutex<FrameState>,
+}
+
+#[derive(Debug, Default)]
+struct FrameState {
+    buffer: Option<wl_buffer::WlBuffer>,
+
+    /// The size of the capture, set once the capture was requested.
+    size: Option<Size<i32, Physical>>,
+}
+
+impl GlobalDispatch<ExtImageCopyCaptureManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtImageCopyCaptureManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::SCREEN_CAPTURE))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureManagerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ExtImageCopyCaptureManagerV1,
+        request: ext_image_copy_capture_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            ext_image_copy_capture_manager_v1::Request::CreateSession {
+                session,
+                source,
+                options,
+            } => {
+                // TODO: Paint cursors once the cursor is part of the scene.
+                if options.into_result().is_err() {
+                    resource.post_error(
+                        ext_image_copy_capture_manager_v1::Error::InvalidOption,
+                        "unknown capture options",
+                    );
+                    return;
+                }
+
+                let source = source.data::<CaptureSourceData>().cloned().flatten();
+                create_session(state, init, session, source);
+            }
+
+            ext_image_copy_capture_manager_v1::Request::CreatePointerCursorSession { session, .. } => {
+                init.init(session, ());
+            }
+
+            ext_image_copy_capture_manager_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, SessionData> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &ExtImageCopyCaptureSessionV1,
+        request: ext_image_copy_capture_session_v1::Request,
+        data: &SessionData,
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            ext_image_copy_capture_session_v1::Request::CreateFrame { frame } => {
+                let mut session = data.inner.lock().unwrap();
+
+                if session.frame.as_ref().map_or(false, Resource::is_alive) {
+                    resource.post_error(
+                        ext_image_copy_capture_session_v1::Error::DuplicateFrame,
+                        "the previous frame was not destroyed",
+                    );
+                    return;
+                }
+
+                let frame = init.init(
+                    frame,
+                    FrameData {
+                        session: resource.clone(),
+                        inner: Mutex::new(FrameState::default()),
+                    },
+                );
+                session.frame = Some(frame);
+            }
+
+            ext_image_copy_capture_session_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ExtImageCopyCaptureSessionV1, _data: &SessionData) {
+        state.captures.sessions.retain(|session| session != resource);
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, FrameData> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ExtImageCopyCaptureFrameV1,
+        request: ext_image_copy_capture_frame_v1::Request,
+        data: &FrameData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            ext_image_copy_capture_frame_v1::Request::AttachBuffer { buffer } => {
+                let mut frame = data.inner.lock().unwrap();
+
+                if frame.size.is_some() {
+                    resource.post_error(
+                        ext_image_copy_capture_frame_v1::Error::AlreadyCaptured,
+                        "the frame was already captured",
+                    );
+                    return;
+                }
+
+                frame.buffer = Some(buffer);
+            }
+
+            ext_image_copy_capture_frame_v1::Request::DamageBuffer { x, y, width, height } => {
+                if data.inner.lock().unwrap().size.is_some() {
+                    resource.post_error(
+                        ext_image_copy_capture_frame_v1::Error::AlreadyCaptured,
+                        "the frame was already captured",
+                    );
+                    return;
+                }
+
+                if x < 0 || y < 0 || width <= 0 || height <= 0 {
+                    resource.post_error(
+                        ext_image_copy_capture_frame_v1::Error::InvalidBufferDamage,
+                        "invalid buffer damage",
+                    );
+                }
+
+                // TODO: Only copy the damaged area of the buffer. For now the whole buffer is always copied.
+            }
+
+            ext_image_copy_capture_frame_v1::Request::Capture => capture(state, resource, data),
+
+            ext_image_copy_capture_frame_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(_state: &mut Self, _client: ClientId, resource: &ExtImageCopyCaptureFrameV1, data: &FrameData) {
+        let mut session = data.session.data::<SessionData>().unwrap().inner.lock().unwrap();
+
+        if session.frame.as_ref() == Some(resource) {
+            session.frame = None;
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureCursorSessionV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ExtImageCopyCaptureCursorSessionV1,
+        request: ext_image_copy_capture_cursor_session_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            // TODO: Capture the cursor once the cursor is part of the scene. Until then the session is stopped.
+            ext_image_copy_capture_cursor_session_v1::Request::GetCaptureSession { session } => {
+                create_session(state, init, session, None);
+            }
+
+            ext_image_copy_capture_cursor_session_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Notify the client that the frame was captured.
+pub(crate) fn frame_ready(frame: &ExtImageCopyCaptureFrameV1, time: Duration) {
+    let size = frame
+        .data::<FrameData>()
+        .and_then(|data| data.inner.lock().unwrap().size)
+        .unwrap_or_default();
+
+    frame.transform(wl_output::Transform::Normal);
+    // The whole buffer is copied.
+    frame.damage(0, 0, size.w, size.h);
+    frame.presentation_time(
+        (time.as_secs() >> 32) as u32,
+        time.as_secs() as u32,
+        time.subsec_nanos(),
+    );
+    frame.ready();
+}
+
+/// Notify the client that the frame could not be captured.
+pub(crate) fn frame_failed(frame: &ExtImageCopyCaptureFrameV1, err: &CaptureError) {
+    frame.failed(failure_reason(err));
+}
+
+/// The reason sent to the client when a capture fails.
+fn failure_reason(err: &CaptureError) -> ext_image_copy_capture_frame_v1::FailureReason {
+    match err {
+        CaptureError::BufferConstraints => ext_image_copy_capture_frame_v1::FailureReason::BufferConstraints,
+        CaptureError::SourceUnavailable => ext_image_copy_capture_frame_v1::FailureReason::Stopped,
+        CaptureError::Render(_) => ext_image_copy_capture_frame_v1::FailureReason::Unknown,
+    }
+}
+
+/// Stop every session capturing the source.
+pub(crate) fn stop_sessions(state: &mut Aerugo, source: &CaptureSource) {
+    state.captures.sessions.retain(|session| {
+        let data = session.data::<SessionData>().unwrap();
+
+        if data.source.as_ref() != Some(source) {
+            return true;
+        }
+
+        data.inner.lock().unwrap().stopped = true;
+        session.stopped();
+        false
+    });
+}
+
+fn create_session(
+    state: &mut Aerugo,
+    init: &mut DataInit<'_, Aerugo>,
+    session: New<ExtImageCopyCaptureSessionV1>,
+    source: Option<CaptureSource>,
+) {
+    let size = source.as_ref().and_then(|source| state.capture_size(source));
+
+    let session = init.init(
+        session,
+        SessionData {
+            source,
+            inner: Mutex::new(SessionState::default()),
+        },
+    );
+
+    match size {
+        Some(size) => {
+            send_constraints(&session, size);
+            state.captures.sessions.push(session);
+        }
+
+        None => {
+            session.data::<SessionData>().unwrap().inner.lock().unwrap().stopped = true;
+            session.stopped();
+        }
+    }
+}
+
+fn send_constraints(session: &ExtImageCopyCaptureSessionV1, size: Size<i32, Physical>) {
+    session.data::<SessionData>().unwrap().inner.lock().unwrap().size = size;
+
+    session.buffer_size(size.w as u32, size.h as u32);
+
+    for format in SHM_FORMATS {
+        session.shm_format(format);
+    }
+
+    // TODO: Dmabuf constraints
+    session.done();
+}
+
+fn capture(state: &mut Aerugo, resource: &ExtImageCopyCaptureFrameV1, data: &FrameData) {
+    let mut frame = data.inner.lock().unwrap();
+
+    if frame.size.is_some() {
+        resource.post_error(
+            ext_image_copy_capture_frame_v1::Error::AlreadyCaptured,
+            "the frame was already captured",
+        );
+        return;
+    }
+
+    let Some(buffer) = frame.buffer.clone() else {
+        resource.post_error(
+            ext_image_copy_capture_frame_v1::Error::NoBuffer,
+            "no buffer was attached",
+        );
+        return;
+    };
+
+    let session_data = data.session.data::<SessionData>().unwrap();
+    let source = session_data.source.clone();
+    let size = source.as_ref().and_then(|source| state.capture_size(source));
+
+    let (Some(source), Some(size)) = (source, size) else {
+        frame.size = Some(Size::default());
+        resource.failed(ext_image_copy_capture_frame_v1::FailureReason::Stopped);
+        return;
+    };
+
+    frame.size = Some(size);
+    drop(frame);
+
+    let mut session = session_data.inner.lock().unwrap();
+
+    if session.stopped {
+        resource.failed(ext_image_copy_capture_frame_v1::FailureReason::Stopped);
+        return;
+    }
+
+    // The size of the source changed, so the client needs to allocate a new buffer.
+    if session.size != size {
+        drop(session);
+        send_constraints(&data.session, size);
+        resource.failed(ext_image_copy_capture_frame_v1::FailureReason::BufferConstraints);
+        return;
+    }
+
+    // Capture the first frame immediately, later frames are captured when the source is damaged.
+    let immediate = !session.captured;
+    session.captured = true;
+    drop(session);
+
+    state.capture(
+        Capture {
+            source,
+            frame: CaptureFrame::Ext(resource.clone()),
+            buffer,
+            region: None,
+        },
+        immediate,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capture::CaptureError;
+
+    use super::{ext_image_copy_capture_frame_v1::FailureReason, failure_reason};
+
+    /// Clients reallocate their buffer if the constraints were not met and stop capturing if the source is gone.
+    #[test]
+    fn failure_reasons() {
+        assert_eq!(
+            failure_reason(&CaptureError::BufferConstraints),
+            FailureReason::BufferConstraints
+        );
+        assert_eq!(failure_reason(&CaptureError::SourceUnavailable), FailureReason::Stopped);
+        assert_eq!(
+            failure_reason(&CaptureError::Render("out of memory".into())),
+            FailureReason::Unknown
+        );
+    }
+}
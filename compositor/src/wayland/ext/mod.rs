@@ -1,3 +1,5 @@
 //! `ext` vendored wayland protocol implementations
 
 pub mod foreign_toplevel;
+pub mod image_capture_source;
+pub mod image_copy_capture;
@@ -7,9 +7,16 @@
 
 pub mod core;
 pub mod ext;
+pub mod wlr;
+pub mod wp;
 
+pub mod xdg_decoration;
 pub mod xdg_shell;
 
 pub mod versions {
     pub const EXT_FOREIGN_TOPLEVEL_LIST_V1: u32 = 1;
+    pub const EXT_IMAGE_CAPTURE_SOURCE_V1: u32 = 1;
+    pub const EXT_IMAGE_COPY_CAPTURE_V1: u32 = 1;
+    pub const ZWLR_OUTPUT_MANAGER_V1: u32 = 4;
+    pub const ZWLR_SCREENCOPY_V1: u32 = 3;
 }
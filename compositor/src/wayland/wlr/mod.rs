@@ -0,0 +1,4 @@
+//! `wlr` wayland protocol implementations
+
+pub mod output_management;
+pub mod screencopy;
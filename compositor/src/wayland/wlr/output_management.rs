@@ -0,0 +1,556 @@
+//! Implementation for the `wlr-output-management-unstable-v1` protocol.
+//!
+//! Configurations created by clients are applied with [`Aerugo::configure_outputs`], the same path every other
+//! change to outputs goes through.
+
+use std::sync::Mutex;
+
+use smithay::{
+    output::{Mode, Output},
+    utils::{Logical, Point, Transform},
+};
+use wayland_server::{
+    backend::ClientId, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+};
+
+pub use smithay::reexports::wayland_protocols_wlr::output_management::v1::server::{
+    zwlr_output_configuration_head_v1, zwlr_output_configuration_v1, zwlr_output_head_v1, zwlr_output_manager_v1,
+    zwlr_output_mode_v1,
+};
+
+use crate::{
+    output::{ConnectedOutput, OutputChange, OutputConfiguration},
+    Aerugo, ClientData, PrivilegedGlobals,
+};
+
+use self::{
+    zwlr_output_configuration_head_v1::ZwlrOutputConfigurationHeadV1,
+    zwlr_output_configuration_v1::ZwlrOutputConfigurationV1, zwlr_output_head_v1::ZwlrOutputHeadV1,
+    zwlr_output_manager_v1::ZwlrOutputManagerV1, zwlr_output_mode_v1::ZwlrOutputModeV1,
+};
+
+/// Output management state.
+#[derive(Debug, Default)]
+pub struct OutputManagementState {
+    managers: Vec<ManagerInstance>,
+
+    /// Serial of the current output state.
+    ///
+    /// Configurations created for an older serial are cancelled.
+    serial: u32,
+}
+
+#[derive(Debug)]
+struct ManagerInstance {
+    manager: ZwlrOutputManagerV1,
+    heads: Vec<HeadInstance>,
+}
+
+#[derive(Debug)]
+struct HeadInstance {
+    output: Output,
+    head: ZwlrOutputHeadV1,
+    modes: Vec<ZwlrOutputModeV1>,
+}
+
+#[derive(Debug)]
+pub struct ModeData {
+    output: Output,
+    mode: Mode,
+}
+
+#[derive(Debug)]
+pub struct ConfigurationData {
+    manager: ZwlrOutputManagerV1,
+    serial: u32,
+    inner: Mutex<ConfigurationState>,
+}
+
+#[derive(Debug, Default)]
+struct ConfigurationState {
+    /// Whether the configuration was applied or tested.
+    used: bool,
+    heads: Vec<(Output, Option<ZwlrOutputConfigurationHeadV1>)>,
+}
+
+#[derive(Debug)]
+pub struct HeadConfigurationData {
+    output: Output,
+    inner: Mutex<HeadChanges>,
+}
+
+#[derive(Debug, Default)]
+struct HeadChanges {
+    mode: Option<Mode>,
+    location: Option<Point<i32, Logical>>,
+    transform: Option<Transform>,
+    scale: Option<f64>,
+}
+
+/// Notify output management clients that the state of the outputs has changed.
+pub(crate) fn outputs_changed(state: &mut Aerugo) {
+    let management = &mut state.output_management;
+    management.serial = management.serial.wrapping_add(1);
+
+    for instance in &mut management.managers {
+        update_manager(&state.display, instance, &state.outputs, management.serial);
+    }
+}
+
+fn update_manager(display: &DisplayHandle, instance: &mut ManagerInstance, outputs: &[ConnectedOutput], serial: u32) {
+    // Remove the heads of outputs which were disconnected.
+    instance.heads.retain(|head| {
+        let connected = outputs.iter().any(|connected| connected.output() == &head.output);
+
+        if !connected {
+            for mode in &head.modes {
+                mode.finished();
+            }
+
+            head.head.finished();
+        }
+
+        connected
+    });
+
+    for connected in outputs {
+        let output = connected.output();
+
+        if !instance.heads.iter().any(|head| &head.output == output) {
+            let Some(head) = create_head(display, &instance.manager, output) else {
+                continue;
+            };
+
+            instance.heads.push(head);
+        }
+
+        let head = instance.heads.iter().find(|head| &head.output == output).unwrap();
+        head.head.enabled(connected.enabled() as i32);
+
+        if !connected.enabled() {
+            continue;
+        }
+
+        let current_mode = output.current_mode();
+        if let Some(mode) = head
+            .modes
+            .iter()
+            .find(|mode| mode.data::<ModeData>().map(|data| data.mode) == current_mode)
+        {
+            head.head.current_mode(mode);
+        }
+
+        let location = output.current_location();
+        head.head.position(location.x, location.y);
+        head.head.transform(output.current_transform().into());
+        head.head.scale(output.current_scale().fractional_scale());
+
+        if head.head.version() >= zwlr_output_head_v1::EVT_ADAPTIVE_SYNC_SINCE {
+            // TODO: Adaptive sync
+            head.head
+                .adaptive_sync(zwlr_output_head_v1::AdaptiveSyncState::Disabled);
+        }
+    }
+
+    instance.manager.done(serial);
+}
+
+/// Create a head describing the output along with the modes of the output.
+fn create_head(display: &DisplayHandle, manager: &ZwlrOutputManagerV1, output: &Output) -> Option<HeadInstance> {
+    let client = manager.client()?;
+    let head = client
+        .create_resource::<ZwlrOutputHeadV1, _, Aerugo>(display, manager.version(), output.clone())
+        .ok()?;
+    manager.head(&head);
+
+    let properties = output.physical_properties();
+    head.name(output.name());
+    head.description(output.description());
+
+    if properties.size.w > 0 && properties.size.h > 0 {
+        head.physical_size(properties.size.w, properties.size.h);
+    }
+
+    if head.version() >= zwlr_output_head_v1::EVT_MAKE_SINCE {
+        head.make(properties.make);
+        head.model(properties.model);
+    }
+
+    let preferred = output.preferred_mode();
+    let modes = output
+        .modes()
+        .into_iter()
+        .filter_map(|mode| {
+            let resource = client
+                .create_resource::<ZwlrOutputModeV1, _, Aerugo>(
+                    display,
+                    head.version(),
+                    ModeData {
+                        output: output.clone(),
+                        mode,
+                    },
+                )
+                .ok()?;
+
+            head.mode(&resource);
+            resource.size(mode.size.w, mode.size.h);
+
+            if mode.refresh > 0 {
+                resource.refresh(mode.refresh);
+            }
+
+            if Some(mode) == preferred {
+                resource.preferred();
+            }
+
+            Some(resource)
+        })
+        .collect();
+
+    Some(HeadInstance {
+        output: output.clone(),
+        head,
+        modes,
+    })
+}
+
+impl GlobalDispatch<ZwlrOutputManagerV1, ()> for Aerugo {
+    fn bind(
+        state: &mut Self,
+        display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrOutputManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = init.init(resource, ());
+        let mut instance = ManagerInstance {
+            manager,
+            heads: Vec::new(),
+        };
+
+        update_manager(display, &mut instance, &state.outputs, state.output_management.serial);
+        state.output_management.managers.push(instance);
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::OUTPUT_MANAGEMENT))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ZwlrOutputManagerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrOutputManagerV1,
+        request: zwlr_output_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_output_manager_v1::Request::CreateConfiguration { id, serial } => {
+                init.init(
+                    id,
+                    ConfigurationData {
+                        manager: resource.clone(),
+                        serial,
+                        inner: Mutex::new(ConfigurationState::default()),
+                    },
+                );
+            }
+
+            zwlr_output_manager_v1::Request::Stop => {
+                state
+                    .output_management
+                    .managers
+                    .retain(|instance| &instance.manager != resource);
+                resource.finished();
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ZwlrOutputManagerV1, _data: &()) {
+        state
+            .output_management
+            .managers
+            .retain(|instance| &instance.manager != resource);
+    }
+}
+
+impl Dispatch<ZwlrOutputHeadV1, Output> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrOutputHeadV1,
+        request: zwlr_output_head_v1::Request,
+        _data: &Output,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            // Dispatch::destroyed handles cleanup
+            zwlr_output_head_v1::Request::Release => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ZwlrOutputHeadV1, _data: &Output) {
+        for instance in &mut state.output_management.managers {
+            instance.heads.retain(|head| &head.head != resource);
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputModeV1, ModeData> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrOutputModeV1,
+        request: zwlr_output_mode_v1::Request,
+        _data: &ModeData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            // Dispatch::destroyed handles cleanup
+            zwlr_output_mode_v1::Request::Release => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ZwlrOutputModeV1, _data: &ModeData) {
+        for instance in &mut state.output_management.managers {
+            for head in &mut instance.heads {
+                head.modes.retain(|mode| mode != resource);
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationV1, ConfigurationData> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrOutputConfigurationV1,
+        request: zwlr_output_configuration_v1::Request,
+        data: &ConfigurationData,
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        let mut configuration = data.inner.lock().unwrap();
+
+        let (head, enable) = match request {
+            zwlr_output_configuration_v1::Request::EnableHead { id, head } => (head, Some(id)),
+            zwlr_output_configuration_v1::Request::DisableHead { head } => (head, None),
+
+            zwlr_output_configuration_v1::Request::Apply => {
+                drop(configuration);
+                return apply(state, resource, data, false);
+            }
+
+            zwlr_output_configuration_v1::Request::Test => {
+                drop(configuration);
+                return apply(state, resource, data, true);
+            }
+
+            zwlr_output_configuration_v1::Request::Destroy => return,
+
+            _ => unreachable!(),
+        };
+
+        // Every head is created with the output it describes.
+        let output = head.data::<Output>().unwrap().clone();
+
+        // The configuration head is created before checking for errors so the new object is always initialized.
+        let configuration_head = enable.map(|id| {
+            init.init(
+                id,
+                HeadConfigurationData {
+                    output: output.clone(),
+                    inner: Mutex::new(HeadChanges::default()),
+                },
+            )
+        });
+
+        if configuration.used {
+            resource.post_error(
+                zwlr_output_configuration_v1::Error::AlreadyUsed,
+                "the configuration was already used",
+            );
+            return;
+        }
+
+        if configuration.heads.iter().any(|(configured, _)| configured == &output) {
+            resource.post_error(
+                zwlr_output_configuration_v1::Error::AlreadyConfiguredHead,
+                "the head was already configured",
+            );
+            return;
+        }
+
+        configuration.heads.push((output, configuration_head));
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationHeadV1, HeadConfigurationData> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrOutputConfigurationHeadV1,
+        request: zwlr_output_configuration_head_v1::Request,
+        data: &HeadConfigurationData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        let mut changes = data.inner.lock().unwrap();
+
+        let already_set = match request {
+            zwlr_output_configuration_head_v1::Request::SetMode { mode } => {
+                let Some(mode) = mode.data::<ModeData>().filter(|mode| mode.output == data.output) else {
+                    resource.post_error(
+                        zwlr_output_configuration_head_v1::Error::InvalidMode,
+                        "the mode does not belong to the head",
+                    );
+                    return;
+                };
+
+                changes.mode.replace(mode.mode).is_some()
+            }
+
+            zwlr_output_configuration_head_v1::Request::SetCustomMode { width, height, refresh } => {
+                if width <= 0 || height <= 0 || refresh < 0 {
+                    resource.post_error(
+                        zwlr_output_configuration_head_v1::Error::InvalidCustomMode,
+                        "invalid custom mode",
+                    );
+                    return;
+                }
+
+                // Custom modes are only accepted if the output advertises a matching mode.
+                let mode = Mode {
+                    size: (width, height).into(),
+                    refresh,
+                };
+                changes.mode.replace(mode).is_some()
+            }
+
+            zwlr_output_configuration_head_v1::Request::SetPosition { x, y } => {
+                changes.location.replace((x, y).into()).is_some()
+            }
+
+            zwlr_output_configuration_head_v1::Request::SetTransform { transform } => {
+                let WEnum::Value(transform) = transform else {
+                    resource.post_error(
+                        zwlr_output_configuration_head_v1::Error::InvalidTransform,
+                        "invalid transform",
+                    );
+                    return;
+                };
+
+                changes.transform.replace(transform.into()).is_some()
+            }
+
+            zwlr_output_configuration_head_v1::Request::SetScale { scale } => {
+                if !scale.is_finite() || scale <= 0.0 {
+                    resource.post_error(zwlr_output_configuration_head_v1::Error::InvalidScale, "invalid scale");
+                    return;
+                }
+
+                changes.scale.replace(scale).is_some()
+            }
+
+            // TODO: Adaptive sync
+            zwlr_output_configuration_head_v1::Request::SetAdaptiveSync { .. } => false,
+
+            _ => unreachable!(),
+        };
+
+        if already_set {
+            resource.post_error(
+                zwlr_output_configuration_head_v1::Error::AlreadySet,
+                "the property was already set",
+            );
+        }
+    }
+}
+
+fn apply(state: &mut Aerugo, resource: &ZwlrOutputConfigurationV1, data: &ConfigurationData, test_only: bool) {
+    let mut configuration = data.inner.lock().unwrap();
+
+    if configuration.used {
+        resource.post_error(
+            zwlr_output_configuration_v1::Error::AlreadyUsed,
+            "the configuration was already used",
+        );
+        return;
+    }
+
+    configuration.used = true;
+
+    let Some(instance) = state
+        .output_management
+        .managers
+        .iter()
+        .find(|instance| instance.manager == data.manager)
+    else {
+        resource.cancelled();
+        return;
+    };
+
+    // Every head must be either enabled or disabled by the configuration.
+    if instance
+        .heads
+        .iter()
+        .any(|head| !configuration.heads.iter().any(|(output, _)| output == &head.output))
+    {
+        resource.post_error(
+            zwlr_output_configuration_v1::Error::UnconfiguredHead,
+            "not every head was configured",
+        );
+        return;
+    }
+
+    // The outputs changed since the configuration was created.
+    if data.serial != state.output_management.serial {
+        resource.cancelled();
+        return;
+    }
+
+    let outputs = configuration
+        .heads
+        .iter()
+        .map(|(output, head)| {
+            let change = match head.as_ref().and_then(|head| head.data::<HeadConfigurationData>()) {
+                Some(head) => {
+                    let changes = head.inner.lock().unwrap();
+
+                    OutputChange::Enable {
+                        mode: changes.mode,
+                        location: changes.location,
+                        transform: changes.transform,
+                        scale: changes.scale,
+                    }
+                }
+
+                None => OutputChange::Disable,
+            };
+
+            (output.clone(), change)
+        })
+        .collect();
+
+    drop(configuration);
+
+    match state.configure_outputs(&OutputConfiguration { outputs }, test_only) {
+        Ok(()) => resource.succeeded(),
+        Err(err) => {
+            tracing::warn!(%err, test_only, "Output configuration failed");
+            resource.failed();
+        }
+    }
+}
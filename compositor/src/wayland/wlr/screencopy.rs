@@ -0,0 +1,297 @@
+//! Implementation for the `wlr-screencopy-unstable-v1` protocol.
+//!
+//! Only outputs can be captured with this protocol, toplevels may be captured with `ext-image-copy-capture-v1`.
+
+use std::{sync::Mutex, time::Duration};
+
+use smithay::{
+    output::Output,
+    utils::{Logical, Physical, Rectangle},
+    wayland::shm,
+};
+use wayland_server::{Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource};
+
+pub use smithay::reexports::wayland_protocols_wlr::screencopy::v1::server::{
+    zwlr_screencopy_frame_v1, zwlr_screencopy_manager_v1,
+};
+
+use crate::{
+    capture::{self, Capture, CaptureFrame, CaptureSource, SHM_FORMATS},
+    output, Aerugo, ClientData, PrivilegedGlobals,
+};
+
+use self::{zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1, zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1};
+
+#[derive(Debug)]
+pub struct FrameData {
+    /// The output being captured, [`None`] if the output was unavailable when the frame was created.
+    output: Option<Output>,
+
+    /// The area of the output to capture.
+    region: Rectangle<i32, Physical>,
+
+    inner: Mutex<FrameState>,
+}
+
+#[derive(Debug, Default)]
+struct FrameState {
+    /// Whether a copy was requested.
+    used: bool,
+
+    with_damage: bool,
+}
+
+impl GlobalDispatch<ZwlrScreencopyManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrScreencopyManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::SCREEN_CAPTURE))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ZwlrScreencopyManagerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrScreencopyManagerV1,
+        request: zwlr_screencopy_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        // TODO: Paint the cursor if overlay_cursor is set once the cursor is part of the scene.
+        let (frame, output, region) = match request {
+            zwlr_screencopy_manager_v1::Request::CaptureOutput { frame, output, .. } => (frame, output, None),
+
+            zwlr_screencopy_manager_v1::Request::CaptureOutputRegion {
+                frame,
+                output,
+                x,
+                y,
+                width,
+                height,
+                ..
+            } => (
+                frame,
+                output,
+                Some(Rectangle::from_loc_and_size((x, y), (width, height))),
+            ),
+
+            zwlr_screencopy_manager_v1::Request::Destroy => return,
+
+            _ => unreachable!(),
+        };
+
+        let output =
+            Output::from_resource(&output).filter(|output| state.outputs().any(|connected| connected == output));
+        let bounds = output
+            .as_ref()
+            .map(|output| Rectangle::from_loc_and_size((0, 0), output::output_size(output)))
+            .unwrap_or_default();
+
+        let region = match (&output, region) {
+            (Some(output), Some(region)) => output_region(region, output.current_scale().fractional_scale(), bounds),
+            (_, None) => Some(bounds),
+            (None, Some(_)) => None,
+        };
+
+        let frame = init.init(
+            frame,
+            FrameData {
+                output: output.filter(|_| region.is_some()),
+                region: region.unwrap_or_default(),
+                inner: Mutex::new(FrameState::default()),
+            },
+        );
+
+        let data = frame.data::<FrameData>().unwrap();
+
+        if data.output.is_none() {
+            frame.failed();
+            return;
+        }
+
+        let size = data.region.size;
+        frame.buffer(SHM_FORMATS[0], size.w as u32, size.h as u32, size.w as u32 * 4);
+
+        if frame.version() >= zwlr_screencopy_frame_v1::EVT_BUFFER_DONE_SINCE {
+            // TODO: Dmabuf constraints
+            frame.buffer_done();
+        }
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, FrameData> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrScreencopyFrameV1,
+        request: zwlr_screencopy_frame_v1::Request,
+        data: &FrameData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        let (buffer, with_damage) = match request {
+            zwlr_screencopy_frame_v1::Request::Copy { buffer } => (buffer, false),
+            zwlr_screencopy_frame_v1::Request::CopyWithDamage { buffer } => (buffer, true),
+            zwlr_screencopy_frame_v1::Request::Destroy => return,
+            _ => unreachable!(),
+        };
+
+        let mut frame = data.inner.lock().unwrap();
+
+        if frame.used {
+            resource.post_error(
+                zwlr_screencopy_frame_v1::Error::AlreadyUsed,
+                "the frame was already used",
+            );
+            return;
+        }
+
+        frame.used = true;
+        frame.with_damage = with_damage;
+        drop(frame);
+
+        let valid = shm::with_buffer_contents(&buffer, |_, _, buffer| {
+            capture::buffer_satisfies_constraints(&buffer, data.region.size)
+        })
+        .unwrap_or(false);
+
+        if !valid {
+            resource.post_error(zwlr_screencopy_frame_v1::Error::InvalidBuffer, "invalid buffer");
+            return;
+        }
+
+        let Some(output) = data.output.clone() else {
+            resource.failed();
+            return;
+        };
+
+        // A copy with damage waits until the output is damaged.
+        state.capture(
+            Capture {
+                source: CaptureSource::Output(output),
+                frame: CaptureFrame::Wlr(resource.clone()),
+                buffer,
+                region: Some(data.region),
+            },
+            !with_damage,
+        );
+    }
+}
+
+/// The area of an output to capture.
+///
+/// The region is in the logical coordinate space of the output and is clamped to the bounds of the output.
+/// Returns [`None`] if the region does not cover any of the output.
+fn output_region(
+    region: Rectangle<i32, Logical>,
+    scale: f64,
+    bounds: Rectangle<i32, Physical>,
+) -> Option<Rectangle<i32, Physical>> {
+    region
+        .to_f64()
+        .to_physical(scale)
+        .to_i32_round()
+        .intersection(bounds)
+        .filter(|region| region.size.w > 0 && region.size.h > 0)
+}
+
+/// Notify the client that the frame was copied.
+pub(crate) fn frame_ready(frame: &ZwlrScreencopyFrameV1, time: Duration) {
+    let Some(data) = frame.data::<FrameData>() else {
+        return;
+    };
+
+    frame.flags(zwlr_screencopy_frame_v1::Flags::empty());
+
+    if data.inner.lock().unwrap().with_damage {
+        // The whole buffer is copied.
+        let size = data.region.size;
+        frame.damage(0, 0, size.w as u32, size.h as u32);
+    }
+
+    frame.ready(
+        (time.as_secs() >> 32) as u32,
+        time.as_secs() as u32,
+        time.subsec_nanos(),
+    );
+}
+
+/// Notify the client that the frame could not be copied.
+pub(crate) fn frame_failed(frame: &ZwlrScreencopyFrameV1, _err: &capture::CaptureError) {
+    frame.failed();
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::utils::Rectangle;
+
+    use super::output_region;
+
+    #[test]
+    fn region_inside_output() {
+        let bounds = Rectangle::from_loc_and_size((0, 0), (1920, 1080));
+        let region = output_region(Rectangle::from_loc_and_size((10, 20), (100, 50)), 1.0, bounds);
+        assert_eq!(region, Some(Rectangle::from_loc_and_size((10, 20), (100, 50))));
+    }
+
+    #[test]
+    fn region_clamped_to_output() {
+        let bounds = Rectangle::from_loc_and_size((0, 0), (1920, 1080));
+
+        let region = output_region(Rectangle::from_loc_and_size((1900, 1000), (100, 100)), 1.0, bounds);
+        assert_eq!(region, Some(Rectangle::from_loc_and_size((1900, 1000), (20, 80))));
+
+        let region = output_region(Rectangle::from_loc_and_size((-10, -20), (100, 100)), 1.0, bounds);
+        assert_eq!(region, Some(Rectangle::from_loc_and_size((0, 0), (90, 80))));
+    }
+
+    /// The region is logical, so the captured area is scaled to the physical size of the output.
+    #[test]
+    fn region_scaled() {
+        let bounds = Rectangle::from_loc_and_size((0, 0), (3840, 2160));
+        let region = output_region(Rectangle::from_loc_and_size((10, 20), (100, 50)), 2.0, bounds);
+        assert_eq!(region, Some(Rectangle::from_loc_and_size((20, 40), (200, 100))));
+
+        // The logical size of the output at a scale of 2 is 1920x1080.
+        let region = output_region(Rectangle::from_loc_and_size((1820, 1000), (200, 200)), 2.0, bounds);
+        assert_eq!(region, Some(Rectangle::from_loc_and_size((3640, 2000), (200, 160))));
+    }
+
+    #[test]
+    fn region_fractional_scale() {
+        let bounds = Rectangle::from_loc_and_size((0, 0), (2400, 1350));
+        let region = output_region(Rectangle::from_loc_and_size((1, 1), (3, 3)), 1.25, bounds);
+        assert_eq!(region, Some(Rectangle::from_loc_and_size((1, 1), (4, 4))));
+    }
+
+    #[test]
+    fn region_outside_output() {
+        let bounds = Rectangle::from_loc_and_size((0, 0), (1920, 1080));
+
+        assert_eq!(
+            output_region(Rectangle::from_loc_and_size((2000, 0), (100, 100)), 1.0, bounds),
+            None
+        );
+        assert_eq!(
+            output_region(Rectangle::from_loc_and_size((-100, -100), (50, 50)), 1.0, bounds),
+            None
+        );
+        assert_eq!(
+            output_region(Rectangle::from_loc_and_size((10, 10), (0, 0)), 1.0, bounds),
+            None
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Implementation of the `wp-fractional-scale-v1` protocol along with the preferred buffer scale and transform of
+//! `wl_surface`.
+
+use std::cell::Cell;
+
+use smithay::{
+    output,
+    utils::Transform,
+    wayland::{
+        compositor,
+        fractional_scale::{self, FractionalScaleHandler},
+    },
+};
+use wayland_server::{
+    protocol::wl_surface::{self, WlSurface},
+    Resource,
+};
+
+use crate::Aerugo;
+
+impl FractionalScaleHandler for Aerugo {
+    fn new_fractional_scale(&mut self, surface: WlSurface) {
+        let outputs = self.scene.surface_outputs(&surface);
+
+        // If the surface is not on any output yet, suggest the scale of the first output so the first buffer the
+        // client attaches is likely to be the right size.
+        let scale = match preferred_scale(outputs) {
+            Some(scale) => scale,
+            None => match self.outputs().next() {
+                Some(output) => output.current_scale(),
+                None => return,
+            },
+        };
+
+        send_preferred_scale(&surface, scale);
+    }
+}
+
+smithay::delegate_fractional_scale!(Aerugo);
+
+/// The preferred buffer state last sent to a surface.
+#[derive(Debug, Default)]
+struct PreferredBufferState {
+    scale: Cell<Option<i32>>,
+    transform: Cell<Option<Transform>>,
+}
+
+/// The scale a surface on the outputs should be rendered at.
+///
+/// This is the largest scale of the outputs, so the surface is crisp on every output.
+pub(crate) fn preferred_scale(outputs: &[output::Output]) -> Option<output::Scale> {
+    outputs
+        .iter()
+        .map(output::Output::current_scale)
+        .max_by(|a, b| a.fractional_scale().total_cmp(&b.fractional_scale()))
+}
+
+/// Tell the client the scale to render the surface at.
+///
+/// Clients which support `wp-fractional-scale-v1` are sent the fractional scale, while `wl_surface` version 6
+/// receives the scale rounded up to an integer. The scale is only sent if the scale changed.
+pub(crate) fn send_preferred_scale(surface: &WlSurface, scale: output::Scale) {
+    compositor::with_states(surface, |states| {
+        fractional_scale::with_fractional_scale(states, |fractional| {
+            fractional.set_preferred_scale(scale.fractional_scale());
+        });
+
+        if surface.version() < wl_surface::EVT_PREFERRED_BUFFER_SCALE_SINCE {
+            return;
+        }
+
+        states.data_map.insert_if_missing(PreferredBufferState::default);
+        let preferred = states.data_map.get::<PreferredBufferState>().unwrap();
+        let integer_scale = scale.integer_scale();
+
+        if preferred.scale.replace(Some(integer_scale)) != Some(integer_scale) {
+            surface.preferred_buffer_scale(integer_scale);
+        }
+    });
+}
+
+/// Tell the client the transform to apply to the buffers of the surface.
+///
+/// The transform is only sent if the transform changed.
+pub(crate) fn send_preferred_transform(surface: &WlSurface, transform: Transform) {
+    if surface.version() < wl_surface::EVT_PREFERRED_BUFFER_TRANSFORM_SINCE {
+        return;
+    }
+
+    compositor::with_states(surface, |states| {
+        states.data_map.insert_if_missing(PreferredBufferState::default);
+        let preferred = states.data_map.get::<PreferredBufferState>().unwrap();
+
+        if preferred.transform.replace(Some(transform)) != Some(transform) {
+            surface.preferred_buffer_transform(transform.into());
+        }
+    });
+}
@@ -0,0 +1,6 @@
+//! `wp` wayland protocol implementations
+
+pub mod fractional_scale;
+mod presentation;
+mod single_pixel_buffer;
+pub mod viewporter;
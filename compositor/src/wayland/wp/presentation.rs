@@ -0,0 +1,3 @@
+use crate::Aerugo;
+
+smithay::delegate_presentation!(Aerugo);
@@ -0,0 +1,8 @@
+//! Implementation of the `wp-single-pixel-buffer-v1` protocol.
+//!
+//! Surfaces with a single pixel buffer attached are filled with the color of the buffer instead of uploading the
+//! buffer into a texture.
+
+use crate::Aerugo;
+
+smithay::delegate_single_pixel_buffer!(Aerugo);
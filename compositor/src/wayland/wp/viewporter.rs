@@ -0,0 +1,32 @@
+//! Implementation of the `wp-viewporter` protocol.
+//!
+//! The source crop and destination size of a viewport are part of the surface view computed when the buffer is
+//! committed, so scene elements apply the viewport when rendering.
+
+use smithay::{
+    backend::renderer::utils::RendererSurfaceStateUserData,
+    wayland::{compositor, viewporter},
+};
+use wayland_server::protocol::wl_surface::WlSurface;
+
+use crate::Aerugo;
+
+smithay::delegate_viewporter!(Aerugo);
+
+/// Check the source rectangle of the surface's viewport is inside the buffer attached to the surface.
+///
+/// If the source rectangle is outside of the buffer, the client receives an `out_of_buffer` protocol error and
+/// false is returned.
+pub(crate) fn validate_viewport(surface: &WlSurface) -> bool {
+    compositor::with_states(surface, |states| {
+        let buffer_size = states
+            .data_map
+            .get::<RendererSurfaceStateUserData>()
+            .and_then(|data| data.borrow().buffer_size());
+
+        // Without a buffer there is nothing the source rectangle could be outside of.
+        buffer_size
+            .map(|size| viewporter::ensure_viewport_valid(states, size))
+            .unwrap_or(true)
+    })
+}
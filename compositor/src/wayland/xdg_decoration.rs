@@ -0,0 +1,114 @@
+//! Implementation of the `xdg-decoration` protocol.
+//!
+//! Toplevels which negotiate server side decorations have decorations drawn around the surface tree by the
+//! scene.
+//!
+//! The decoration mode of a toplevel is the mode the WM configured. If the WM has not configured a mode, the mode
+//! the client prefers is used, otherwise the client draws it's own decorations.
+
+use std::cell::Cell;
+
+use smithay::{
+    reexports::wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode,
+    wayland::{
+        compositor,
+        shell::xdg::{decoration::XdgDecorationHandler, ToplevelSurface, XdgToplevelSurfaceData},
+    },
+};
+use wayland_server::protocol::wl_surface::WlSurface;
+
+use crate::Aerugo;
+
+/// The decoration modes requested for a toplevel.
+#[derive(Debug, Default)]
+struct DecorationState {
+    /// Whether the client created a decoration object for the toplevel.
+    supported: Cell<bool>,
+
+    /// The mode the client prefers.
+    client: Cell<Option<Mode>>,
+
+    /// The mode the WM configured.
+    wm: Cell<Option<Mode>>,
+}
+
+impl XdgDecorationHandler for Aerugo {
+    fn new_decoration(&mut self, toplevel: ToplevelSurface) {
+        update_mode(&toplevel, |state| state.supported.set(true));
+    }
+
+    fn request_mode(&mut self, toplevel: ToplevelSurface, mode: Mode) {
+        update_mode(&toplevel, |state| state.client.set(Some(mode)));
+    }
+
+    fn unset_mode(&mut self, toplevel: ToplevelSurface) {
+        // The client has no preference, so the WM picks the mode.
+        update_mode(&toplevel, |state| state.client.set(None));
+    }
+}
+
+smithay::delegate_xdg_decoration!(Aerugo);
+
+/// Whether the toplevel supports server side decorations.
+pub(crate) fn supports_server_side(surface: &WlSurface) -> bool {
+    compositor::with_states(surface, |states| {
+        states
+            .data_map
+            .get::<DecorationState>()
+            .map_or(false, |state| state.supported.get())
+    })
+}
+
+/// Set the decoration mode the WM configured for the toplevel.
+///
+/// The mode is sent with the next configure of the toplevel.
+pub(crate) fn set_wm_mode(toplevel: &ToplevelSurface, mode: Option<Mode>) {
+    let Some(mode) = with_state(toplevel, |state| {
+        state.wm.set(mode);
+        state.supported.get().then(|| preferred_mode(state))
+    }) else {
+        return;
+    };
+
+    toplevel.with_pending_state(|state| {
+        state.decoration_mode = Some(mode);
+    });
+}
+
+fn with_state<T>(toplevel: &ToplevelSurface, f: impl FnOnce(&DecorationState) -> T) -> T {
+    compositor::with_states(toplevel.wl_surface(), |states| {
+        states.data_map.insert_if_missing(DecorationState::default);
+        f(states.data_map.get::<DecorationState>().unwrap())
+    })
+}
+
+fn preferred_mode(state: &DecorationState) -> Mode {
+    state.wm.get().or(state.client.get()).unwrap_or(Mode::ClientSide)
+}
+
+/// Update the decoration state of the toplevel and configure the toplevel with the preferred mode.
+fn update_mode(toplevel: &ToplevelSurface, f: impl FnOnce(&DecorationState)) {
+    let mode = with_state(toplevel, |state| {
+        f(state);
+        preferred_mode(state)
+    });
+
+    toplevel.with_pending_state(|state| {
+        state.decoration_mode = Some(mode);
+    });
+
+    // The mode is sent with the initial configure if the toplevel was not configured yet.
+    let initial_configure_sent = compositor::with_states(toplevel.wl_surface(), |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .initial_configure_sent
+    });
+
+    if initial_configure_sent {
+        toplevel.send_configure();
+    }
+}
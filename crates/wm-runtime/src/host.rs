@@ -10,8 +10,8 @@ use crate::{ConfigureUpdate, Id, IdError, IdType, WmRequest, WmState, WmToplevel
 
 use self::aerugo::wm::types::{
     DecorationMode, Features, Focus, Geometry, Host, HostOutput, HostServer, HostSnapshot, HostToplevel,
-    HostToplevelConfigure, HostView, HostViewBuilder, Output, OutputId, ResizeEdge, Server, Size, Snapshot, Toplevel,
-    ToplevelConfigure, ToplevelId, ToplevelState, View, ViewBuilder,
+    HostToplevelConfigure, HostView, HostViewBuilder, Output, OutputId, ResizeEdge, SceneDumpFormat, Server, Size,
+    Snapshot, Toplevel, ToplevelConfigure, ToplevelId, ToplevelState, View, ViewBuilder,
 };
 
 wasmtime::component::bindgen!(in "../../wm.wit");
@@ -29,6 +29,12 @@ impl HostServer for WmState {
         todo!()
     }
 
+    fn dump_scene(&mut self, server: Resource<Server>, format: SceneDumpFormat) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+        let _ = self.sender.send(WmRequest::DumpScene(format));
+        Ok(())
+    }
+
     fn drop(&mut self, server: Resource<Server>) -> wasmtime::Result<()> {
         // TODO: What should happen if the server is dropped?
         self.validate_id_server(&server)?;
@@ -62,23 +68,29 @@ impl HostView for WmState {
 
 impl HostOutput for WmState {
     fn id(&mut self, output: Resource<Output>) -> wasmtime::Result<OutputId> {
-        todo!()
+        let id = self.get_id(&output, IdType::Output)?;
+        Ok(id.rep().get())
     }
 
     fn name(&mut self, output: Resource<Output>) -> wasmtime::Result<Option<String>> {
-        todo!()
+        let output = self.get_output_res(&output)?;
+        Ok(output.name.clone())
     }
 
     fn geometry(&mut self, output: Resource<Output>) -> wasmtime::Result<Geometry> {
-        todo!()
+        let output = self.get_output_res(&output)?;
+        Ok(output.geometry)
     }
 
     fn refresh_rate(&mut self, output: Resource<Output>) -> wasmtime::Result<u32> {
-        todo!()
+        let output = self.get_output_res(&output)?;
+        Ok(output.refresh_rate)
     }
 
     fn drop(&mut self, output: Resource<Output>) -> wasmtime::Result<()> {
-        todo!()
+        // The output remains valid until it is disconnected.
+        self.get_id(&output, IdType::Output)?;
+        Ok(())
     }
 }
 
@@ -158,9 +170,9 @@ impl HostToplevel for WmState {
 
 impl HostToplevelConfigure for WmState {
     fn new(&mut self, toplevel: Resource<Toplevel>) -> wasmtime::Result<Resource<ToplevelConfigure>> {
-        let toplevel = self.get_toplevel_res(&toplevel)?;
+        let toplevel_id = self.get_toplevel_res(&toplevel)?.id;
         let configure = WmToplevelConfigure {
-            toplevel_id: toplevel.id,
+            toplevel_id,
             decorations: Default::default(),
             parent: Default::default(),
             state: Default::default(),
@@ -168,12 +180,18 @@ impl HostToplevelConfigure for WmState {
             bounds: Default::default(),
         };
 
-        Ok(Resource::new_own(todo!("Allocate owned id for toplevel configure")))
+        let id = self.alloc_id(IdType::ToplevelConfigure);
+        self.configures.insert(id.rep(), configure);
+        Ok(Resource::new_own(id.rep().get()))
     }
 
     fn submit(&mut self, configure: Resource<ToplevelConfigure>) -> wasmtime::Result<u32> {
-        let _configure = self.get_toplevel_configure(&configure)?;
-        todo!()
+        let configure = self.get_toplevel_configure(&configure)?.clone();
+        let serial = self.next_serial;
+        self.next_serial = self.next_serial.wrapping_add(1);
+
+        let _ = self.sender.send(WmRequest::ConfigureToplevel { serial, configure });
+        Ok(serial)
     }
 
     fn decorations(
@@ -230,20 +248,31 @@ impl HostToplevelConfigure for WmState {
     }
 
     fn drop(&mut self, configure: Resource<ToplevelConfigure>) -> wasmtime::Result<()> {
-        todo!()
+        let id = self.get_id(&configure, IdType::ToplevelConfigure)?;
+        self.remove_id(id);
+        self.configures.remove(&id.rep());
+        Ok(())
     }
 }
 
 impl HostSnapshot for WmState {
     fn size(&mut self, snapshot: Resource<Snapshot>) -> wasmtime::Result<Size> {
-        todo!()
+        let snapshot = self.get_snapshot_res(&snapshot)?;
+        Ok(snapshot.size)
     }
 
     fn scale(&mut self, snapshot: Resource<Snapshot>) -> wasmtime::Result<f32> {
-        todo!()
+        let snapshot = self.get_snapshot_res(&snapshot)?;
+        Ok(snapshot.scale)
     }
 
     fn drop(&mut self, snapshot: Resource<Snapshot>) -> wasmtime::Result<()> {
-        todo!()
+        let id = self.get_id(&snapshot, IdType::Snapshot)?;
+        self.remove_id(id);
+        self.snapshots.remove(&id.rep());
+
+        // The display server frees the contents of the snapshot.
+        let _ = self.sender.send(WmRequest::SnapshotDrop(id));
+        Ok(())
     }
 }
@@ -8,6 +8,10 @@ use std::{
     collections::HashMap,
     fmt::{self, Display},
     num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
 };
 
 use calloop::{
@@ -15,7 +19,7 @@ use calloop::{
     EventSource, Poll, PostAction, TokenFactory,
 };
 use host::{
-    aerugo::wm::types::{DecorationMode, Features, Geometry, ResizeEdge, Server, Size, ToplevelState},
+    aerugo::wm::types::{ResizeEdge, Server},
     exports::aerugo::wm::wm_types::WmTypes,
 };
 use runner::WmRunner;
@@ -24,6 +28,8 @@ use wasmtime::{
     Config, Engine, Store,
 };
 
+pub use host::aerugo::wm::types::{DecorationMode, Features, Geometry, SceneDumpFormat, Size, ToplevelState};
+
 /// An ID which references an object allocated in the WM.
 ///
 /// ID 0 is always reserved by the WM's server object.
@@ -31,6 +37,10 @@ use wasmtime::{
 pub struct Id(NonZeroU32, IdType);
 
 impl Id {
+    pub fn new(rep: NonZeroU32, ty: IdType) -> Self {
+        Self(rep, ty)
+    }
+
     pub fn rep(self) -> NonZeroU32 {
         self.0
     }
@@ -59,6 +69,9 @@ pub enum IdType {
 
     /// A view is a combination of a surface and a snapshot which can be presented.
     View,
+
+    /// A toplevel configure being built by the WM.
+    ToplevelConfigure,
 }
 
 /// An event sent to the wm runtime.
@@ -81,23 +94,35 @@ pub enum WmEvent {
         update: ToplevelUpdate,
     },
 
+    /// Notify the runtime that a toplevel was committed.
+    ///
+    /// A snapshot of the last contents of the toplevel is provided before the toplevel is closed.
+    CommittedToplevel {
+        toplevel: Id,
+        snapshot: Option<(Id, SnapshotInfo)>,
+    },
+
     /// Notify the runtime that a configure has been acked.
     AckToplevel {
         toplevel: Id,
         serial: u32,
     },
 
+    /// Notify the runtime that a new output was connected.
     NewOutput {
         output: Id,
-        // TODO: Info
+        info: OutputInfo,
     },
 
+    /// Notify the runtime that the state of an output has changed.
+    ///
     /// TODO: Add to wit file
     UpdateOutput {
         output: Id,
-        // TODO: Info
+        info: OutputInfo,
     },
 
+    /// Notify the runtime that an output was disconnected.
     DisconnectOutput(Id),
 }
 
@@ -114,6 +139,17 @@ pub enum WmRequest {
 
     /// The wm runtime requested the toplevel with the specified id be closed.
     ToplevelRequestClose(Id),
+
+    /// The wm dropped the snapshot and the contents of the snapshot can be freed.
+    SnapshotDrop(Id),
+
+    /// The wm runtime requested a description of the scene graph be written to the log.
+    DumpScene(SceneDumpFormat),
+
+    /// The wm submitted a configure for a toplevel.
+    ///
+    /// The serial is the serial the wm was given when submitting the configure.
+    ConfigureToplevel { serial: u32, configure: WmToplevelConfigure },
 }
 
 /// A message from the wm runtime.
@@ -124,6 +160,29 @@ pub enum RuntimeMessage {
     Closed,
 }
 
+/// The state of an output.
+#[derive(Debug, Clone)]
+pub struct OutputInfo {
+    /// A human readable name for the output.
+    pub name: Option<String>,
+
+    /// The location and size of the output in the global space, in logical coordinates.
+    pub geometry: Geometry,
+
+    /// The refresh rate of the output in millihertz.
+    pub refresh_rate: u32,
+}
+
+/// The state of a snapshot.
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotInfo {
+    /// The size of the snapshot in logical coordinates.
+    pub size: Size,
+
+    /// The number of buffer pixels per logical pixel.
+    pub scale: f32,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ToplevelUpdate {
     pub app_id: Option<String>,
@@ -146,6 +205,7 @@ pub struct ToplevelUpdate {
 pub struct WmRuntime {
     channel: Channel<WmRequest>,
     sender: Sender<WmEvent>,
+    next_id: Arc<AtomicU32>,
 }
 
 impl EventSource for WmRuntime {
@@ -213,12 +273,20 @@ impl WmRuntime {
 
         let engine = Engine::new(&config)?;
 
+        // Id 0 is reserved for the server.
+        let next_id = Arc::new(AtomicU32::new(1));
+
         let mut store = Store::new(
             &engine,
             WmState {
                 sender: req_sender,
+                next_id: next_id.clone(),
+                next_serial: 0,
                 ids: Vec::new(),
                 toplevels: HashMap::new(),
+                outputs: HashMap::new(),
+                snapshots: HashMap::new(),
+                configures: HashMap::new(),
             },
         );
 
@@ -255,6 +323,7 @@ impl WmRuntime {
         let runtime = WmRuntime {
             channel: req_channel,
             sender: event_sender,
+            next_id,
         };
 
         // Start the wm thread.
@@ -262,6 +331,38 @@ impl WmRuntime {
 
         Ok(runtime)
     }
+
+    /// Create a handle to send events to the wm.
+    pub fn handle(&self) -> WmHandle {
+        WmHandle {
+            sender: self.sender.clone(),
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+/// A handle used to send events to the wm.
+#[derive(Debug, Clone)]
+pub struct WmHandle {
+    sender: Sender<WmEvent>,
+    next_id: Arc<AtomicU32>,
+}
+
+impl WmHandle {
+    /// Allocate a new id for an object of the specified type.
+    ///
+    /// Ids are not reused.
+    pub fn alloc_id(&self, ty: IdType) -> Id {
+        let rep = self.next_id.fetch_add(1, Ordering::Relaxed);
+        Id(NonZeroU32::new(rep).expect("Ids exhausted"), ty)
+    }
+
+    /// Send an event to the wm.
+    ///
+    /// Events sent after the wm runtime has closed are ignored.
+    pub fn send(&self, event: WmEvent) {
+        let _ = self.sender.send(event);
+    }
 }
 
 #[derive(Debug)]
@@ -289,7 +390,10 @@ impl From<IdError> for Error {
 pub enum IdError {
     ZeroId,
 
-    InvalidId { rep: u32, ty: IdType },
+    InvalidId {
+        rep: u32,
+        ty: IdType,
+    },
 }
 
 impl Display for IdError {
@@ -306,8 +410,14 @@ impl std::error::Error for IdError {}
 #[derive(Debug)]
 struct WmState {
     sender: Sender<WmRequest>,
+    /// Shared with the display server, so objects created by the wm do not reuse ids of the display server.
+    next_id: Arc<AtomicU32>,
+    next_serial: u32,
     ids: Vec<Option<IdType>>,
     toplevels: HashMap<NonZeroU32, WmToplevel>,
+    outputs: HashMap<NonZeroU32, OutputInfo>,
+    snapshots: HashMap<NonZeroU32, SnapshotInfo>,
+    configures: HashMap<NonZeroU32, WmToplevelConfigure>,
 }
 
 impl WmState {
@@ -324,7 +434,7 @@ impl WmState {
             return Err(Error::Id(IdError::InvalidId { rep: rep.get(), ty }));
         }
 
-        Ok(Id(rep, IdType::Toplevel))
+        Ok(Id(rep, ty))
     }
 
     fn validate_id_server(&self, resource: &Resource<Server>) -> Result<(), Error> {
@@ -351,8 +461,59 @@ impl WmState {
         }))
     }
 
-    fn get_toplevel_configure<T: 'static>(&self, _resource: &Resource<T>) -> Result<&mut WmToplevelConfigure, Error> {
-        todo!()
+    fn get_output_res<T: 'static>(&self, resource: &Resource<T>) -> Result<&OutputInfo, Error> {
+        let id = self.get_id(resource, IdType::Output)?;
+
+        self.outputs.get(&id.rep()).ok_or(Error::Id(IdError::InvalidId {
+            rep: id.rep().get(),
+            ty: IdType::Output,
+        }))
+    }
+
+    fn get_snapshot_res<T: 'static>(&self, resource: &Resource<T>) -> Result<&SnapshotInfo, Error> {
+        let id = self.get_id(resource, IdType::Snapshot)?;
+
+        self.snapshots.get(&id.rep()).ok_or(Error::Id(IdError::InvalidId {
+            rep: id.rep().get(),
+            ty: IdType::Snapshot,
+        }))
+    }
+
+    /// Record the type of a newly created id.
+    fn insert_id(&mut self, id: Id) {
+        let index = id.rep().get() as usize;
+
+        if self.ids.len() <= index {
+            self.ids.resize(index + 1, None);
+        }
+
+        self.ids[index] = Some(id.ty());
+    }
+
+    fn remove_id(&mut self, id: Id) {
+        if let Some(ty) = self.ids.get_mut(id.rep().get() as usize) {
+            *ty = None;
+        }
+    }
+
+    /// Allocate an id for an object created by the wm.
+    fn alloc_id(&mut self, ty: IdType) -> Id {
+        let rep = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let id = Id(NonZeroU32::new(rep).expect("Ids exhausted"), ty);
+        self.insert_id(id);
+        id
+    }
+
+    fn get_toplevel_configure<T: 'static>(
+        &mut self,
+        resource: &Resource<T>,
+    ) -> Result<&mut WmToplevelConfigure, Error> {
+        let id = self.get_id(resource, IdType::ToplevelConfigure)?;
+
+        self.configures.get_mut(&id.rep()).ok_or(Error::Id(IdError::InvalidId {
+            rep: id.rep().get(),
+            ty: IdType::ToplevelConfigure,
+        }))
     }
 }
 
@@ -386,19 +547,32 @@ impl<T> ConfigureUpdate<T> {
     }
 }
 
-#[derive(Debug)]
-struct WmToplevelConfigure {
-    toplevel_id: Id,
-    decorations: Option<DecorationMode>,
-    parent: ConfigureUpdate<Id>,
-    state: Option<ToplevelState>,
-    size: ConfigureUpdate<Size>,
-    bounds: ConfigureUpdate<Size>,
+/// A configure of a toplevel built by the wm.
+///
+/// Only the state which the wm set is changed.
+#[derive(Debug, Clone)]
+pub struct WmToplevelConfigure {
+    pub toplevel_id: Id,
+    pub decorations: Option<DecorationMode>,
+    pub parent: ConfigureUpdate<Id>,
+    pub state: Option<ToplevelState>,
+    pub size: ConfigureUpdate<Size>,
+    pub bounds: ConfigureUpdate<Size>,
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Id, WmEvent, WmRequest};
+    use std::{
+        collections::HashMap,
+        num::NonZeroU32,
+        sync::{atomic::AtomicU32, Arc},
+    };
+
+    use wasmtime::component::Resource;
+
+    use crate::{
+        host::aerugo::wm::types::HostSnapshot, Id, IdType, Size, SnapshotInfo, WmEvent, WmHandle, WmRequest, WmState,
+    };
 
     fn assert_send<T: Send>() {}
 
@@ -416,4 +590,41 @@ mod tests {
     fn is_request_send() {
         assert_send::<WmRequest>();
     }
+
+    #[test]
+    fn is_handle_send() {
+        assert_send::<WmHandle>();
+    }
+
+    /// Dropping a snapshot in the wm tells the display server to free the contents of the snapshot.
+    #[test]
+    fn drop_snapshot() {
+        let (sender, channel) = calloop::channel::channel();
+        let mut state = WmState {
+            sender,
+            next_id: Arc::new(AtomicU32::new(2)),
+            next_serial: 0,
+            ids: Vec::new(),
+            toplevels: HashMap::new(),
+            outputs: HashMap::new(),
+            snapshots: HashMap::new(),
+            configures: HashMap::new(),
+        };
+
+        let id = Id::new(NonZeroU32::new(1).unwrap(), IdType::Snapshot);
+        state.insert_id(id);
+        state.snapshots.insert(
+            id.rep(),
+            SnapshotInfo {
+                size: Size { width: 1, height: 1 },
+                scale: 1.0,
+            },
+        );
+
+        HostSnapshot::drop(&mut state, Resource::new_own(id.rep().get())).unwrap();
+
+        assert!(state.snapshots.is_empty());
+        assert!(state.get_snapshot_res(&Resource::<()>::new_own(id.rep().get())).is_err());
+        assert!(matches!(channel.recv(), Ok(WmRequest::SnapshotDrop(dropped)) if dropped == id));
+    }
 }
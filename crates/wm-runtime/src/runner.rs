@@ -11,7 +11,7 @@ use crate::{
         aerugo::wm::types::{DecorationMode, Features, ToplevelUpdates},
         exports::aerugo::wm::wm_types::WmTypes,
     },
-    ConfigureUpdate, Id, ToplevelUpdate, WmEvent, WmState, WmToplevel,
+    ConfigureUpdate, Id, OutputInfo, SnapshotInfo, ToplevelUpdate, WmEvent, WmState, WmToplevel,
 };
 
 pub struct WmRunner {
@@ -54,10 +54,13 @@ impl WmRunner {
                             WmEvent::NewToplevel { toplevel, features } => self.new_toplevel(toplevel, features),
                             WmEvent::ClosedToplevel(id) => self.closed_toplevel(id),
                             WmEvent::UpdateToplevel { toplevel, update } => self.update_toplevel(toplevel, update),
+                            WmEvent::CommittedToplevel { toplevel, snapshot } => {
+                                self.committed_toplevel(toplevel, snapshot)
+                            }
                             WmEvent::AckToplevel { toplevel, serial } => todo!(),
-                            WmEvent::NewOutput { output } => todo!(),
-                            WmEvent::UpdateOutput { output } => todo!(),
-                            WmEvent::DisconnectOutput(_) => todo!(),
+                            WmEvent::NewOutput { output, info } => self.new_output(output, info),
+                            WmEvent::UpdateOutput { output, info } => self.update_output(output, info),
+                            WmEvent::DisconnectOutput(id) => self.disconnect_output(id),
                         };
 
                         result.expect("handle error");
@@ -74,11 +77,15 @@ impl WmRunner {
 
     // TODO: Somehow communicate all the initial state
     fn new_toplevel(&mut self, id: Id, features: Features) -> wasmtime::Result<()> {
-        self.store.data_mut().toplevels.insert(
+        let wm = self.store.data_mut();
+        wm.insert_id(id);
+
+        // The wm is told about the toplevel once the initial state is sent.
+        wm.toplevels.insert(
             id.rep(),
             WmToplevel {
                 id,
-                initial_commit: false,
+                initial_commit: true,
                 features,
                 app_id: Default::default(),
                 title: Default::default(),
@@ -101,6 +108,49 @@ impl WmRunner {
             .call_closed_toplevel(&mut self.store, self.wm, id.rep().get())
     }
 
+    fn committed_toplevel(&mut self, id: Id, snapshot: Option<(Id, SnapshotInfo)>) -> wasmtime::Result<()> {
+        let wm = self.store.data_mut();
+        wm.get_toplevel(id)?;
+
+        let snapshot = snapshot.map(|(snapshot, info)| {
+            wm.insert_id(snapshot);
+            wm.snapshots.insert(snapshot.rep(), info);
+            Resource::new_own(snapshot.rep().get())
+        });
+
+        self.funcs
+            .wm()
+            .call_committed_toplevel(&mut self.store, self.wm, id.rep().get(), snapshot)
+    }
+
+    fn new_output(&mut self, id: Id, info: OutputInfo) -> wasmtime::Result<()> {
+        let wm = self.store.data_mut();
+        wm.insert_id(id);
+        wm.outputs.insert(id.rep(), info);
+
+        let output = Resource::new_own(id.rep().get());
+        self.funcs.wm().call_new_output(&mut self.store, self.wm, output)
+    }
+
+    fn update_output(&mut self, id: Id, info: OutputInfo) -> wasmtime::Result<()> {
+        // TODO: Notify the wm once output updates are part of the wit file.
+        if let Some(output) = self.store.data_mut().outputs.get_mut(&id.rep()) {
+            *output = info;
+        }
+
+        Ok(())
+    }
+
+    fn disconnect_output(&mut self, id: Id) -> wasmtime::Result<()> {
+        let wm = self.store.data_mut();
+        wm.remove_id(id);
+        wm.outputs.remove(&id.rep());
+
+        self.funcs
+            .wm()
+            .call_disconnect_output(&mut self.store, self.wm, id.rep().get())
+    }
+
     fn update_toplevel(&mut self, id: Id, update: ToplevelUpdate) -> wasmtime::Result<()> {
         let mut updates = ToplevelUpdates::default();
         let wm = self.store.data_mut();
@@ -111,10 +161,12 @@ impl WmRunner {
 
         if (toplevel.app_id != update.app_id) && update.app_id.is_some() {
             updates |= ToplevelUpdates::APP_ID;
+            toplevel.app_id = update.app_id;
         }
 
         if (toplevel.title != update.title) && update.title.is_some() {
             updates |= ToplevelUpdates::TITLE;
+            toplevel.title = update.title;
         }
 
         if let ConfigureUpdate::Update(min_size) = update.min_size {